@@ -1,70 +1,474 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+use crate::error::CowError;
 use crate::lang;
 use lang::function::Function;
+use lang::global::{Global, Globals};
+use lang::parser::parse_module;
+use lang::printer::{write_extern, write_global};
 use lang::lang_type::Type;
-use crate::gen::x86_64::gen::X86_64Gen;
-use std::os::raw::{c_void, c_ulong};
+use lang::signature::{Signature, Signatures};
+use lang::verifier::verify_function;
+use crate::gen::x86_64::gen::{Relocation, RelocationTarget, X86_64Gen};
+use crate::jit::code_arena::{ArenaConfig, CodeArena, CodeRegion, PageUsage};
+use crate::jit::typed_func::{JitSignature, TypedFunc};
 
-const PAGE_EXECUTE_READWRITE: u32 = 0x40;
-const MEM_COMMIT: u32 = 0x1000;
-const MEM_RELEASE: u32 = 0x8000;
+pub struct Compiler {
+    funcs: HashMap<String, Function>,
+    signatures: Signatures,
+    // address of the host functions callable by name
+    externs: HashMap<String, usize>,
+    globals: Globals,
+    code_arena: Rc<RefCell<CodeArena>>,
+}
 
-use std::mem::transmute;
+impl Compiler {
+    pub fn new() -> Result<Self, CowError> {
+        Self::with_arena_config(ArenaConfig::default())
+    }
 
-extern "system" {
-    fn VirtualAlloc(lpAddress: *mut c_void, dwSize: usize, flAllocationType: u32, flProtect: u32) -> *mut c_void;
-    fn VirtualFree(lpAddress: *mut c_void, dwSize: usize, dwFreeType: u32) -> i32;
-}
+    // fails when the configuration is invalid or the address space of the arena cannot be reserved
+    pub fn with_arena_config(config: ArenaConfig) -> Result<Self, CowError> {
+        let code_arena = CodeArena::new(&config)?;
+        Ok(Compiler {
+            funcs: HashMap::new(),
+            signatures: Rc::new(RefCell::new(HashMap::new())),
+            externs: HashMap::new(),
+            globals: Rc::new(RefCell::new(HashMap::new())),
+            code_arena: Rc::new(RefCell::new(code_arena)),
+        })
+    }
 
-fn create_executable_function(function_bytes: &Vec<u8>) -> *mut u8 {
-    let length = function_bytes.len();
+    pub fn add_func(&mut self, name: &str, args: &[Type], return_type: Type) -> Result<&mut Function, CowError> {
+        if self.signatures.borrow().contains_key(name) {
+            return Err(CowError::DuplicateFunction(name.to_string()));
+        }
 
-    let addr = unsafe {
-        VirtualAlloc(
-            std::ptr::null_mut(),
-            length,
-            MEM_COMMIT,
-            PAGE_EXECUTE_READWRITE,
-        )
-    };
+        self.signatures.borrow_mut().insert(name.to_string(), Signature::new(args, return_type.clone()));
+        let new_func = Function::new(name, args, return_type, self.signatures.clone(), self.globals.clone());
+        Ok(self.funcs.entry(name.to_string()).or_insert(new_func))
+    }
+
+    // make a host function callable by name from the functions of this compiler,
+    // the pointer must be an extern "C" function matching the signature
+    pub fn register_extern(&mut self, name: &str, fn_ptr: *const u8, args: &[Type], return_type: Type) -> Result<(), CowError> {
+        if self.signatures.borrow().contains_key(name) {
+            return Err(CowError::DuplicateFunction(name.to_string()));
+        }
 
-    let addr_ptr = addr as *mut u8;
+        self.signatures.borrow_mut().insert(name.to_string(), Signature::new(args, return_type));
+        self.externs.insert(name.to_string(), fn_ptr as usize);
+        Ok(())
+    }
 
-    for (i, byte) in function_bytes.iter().enumerate() {
-        unsafe { *addr_ptr.add(i) = *byte };
+    // define a global in the data region next to the code, the rest of its size after the initial bytes is zeroed.
+    // a global that is not mutable is read only once defined
+    pub fn add_global(&mut self, name: &str, size: usize, align: usize, init_bytes: &[u8], mutable: bool) -> Result<(), CowError> {
+        if self.globals.borrow().contains_key(name) {
+            return Err(CowError::DuplicateGlobal(name.to_string()));
+        }
+        if init_bytes.len() > size {
+            return Err(CowError::InvalidGlobal(format!("{} initial bytes do not fit in the {} bytes of {}", init_bytes.len(), size, name)));
+        }
+        if !align.is_power_of_two() || align > self.code_arena.borrow().page_size() {
+            return Err(CowError::InvalidGlobal(format!("{} cannot be aligned on {} bytes", name, align)));
+        }
+
+        let ptr = self.code_arena.borrow_mut().allocate_data(init_bytes, size, align, mutable).ok_or(CowError::CodeArenaFull)?;
+
+        self.globals.borrow_mut().insert(name.to_string(), Global::new(ptr as usize, size, align, mutable));
+        Ok(())
+    }
+
+    // add the globals and the functions written in the textual ir, the host functions it declares have to be registered first.
+    // nothing is added when the text is rejected, the memory taken by its globals is given back to the arena
+    pub fn parse_ir(&mut self, text: &str) -> Result<(), CowError> {
+        let func_names: HashSet<String> = self.funcs.keys().cloned().collect();
+        let global_names: HashSet<String> = self.globals.borrow().keys().cloned().collect();
+        let data_mark = self.code_arena.borrow().data_mark();
+
+        let result = parse_module(self, text);
+        if result.is_err() {
+            self.funcs.retain(|name, _| func_names.contains(name));
+            self.signatures.borrow_mut().retain(|name, _| func_names.contains(name) || self.externs.contains_key(name));
+            self.globals.borrow_mut().retain(|name, _| global_names.contains(name));
+            // the globals of the text were the last data placed, nothing points to them anymore
+            self.code_arena.borrow_mut().rewind_data(data_mark);
+        }
+        result
+    }
+
+    pub(crate) fn host_signature(&self, name: &str) -> Option<Signature> {
+        if !self.externs.contains_key(name) {
+            return None;
+        }
+        self.signatures.borrow().get(name).cloned()
+    }
+
+    pub fn get_global(&self, name: &str) -> Result<Global, CowError> {
+        self.globals.borrow().get(name).cloned().ok_or_else(|| CowError::UnknownGlobal(name.to_string()))
+    }
+
+    // the current content of a global, copied since the jitted functions can write to it
+    pub fn global_bytes(&self, name: &str) -> Result<Vec<u8>, CowError> {
+        let global = self.get_global(name)?;
+        // the global was defined in the arena of this compiler, which is alive as long as self
+        Ok(unsafe { global.read_bytes() })
+    }
+
+    pub fn get_func_by_name(&self, name: &str) -> Result<&Function, CowError> {
+        self.funcs.get(name).ok_or_else(|| CowError::UnknownFunction(name.to_string()))
+    }
+
+    // needed to build a function declared before the functions it calls
+    pub fn get_func_mut_by_name(&mut self, name: &str) -> Result<&mut Function, CowError> {
+        self.funcs.get_mut(name).ok_or_else(|| CowError::UnknownFunction(name.to_string()))
+    }
+
+    // get a callable handle on a jitted function with the signature of F
+    pub fn get_typed<F: JitSignature>(&self, name: &str) -> Result<TypedFunc<'_, F>, CowError> {
+        let func = self.get_func_by_name(name)?;
+        if func.jit_ptr().is_null() {
+            return Err(CowError::NotJitted(name.to_string()));
+        }
+        if !TypedFunc::<F>::matches(func.args(), func.return_type()) {
+            return Err(CowError::SignatureMismatch(name.to_string()));
+        }
+
+        Ok(TypedFunc::new(func.jit_ptr()))
+    }
+
+    // remove a function and give its code back to the code arena,
+    // a function still called by another function is kept since the caller would jump in freed memory
+    pub fn remove_func(&mut self, name: &str) -> Result<(), CowError> {
+        if !self.funcs.contains_key(name) {
+            return Err(CowError::UnknownFunction(name.to_string()));
+        }
+        if self.funcs.values().any(|func| func.name() != name && func.builder_ref().calls_func(name)) {
+            return Err(CowError::FunctionInUse(name.to_string()));
+        }
+
+        self.funcs.remove(name);
+        self.signatures.borrow_mut().remove(name);
+        Ok(())
+    }
+
+    // verify and generate every function. when anything fails the functions keep running their previous code:
+    // the new code gives its space back to the arena and the pages it made writable are made executable again
+    pub fn jit(&mut self) -> Result<(), CowError> {
+        for func in self.funcs.values() {
+            verify_function(func)?;
+        }
+
+        let mut gen = X86_64Gen::new(self.externs.clone());
+        let relocations = gen.gen(&mut self.funcs);
+
+        match self.place_code(&relocations) {
+            Ok(regions) => {
+                for (name, region) in regions {
+                    self.funcs.get_mut(&name).unwrap().set_code_region(region);
+                }
+                Ok(())
+            }
+            Err(error) => {
+                // the new regions are already dropped, the pages shared with the previous code were left writable
+                self.code_arena.borrow_mut().finalize();
+                Err(error)
+            }
+        }
+    }
+
+    // copy the code of every function in the arena and patch the calls between them and the global addresses in it,
+    // the previous code of the functions is left untouched until the new regions are handed to them
+    fn place_code(&self, relocations: &HashMap<String, Vec<Relocation>>) -> Result<HashMap<String, CodeRegion>, CowError> {
+        let mut regions = HashMap::new();
+        for func in self.funcs.values() {
+            let region = CodeArena::allocate(&self.code_arena, func.code()).ok_or(CowError::CodeArenaFull)?;
+            regions.insert(func.name().clone(), region);
+        }
+
+        let mut patches = vec![];
+        for (func_name, func_relocations) in relocations {
+            let func_ptr = regions[func_name].ptr();
+
+            for relocation in func_relocations {
+                let target = match relocation.target() {
+                    RelocationTarget::Func(name) => regions.get(name).ok_or_else(|| CowError::UnknownFunction(name.clone()))?.ptr() as usize,
+                    RelocationTarget::Global(name) => self.get_global(name)?.address(),
+                };
+
+                // the displacement is relative to the end of the instruction, the rel32 is always its last bytes
+                let instr_end = func_ptr as i64 + relocation.offset() as i64 + 4;
+                let displacement = (target as i64 - instr_end) as i32;
+                patches.push((unsafe { func_ptr.add(relocation.offset()) }, displacement.to_le_bytes().to_vec()));
+            }
+        }
+
+        // the patching leaves every page of code executable, the new code included
+        if !self.code_arena.borrow_mut().patch(&patches) {
+            return Err(CowError::MemoryProtection);
+        }
+        Ok(regions)
     }
 
-    addr_ptr
+    // how much of every page of the code arena is filled with code
+    pub fn code_page_usage(&self) -> Vec<PageUsage> {
+        self.code_arena.borrow().page_usage()
+    }
+
+    // the textual ir of the globals, the host functions and the functions, each sorted by name
+    pub fn print_ir(&self) -> String {
+        self.to_string()
+    }
 }
 
-pub struct Compiler {
-    funcs: HashMap<String, Function>,
+impl fmt::Display for Compiler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let globals = self.globals.borrow();
+        let mut global_names: Vec<&String> = globals.keys().collect();
+        global_names.sort();
+        for name in &global_names {
+            write_global(f, name, &globals[*name])?;
+        }
+
+        let signatures = self.signatures.borrow();
+        let mut extern_names: Vec<&String> = self.externs.keys().collect();
+        extern_names.sort();
+        for name in &extern_names {
+            write_extern(f, name, &signatures[*name])?;
+        }
+
+        let mut func_names: Vec<&String> = self.funcs.keys().collect();
+        func_names.sort();
+        for (index, name) in func_names.iter().enumerate() {
+            if index > 0 || !global_names.is_empty() || !extern_names.is_empty() {
+                writeln!(f)?;
+            }
+            write!(f, "{}", self.funcs[*name])?;
+        }
+        Ok(())
+    }
 }
 
-impl Compiler {
-    pub fn new() -> Self {
-        Compiler { funcs: HashMap::new() }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_invalid_arena_config_is_an_error() {
+        let config = ArenaConfig { alignment: 12, reserve_size: 1024 * 1024 };
+        assert!(matches!(Compiler::with_arena_config(config), Err(CowError::InvalidArenaConfig(_))));
+        let config = ArenaConfig { alignment: 16, reserve_size: 1 << 32 };
+        assert!(matches!(Compiler::with_arena_config(config), Err(CowError::InvalidArenaConfig(_))));
     }
 
-    pub fn add_func(&mut self, name: &str, args: &Vec<Type>, return_type: Type) -> Option<&mut Function> {
-        let new_func = Function::new(name, args, return_type);
-        if Option::is_some(&self.funcs.insert(name.to_string(), new_func)) {
-            None
-        } else {
-            self.funcs.get_mut(name)
-        }
+    #[test]
+    fn jitted_code_updates_a_mutable_global() {
+        let mut compiler = Compiler::new().unwrap();
+        compiler.add_global("counter", 8, 8, &[5], true).unwrap();
+        let builder = compiler.add_func("bump", &[], Type::i64()).unwrap().builder();
+        let address = builder.global_addr("counter").unwrap();
+        let value = builder.load(Type::i64(), address.clone(), 0).unwrap();
+        let one = builder.const_i64(1);
+        let bumped = builder.add(value, one);
+        builder.store(address, bumped.clone(), 0).unwrap();
+        builder.ret(bumped);
+
+        compiler.jit().unwrap();
+        let bump = compiler.get_typed::<fn() -> i64>("bump").unwrap();
+        assert_eq!(bump.call(), 6);
+        assert_eq!(bump.call(), 7);
+        assert_eq!(compiler.global_bytes("counter").unwrap(), vec![7, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn reads_a_read_only_global() {
+        let mut compiler = Compiler::new().unwrap();
+        compiler.add_global("table", 32, 16, &[1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0], false).unwrap();
+        let builder = compiler.add_func("second", &[], Type::i64()).unwrap().builder();
+        let address = builder.global_addr("table").unwrap();
+        let value = builder.load(Type::i64(), address, 8).unwrap();
+        builder.ret(value);
+
+        compiler.jit().unwrap();
+        assert_eq!(compiler.get_typed::<fn() -> i64>("second").unwrap().call(), 2);
+        assert_eq!(compiler.get_global("table").unwrap().address() % 16, 0);
+        assert_eq!(compiler.global_bytes("table").unwrap().len(), 32);
+    }
+
+    #[test]
+    fn rejects_invalid_globals() {
+        let mut compiler = Compiler::new().unwrap();
+        compiler.add_global("x", 4, 4, &[], true).unwrap();
+        assert_eq!(compiler.add_global("x", 4, 4, &[], true), Err(CowError::DuplicateGlobal("x".to_string())));
+        assert!(matches!(compiler.add_global("y", 2, 2, &[1, 2, 3], true), Err(CowError::InvalidGlobal(_))));
+        assert!(matches!(compiler.add_global("z", 8, 3, &[], true), Err(CowError::InvalidGlobal(_))));
+        assert_eq!(compiler.global_bytes("w"), Err(CowError::UnknownGlobal("w".to_string())));
+    }
+
+    #[test]
+    fn a_global_too_big_for_the_arena_is_an_error() {
+        let mut compiler = Compiler::new().unwrap();
+        assert_eq!(compiler.add_global("huge", usize::MAX / 2, 8, &[], true), Err(CowError::CodeArenaFull));
+        assert_eq!(compiler.add_global("huge", usize::MAX, 1, &[1], false), Err(CowError::CodeArenaFull));
+        assert_eq!(compiler.get_global("huge").err(), Some(CowError::UnknownGlobal("huge".to_string())));
+
+        // the arena is still usable after the failed globals
+        compiler.add_global("small", 8, 8, &[7], true).unwrap();
+        assert_eq!(compiler.global_bytes("small").unwrap(), vec![7, 0, 0, 0, 0, 0, 0, 0]);
     }
 
-    pub fn get_func_by_name(&self, name: &str) -> Option<&Function> {
-        self.funcs.get(name)
+    #[test]
+    fn globals_keep_their_address_and_content_when_jitting_again() {
+        let mut compiler = Compiler::new().unwrap();
+        compiler.add_global("counter", 8, 8, &[], true).unwrap();
+        let builder = compiler.add_func("bump", &[], Type::void()).unwrap().builder();
+        let address = builder.global_addr("counter").unwrap();
+        let value = builder.load(Type::i64(), address.clone(), 0).unwrap();
+        let one = builder.const_i64(1);
+        let bumped = builder.add(value, one);
+        builder.store(address, bumped, 0).unwrap();
+        builder.ret_void();
+        assert_eq!(builder.global_addr("missing").err(), Some(CowError::UnknownGlobal("missing".to_string())));
+
+        compiler.jit().unwrap();
+        compiler.get_typed::<fn()>("bump").unwrap().call();
+        let address = compiler.get_global("counter").unwrap().address();
+
+        // a function added later reads what the first one wrote
+        let builder = compiler.add_func("read", &[], Type::i64()).unwrap().builder();
+        let counter = builder.global_addr("counter").unwrap();
+        let value = builder.load(Type::i64(), counter, 0).unwrap();
+        builder.ret(value);
+        compiler.jit().unwrap();
+
+        compiler.get_typed::<fn()>("bump").unwrap().call();
+        assert_eq!(compiler.get_typed::<fn() -> i64>("read").unwrap().call(), 2);
+        assert_eq!(compiler.get_global("counter").unwrap().address(), address);
     }
 
-    pub fn jit(&mut self) {
-        let mut gen = X86_64Gen::new();
-        gen.gen(&mut self.funcs);
+    fn add_constant_func(compiler: &mut Compiler, name: &str, additions: usize) {
+        let builder = compiler.add_func(name, &[], Type::i64()).unwrap().builder();
+        let mut sum = builder.const_i64(0);
+        for _ in 0..additions {
+            let one = builder.const_i64(1);
+            sum = builder.add(sum, one);
+        }
+        builder.ret(sum);
+    }
 
-        for func in self.funcs.values_mut() {
-            func.set_jit_ptr(create_executable_function(func.code()))
+    #[test]
+    fn a_full_arena_keeps_the_previous_code_callable() {
+        // the functions are placed in the order of a hash map, the failure has to happen after a write as well as before
+        for _ in 0..16 {
+            jit_past_the_end_of_the_arena();
         }
     }
-}
\ No newline at end of file
+
+    fn jit_past_the_end_of_the_arena() {
+        let page_size = crate::jit::os_memory::page_size();
+        let mut compiler = Compiler::with_arena_config(ArenaConfig { alignment: 16, reserve_size: 2 * page_size }).unwrap();
+        add_constant_func(&mut compiler, "small", 3);
+        compiler.jit().unwrap();
+        let usage = compiler.code_page_usage();
+
+        add_constant_func(&mut compiler, "large", page_size / 4);
+        assert_eq!(compiler.jit(), Err(CowError::CodeArenaFull));
+
+        // the code of small is still executable and the space taken by the failed jit is free again
+        assert_eq!(compiler.get_typed::<fn() -> i64>("small").unwrap().call(), 3);
+        assert_eq!(compiler.code_page_usage().iter().map(|page| page.used_bytes).sum::<usize>(),
+                   usage.iter().map(|page| page.used_bytes).sum::<usize>());
+
+        compiler.remove_func("large").unwrap();
+        compiler.jit().unwrap();
+        assert_eq!(compiler.get_typed::<fn() -> i64>("small").unwrap().call(), 3);
+    }
+
+    fn used_code_bytes(compiler: &Compiler) -> usize {
+        compiler.code_page_usage().iter().map(|page| page.used_bytes).sum()
+    }
+
+    #[test]
+    fn jitting_again_gives_the_previous_code_back() {
+        let mut compiler = Compiler::new().unwrap();
+        add_constant_func(&mut compiler, "f", 10);
+        compiler.jit().unwrap();
+        let used = used_code_bytes(&compiler);
+        assert_eq!(used, compiler.get_func_by_name("f").unwrap().code().len());
+
+        compiler.jit().unwrap();
+        compiler.jit().unwrap();
+        assert_eq!(used_code_bytes(&compiler), used);
+        assert_eq!(compiler.get_typed::<fn() -> i64>("f").unwrap().call(), 10);
+    }
+
+    #[test]
+    fn removing_a_function_frees_its_code() {
+        let mut compiler = Compiler::new().unwrap();
+        add_constant_func(&mut compiler, "callee", 2);
+        let builder = compiler.add_func("caller", &[], Type::i64()).unwrap().builder();
+        let value = builder.call("callee", &[]).unwrap();
+        builder.ret(value);
+        compiler.jit().unwrap();
+
+        assert_eq!(compiler.remove_func("callee"), Err(CowError::FunctionInUse("callee".to_string())));
+        assert_eq!(compiler.remove_func("unknown"), Err(CowError::UnknownFunction("unknown".to_string())));
+
+        compiler.remove_func("caller").unwrap();
+        compiler.remove_func("callee").unwrap();
+        assert_eq!(used_code_bytes(&compiler), 0);
+        assert!(compiler.get_typed::<fn() -> i64>("callee").is_err());
+
+        // the name and the space can be used again
+        add_constant_func(&mut compiler, "callee", 3);
+        compiler.jit().unwrap();
+        assert_eq!(compiler.get_typed::<fn() -> i64>("callee").unwrap().call(), 3);
+    }
+
+    extern "C" fn host_scale(value: f64, factor: i32) -> f64 {
+        value * factor as f64
+    }
+
+    extern "C" fn host_store(ptr: *mut i64, value: i64) {
+        unsafe { *ptr = value };
+    }
+
+    #[test]
+    fn host_functions_are_called_by_name() {
+        let mut compiler = Compiler::new().unwrap();
+        compiler.register_extern("scale", host_scale as *const u8, &[Type::f64(), Type::i32()], Type::f64()).unwrap();
+        compiler.register_extern("store", host_store as *const u8, &[Type::ptr(), Type::i64()], Type::void()).unwrap();
+
+        let builder = compiler.add_func("f", &[Type::ptr(), Type::f64()], Type::f64()).unwrap().builder();
+        let (ptr, value) = (builder.param(0).unwrap(), builder.param(1).unwrap());
+        let three = builder.const_i32(3);
+        let scaled = builder.call("scale", &[value, three]).unwrap();
+        let stored = builder.const_i64(99);
+        builder.call("store", &[ptr, stored]).unwrap();
+        builder.ret(scaled);
+
+        compiler.jit().unwrap();
+        let mut target = 0i64;
+        assert_eq!(compiler.get_typed::<fn(*mut i64, f64) -> f64>("f").unwrap().call(&mut target, 1.5), 4.5);
+        assert_eq!(target, 99);
+    }
+
+    #[test]
+    fn host_functions_share_the_names_of_the_functions() {
+        let mut compiler = Compiler::new().unwrap();
+        compiler.register_extern("scale", host_scale as *const u8, &[Type::f64(), Type::i32()], Type::f64()).unwrap();
+        add_constant_func(&mut compiler, "f", 1);
+
+        assert_eq!(compiler.register_extern("scale", host_scale as *const u8, &[], Type::void()), Err(CowError::DuplicateFunction("scale".to_string())));
+        assert_eq!(compiler.register_extern("f", host_scale as *const u8, &[], Type::void()), Err(CowError::DuplicateFunction("f".to_string())));
+        assert!(matches!(compiler.add_func("scale", &[], Type::void()), Err(CowError::DuplicateFunction(_))));
+
+        let builder = compiler.add_func("g", &[], Type::f64()).unwrap().builder();
+        let wrong = builder.const_i64(1);
+        assert!(matches!(builder.call("scale", &[wrong.clone(), wrong]), Err(CowError::InvalidInstr(_))));
+        assert_eq!(builder.call("missing", &[]).err(), Some(CowError::UnknownFunction("missing".to_string())));
+    }
+}
@@ -0,0 +1,109 @@
+use std::fmt;
+use crate::lang::parser::ParseError;
+use crate::lang::verifier::VerifierError;
+
+// everything that can go wrong while building and jitting functions
+#[derive(Clone, PartialEq, Debug)]
+pub enum CowError {
+    // a function or a host function already has the name
+    DuplicateFunction(String),
+    UnknownFunction(String),
+    DuplicateGlobal(String),
+    UnknownGlobal(String),
+    // the size or the alignment of a global cannot be used, with the reason
+    InvalidGlobal(String),
+    // the function is still called by another function
+    FunctionInUse(String),
+    NotJitted(String),
+    // the function exists but its signature is not the one asked for
+    SignatureMismatch(String),
+    // an instruction the builder refused, with the reason
+    InvalidInstr(String),
+    // the id of a variable read before any definition reaches it
+    UndefinedVariable(usize),
+    // a handle that was not created by the builder it is given to
+    UnknownBlock(usize),
+    UnknownVariable(usize),
+    // the index of a parameter the function or the block does not have
+    UnknownParam(usize),
+    // a type that cannot be used there, with the reason
+    InvalidType(String),
+    Verifier(VerifierError),
+    Parse(ParseError),
+    CodeArenaFull,
+    // the configuration of the code arena cannot be used, with the reason
+    InvalidArenaConfig(String),
+    // the address space of the code arena could not be reserved
+    ArenaReservation,
+    // the protection of the code memory could not be changed
+    MemoryProtection,
+}
+
+impl fmt::Display for CowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CowError::DuplicateFunction(name) => write!(f, "the function {} is already defined", name),
+            CowError::UnknownFunction(name) => write!(f, "the function {} is not defined", name),
+            CowError::DuplicateGlobal(name) => write!(f, "the global {} is already defined", name),
+            CowError::UnknownGlobal(name) => write!(f, "the global {} is not defined", name),
+            CowError::InvalidGlobal(reason) => write!(f, "invalid global: {}", reason),
+            CowError::FunctionInUse(name) => write!(f, "the function {} is still called by another function", name),
+            CowError::NotJitted(name) => write!(f, "the function {} is not jitted", name),
+            CowError::SignatureMismatch(name) => write!(f, "the function {} does not have the expected signature", name),
+            CowError::InvalidInstr(reason) => write!(f, "invalid instruction: {}", reason),
+            CowError::UndefinedVariable(id) => write!(f, "the variable {} is used before being defined", id),
+            CowError::UnknownBlock(id) => write!(f, "the block {} does not belong to the function", id),
+            CowError::UnknownVariable(id) => write!(f, "the variable {} does not belong to the function", id),
+            CowError::UnknownParam(index) => write!(f, "there is no parameter {}", index),
+            CowError::InvalidType(reason) => write!(f, "invalid type: {}", reason),
+            CowError::Verifier(error) => write!(f, "invalid function: {}", error),
+            CowError::Parse(error) => write!(f, "invalid ir: {}", error),
+            CowError::CodeArenaFull => write!(f, "the code arena is full"),
+            CowError::InvalidArenaConfig(reason) => write!(f, "invalid code arena configuration: {}", reason),
+            CowError::ArenaReservation => write!(f, "unable to reserve the code arena"),
+            CowError::MemoryProtection => write!(f, "unable to change the protection of the code memory"),
+        }
+    }
+}
+
+impl std::error::Error for CowError {}
+
+impl From<VerifierError> for CowError {
+    fn from(error: VerifierError) -> Self {
+        CowError::Verifier(error)
+    }
+}
+
+impl From<ParseError> for CowError {
+    fn from(error: ParseError) -> Self {
+        CowError::Parse(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use crate::compiler::Compiler;
+    use crate::lang::lang_type::Type;
+
+    fn jit_and_call(compiler: &mut Compiler) -> Result<i64, Box<dyn Error>> {
+        compiler.jit()?;
+        Ok(compiler.get_typed::<fn() -> i64>("f")?.call())
+    }
+
+    #[test]
+    fn errors_go_through_the_question_mark_with_their_message() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[], Type::i32()).unwrap().builder();
+        let value = builder.const_i32(1);
+        builder.ret(value);
+        let error = jit_and_call(&mut compiler).unwrap_err();
+        assert_eq!(error.to_string(), "the function f does not have the expected signature");
+
+        let builder = compiler.add_func("g", &[], Type::void()).unwrap().builder();
+        assert_eq!(builder.param(2).unwrap_err().to_string(), "there is no parameter 2");
+        builder.const_i64(1);
+        let error = jit_and_call(&mut compiler).unwrap_err();
+        assert_eq!(error.to_string(), "invalid function: function g, block 0: the block does not end with a terminator");
+    }
+}
@@ -1,12 +1,15 @@
 use std::collections::HashMap;
 use std::vec;
-use crate::gen::x86_64::x86_64_allocator::{X86_64Allocator, X86Register};
-use crate::gen::x86_64::x86_64_allocator::X86Register::{RAX, XMM0};
-use crate::gen::x86_64::x86_64_caller::X86_64Caller;
-use crate::gen::x86_64::x86_64_encoder::X86_64Encoder;
-use crate::lang::block::LangBlock;
+use crate::gen::x86_64::x86_64_allocator::{Location, X86_64Allocator, X86Register};
+use crate::gen::x86_64::x86_64_allocator::X86Register::{R11, RAX, RBP, RCX, RDX, RSP, XMM0, XMM14, XMM15};
+use crate::gen::x86_64::x86_64_caller::{ArgLocation, X86_64Caller};
+use crate::gen::x86_64::x86_64_encoder::{Condition, X86_64Encoder};
+use crate::gen::x86_64::x86_64_frame::X86_64Frame;
+use crate::gen::x86_64::x86_64_moves::{emit_move, emit_parallel_moves};
 use crate::lang::function::{Function};
-use crate::lang::instr::Instr;
+use crate::lang::instr::{Cast, Instr};
+use crate::lang::lang_type::Type;
+use crate::lang::value::Value;
 use crate::misc::byte_writer::ByteWriter;
 
 #[derive(Clone)]
@@ -16,190 +19,1554 @@ struct BlockOffset {
 }
 
 #[derive(Clone)]
-pub struct FunctionOffset {
-    func_name: String,
+pub(crate) enum RelocationTarget {
+    Func(String),
+    Global(String),
+}
+
+// a rel32 to a function or a global, resolved once every function is placed
+#[derive(Clone)]
+pub(crate) struct Relocation {
+    target: RelocationTarget,
     offset: usize,
-    func_id: usize,
 }
 
-pub(crate) struct X86_64Gen {}
+impl Relocation {
+    pub(crate) fn target(&self) -> &RelocationTarget {
+        &self.target
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+pub(crate) struct X86_64Gen {
+    // address of every host function that can be called by name
+    externs: HashMap<String, usize>,
+    // the comparison whose result is still in the flags, with the condition it tested
+    flags: Option<(usize, Condition)>,
+}
 
 impl X86_64Gen {
-    pub(crate) fn new() -> Self {
-        X86_64Gen {}
+    pub(crate) fn new(externs: HashMap<String, usize>) -> Self {
+        X86_64Gen { externs, flags: None }
     }
 
 
-    pub(crate) fn gen(&mut self, funcs: &mut HashMap<String, Function>) {
-        let mut func_offsets = vec![];
+    // generate every function, the calls between functions and the global addresses are returned by function name to be resolved after placement
+    pub(crate) fn gen(&mut self, funcs: &mut HashMap<String, Function>) -> HashMap<String, Vec<Relocation>> {
+        let mut relocations = HashMap::new();
 
-        // generate the function
         for func in funcs.values_mut() {
-            self.gen_func(func, &mut func_offsets)
+            let offsets = self.gen_func(func);
+            relocations.insert(func.name().clone(), offsets);
         }
 
-        // replace the offset of all functions
-        for offset in func_offsets {
-            let mut func = funcs.get_mut(&offset.func_name).unwrap();
-            let writer = ByteWriter::from_bytes(func.code());
-            //writer.rewrite_i32(offset.offset, )
-        }
+        relocations
     }
 
-    fn gen_func(&mut self, func: &mut Function, func_offsets: &mut Vec<FunctionOffset>) {
-        let mut func_offset: Vec<FunctionOffset> = vec![];
+    fn gen_func(&mut self, func: &mut Function) -> Vec<Relocation> {
+        let mut relocations: Vec<Relocation> = vec![];
         let mut block_offset: Vec<BlockOffset> = vec![];
-        let func_name = func.name().clone();
+        let caller = X86_64Caller::new();
+        let arg_locations = caller.arg_locations(func.args());
         let builder = func.builder();
-        let mut allocator = X86_64Allocator::new();
+        let mut allocator = X86_64Allocator::new(builder, caller.volatiles());
+        let frame = X86_64Frame::new(&mut allocator, caller.volatiles(), builder);
         let mut encoder = X86_64Encoder::new();
 
-        for block in builder.blocks() {
+        frame.emit_prologue(&mut encoder);
+        self.gen_params(builder.params(), &arg_locations, &allocator, &mut encoder);
+
+        let block_params: Vec<Vec<Value>> = builder.blocks().iter().map(|block| block.params().clone()).collect();
+        let mut instr_index = 0;
+        for block in builder.blocks_mut() {
             block.set_offset(encoder.bytes().len());
-            for instr in block.instructions() {
-                self.gen_instr(&func_name, instr, &mut allocator, &mut encoder, &mut func_offset, &mut block_offset);
+            for instr in block.instructions_mut() {
+                let position = X86_64Allocator::instr_position(instr_index);
+                self.gen_instr(instr, position, &allocator, &frame, &block_params, &mut encoder, &mut relocations, &mut block_offset);
+                instr_index += 1;
             }
         }
 
         let mut writer = ByteWriter::from_bytes(encoder.bytes());
 
         for offset in block_offset {
-            writer.rewrite_i32(offset.offset, builder.blocks()[offset.block].offset() as i32 - offset.offset as i32 - 4);
+            writer.rewrite_i32(offset.offset, builder.blocks_mut()[offset.block].offset() as i32 - offset.offset as i32 - 4);
         }
 
-        func.set_code(writer.bytes())
+        func.set_code(writer.bytes());
+        relocations
     }
 
-    fn gen_instr(&mut self, func_name: &String, instr: &mut Instr, allocator: &mut X86_64Allocator, encode: &mut X86_64Encoder,
-                 func_offsets: &mut Vec<FunctionOffset>, block_offsets: &mut Vec<BlockOffset>) {
-        match instr {
-            Instr::ConstInt128 { .. } => {}
+    // every argument of a branch is moved at once to the parameter of the block it goes to
+    fn gen_branch_args(&mut self, args: &[Value], params: &[Value], allocator: &X86_64Allocator, encode: &mut X86_64Encoder) {
+        let moves: Vec<(Location, Location)> = args.iter().zip(params)
+            .map(|(arg, param)| (allocator.location(arg), allocator.location(param)))
+            .collect();
+        emit_parallel_moves(encode, &moves);
+    }
+
+    fn gen_params(&mut self, params: &[Value], arg_locations: &[ArgLocation], allocator: &X86_64Allocator, encode: &mut X86_64Encoder) {
+        // every parameter is moved at once from where it is passed to where it was allocated
+        let moves: Vec<(Location, Location)> = params.iter().zip(arg_locations).map(|(param, arg_location)| {
+            let src = match arg_location {
+                ArgLocation::Register(reg) => Location::Register(*reg),
+                // the stack arguments are above the pushed base pointer
+                ArgLocation::Stack(offset) => Location::Stack(offset + 8),
+            };
+            (src, allocator.location(param))
+        }).collect();
+
+        emit_parallel_moves(encode, &moves);
+
+        // the caller only sets the low bytes of a narrow argument, they are kept sign extended in the function
+        for param in params {
+            Self::normalize_value(param, allocator, encode);
+        }
+    }
+
+    fn normalize_value(value: &Value, allocator: &X86_64Allocator, encode: &mut X86_64Encoder) {
+        let value_type = value.get_type();
+        if value_type.is_float() || value_type.size() >= 8 || value_type == Type::void() {
+            return;
+        }
+
+        let reg = allocator.read_value(encode, value, RAX);
+        encode.sign_extend_reg(reg, value_type.size());
+        allocator.write_value(encode, value, reg);
+    }
+
+    // result = left op right, for the instructions that overwrite their first operand with the result
+    fn gen_two_operands(&mut self, left_value: &Value, right_value: &Value, gen_value: &Value, allocator: &X86_64Allocator,
+                        encode: &mut X86_64Encoder, op: impl Fn(&mut X86_64Encoder, X86Register, X86Register)) {
+        let (scratch, second_scratch) = Self::scratch_registers(gen_value);
+        let left = allocator.read_value(encode, left_value, scratch);
+        let right = allocator.read_value(encode, right_value, second_scratch);
+
+        // the right operand can die here and leave its register to the result, it has to be read before being overwritten
+        let mut result = allocator.value_target(gen_value, scratch);
+        if result == right && result != left {
+            result = scratch;
+        }
+
+        emit_move(encode, Location::Register(left), Location::Register(result));
+        op(encode, result, right);
+        if !gen_value.get_type().is_float() {
+            encode.sign_extend_reg(result, gen_value.get_type().size());
+        }
+        allocator.write_value(encode, gen_value, result);
+    }
+
+    // idiv and div only divide rdx:rax, both are kept out of the allocator for them
+    #[allow(clippy::too_many_arguments)]
+    fn gen_division(&mut self, left_value: &Value, right_value: &Value, gen_value: &Value, signed: bool, remainder: bool,
+                    allocator: &X86_64Allocator, encode: &mut X86_64Encoder) {
+        let size = gen_value.get_type().size();
+        emit_move(encode, allocator.location(left_value), Location::Register(RAX));
+        emit_move(encode, allocator.location(right_value), Location::Register(RCX));
+
+        if signed {
+            encode.cqo();
+            encode.div_reg_reg(RCX);
+        } else {
+            // the narrow values are kept sign extended, the unsigned division needs them zero extended
+            encode.zero_extend_reg(RAX, size);
+            encode.zero_extend_reg(RCX, size);
+            encode.clear_rdx();
+            encode.udiv_reg_reg(RCX);
+        }
+
+        let result = if remainder { RDX } else { RAX };
+        encode.sign_extend_reg(result, size);
+        allocator.write_value(encode, gen_value, result);
+    }
+
+    // a shift by register only takes its count in cl, rcx is kept out of the allocator for it
+    fn gen_shift(&mut self, left_value: &Value, right_value: &Value, gen_value: &Value, allocator: &X86_64Allocator,
+                 encode: &mut X86_64Encoder, op: fn(&mut X86_64Encoder, X86Register, usize)) {
+        emit_move(encode, allocator.location(right_value), Location::Register(RCX));
+
+        let left = allocator.read_value(encode, left_value, RAX);
+        let result = allocator.value_target(gen_value, RAX);
+        emit_move(encode, Location::Register(left), Location::Register(result));
+
+        // the shift works on the width of the value, the result is sign extended back to 64 bits
+        let size = gen_value.get_type().size();
+        op(encode, result, size);
+        encode.sign_extend_reg(result, size);
+        allocator.write_value(encode, gen_value, result);
+    }
+
+    fn gen_shift_imm(&mut self, value: &Value, amount: u8, gen_value: &Value, allocator: &X86_64Allocator,
+                     encode: &mut X86_64Encoder, op: fn(&mut X86_64Encoder, X86Register, usize, u8)) {
+        let reg = allocator.read_value(encode, value, RAX);
+        let result = allocator.value_target(gen_value, RAX);
+        emit_move(encode, Location::Register(reg), Location::Register(result));
+
+        let size = gen_value.get_type().size();
+        op(encode, result, size, amount);
+        encode.sign_extend_reg(result, size);
+        allocator.write_value(encode, gen_value, result);
+    }
+
+    fn gen_cast(&mut self, cast: Cast, value: &Value, gen_value: &Value, allocator: &X86_64Allocator, encode: &mut X86_64Encoder) {
+        let from = value.get_type();
+        let to = gen_value.get_type();
+
+        match cast {
+            Cast::SExt | Cast::ZExt | Cast::Trunc | Cast::PtrToInt | Cast::IntToPtr => {
+                let reg = allocator.read_value(encode, value, RAX);
+                let result = allocator.value_target(gen_value, RAX);
+                emit_move(encode, Location::Register(reg), Location::Register(result));
+
+                match cast {
+                    // the narrow integers are already kept sign extended, only a bool has to become 0 or -1
+                    Cast::SExt => {
+                        if from.is_bool() {
+                            encode.neg_reg(result);
+                        }
+                    }
+                    Cast::ZExt | Cast::IntToPtr => {
+                        encode.zero_extend_reg(result, from.size());
+                        encode.sign_extend_reg(result, to.size());
+                    }
+                    _ => encode.sign_extend_reg(result, to.size()),
+                }
+                allocator.write_value(encode, gen_value, result);
+            }
+
+            Cast::Bitcast => {
+                emit_move(encode, allocator.location(value), allocator.location(gen_value));
+                Self::normalize_value(gen_value, allocator, encode);
+            }
+
+            Cast::SIToFP => {
+                let reg = allocator.read_value(encode, value, RAX);
+                let result = allocator.value_target(gen_value, XMM15);
+                encode.int_to_float(reg, result, to == Type::f64());
+                allocator.write_value(encode, gen_value, result);
+            }
+
+            Cast::UIToFP => {
+                let double = to == Type::f64();
+                emit_move(encode, allocator.location(value), Location::Register(RAX));
+                let result = allocator.value_target(gen_value, XMM15);
+
+                if from.size() < 8 {
+                    encode.zero_extend_reg(RAX, from.size());
+                    encode.int_to_float(RAX, result, double);
+                } else {
+                    encode.test_reg_reg(RAX, RAX);
+                    let large_offset = encode.jmp_cond(Condition::Less);
+                    encode.int_to_float(RAX, result, double);
+                    let done_offset = encode.jmp();
+
+                    // above i64::MAX the value is halved, keeping its lowest bit for the rounding, and doubled back
+                    encode.patch_jump_here(large_offset);
+                    encode.mov_reg_to_reg(RAX, R11);
+                    encode.shr_reg_imm(R11, 8, 1);
+                    encode.and_reg_imm(RAX, 1);
+                    encode.or_reg_reg(R11, RAX);
+                    encode.int_to_float(R11, result, double);
+                    encode.add_float(result, result, double);
+                    encode.patch_jump_here(done_offset);
+                }
+                allocator.write_value(encode, gen_value, result);
+            }
+
+            Cast::FPToSI => {
+                let reg = allocator.read_value(encode, value, XMM15);
+                let result = allocator.value_target(gen_value, RAX);
+                encode.float_to_int(reg, result, from == Type::f64());
+                encode.sign_extend_reg(result, to.size());
+                allocator.write_value(encode, gen_value, result);
+            }
+
+            Cast::FPToUI => {
+                let double = from == Type::f64();
+                let reg = allocator.read_value(encode, value, XMM15);
+
+                if to.size() < 8 {
+                    // every narrow unsigned value fits in the signed 64 bits conversion
+                    encode.float_to_int(reg, RAX, double);
+                    encode.sign_extend_reg(RAX, to.size());
+                } else {
+                    // 2^63, the first value the signed conversion cannot handle
+                    let limit = if double { 0x43E0000000000000 } else { 0x5F000000 };
+                    encode.move_reg_i64(RAX, limit);
+                    encode.move_reg_to_xmm(RAX, XMM14);
+                    encode.ucomi(reg, XMM14, double);
+                    let large_offset = encode.jmp_cond(Condition::AboveEqual);
+                    encode.float_to_int(reg, RAX, double);
+                    let done_offset = encode.jmp();
+
+                    // the value is brought under 2^63 and the top bit is set back after the conversion
+                    encode.patch_jump_here(large_offset);
+                    encode.mov_xmm_to_xmm(reg, XMM15);
+                    encode.sub_float(XMM15, XMM14, double);
+                    encode.float_to_int(XMM15, RAX, double);
+                    encode.move_reg_i64(R11, i64::MIN);
+                    encode.xor_reg_reg(RAX, R11);
+                    encode.patch_jump_here(done_offset);
+                }
+                allocator.write_value(encode, gen_value, RAX);
+            }
+
+            Cast::FPExt | Cast::FPTrunc => {
+                let reg = allocator.read_value(encode, value, XMM15);
+                let result = allocator.value_target(gen_value, XMM15);
+                if cast == Cast::FPExt {
+                    encode.float_to_double(reg, result);
+                } else {
+                    encode.double_to_float(reg, result);
+                }
+                allocator.write_value(encode, gen_value, result);
+            }
+        }
+    }
+
+    // the boolean is materialized from the flags, which are left for a cond_br that would follow
+    fn gen_compare(&mut self, left_value: &Value, right_value: &Value, gen_value: &Value, condition: Condition,
+                   allocator: &X86_64Allocator, encode: &mut X86_64Encoder) {
+        if left_value.get_type().is_float() {
+            self.gen_float_compare(left_value, right_value, gen_value, condition, allocator, encode);
+            return;
+        }
+
+        let left = allocator.read_value(encode, left_value, RAX);
+        let right = allocator.read_value(encode, right_value, R11);
+        encode.cmp_reg_reg(left, right);
+
+        // setcc and movzx leave the flags as they are
+        let result = allocator.value_target(gen_value, RAX);
+        encode.set_cond(condition, result);
+        encode.zero_extend_reg(result, 1);
+        allocator.write_value(encode, gen_value, result);
+
+        self.flags = Some((gen_value.get_id(), condition));
+    }
+
+    // ucomisd sets the flags like an unsigned comparison, and sets zf, pf and cf together when an operand is nan.
+    // the comparisons are ordered, false with a nan, but for not equal which is true with a nan
+    fn gen_float_compare(&mut self, left_value: &Value, right_value: &Value, gen_value: &Value, condition: Condition,
+                         allocator: &X86_64Allocator, encode: &mut X86_64Encoder) {
+        let double = left_value.get_type() == Type::f64();
+
+        // below would be true for unordered operands, the operands are swapped to test above instead
+        let (left_value, right_value, condition) = match condition {
+            Condition::Greater => (left_value, right_value, Condition::Above),
+            Condition::GreaterEqual => (left_value, right_value, Condition::AboveEqual),
+            Condition::Less => (right_value, left_value, Condition::Above),
+            Condition::LessEqual => (right_value, left_value, Condition::AboveEqual),
+            _ => (left_value, right_value, condition),
+        };
+
+        let left = allocator.read_value(encode, left_value, XMM15);
+        let right = allocator.read_value(encode, right_value, XMM14);
+        encode.ucomi(left, right, double);
+
+        let result = allocator.value_target(gen_value, RAX);
+        encode.set_cond(condition, result);
+        encode.zero_extend_reg(result, 1);
+
+        // equality needs the parity flag too, the result is no longer a single condition a cond_br could test
+        match condition {
+            Condition::Equal => {
+                encode.set_cond(Condition::NotParity, R11);
+                encode.zero_extend_reg(R11, 1);
+                encode.and_reg_reg(result, R11);
+            }
+            Condition::NotEqual => {
+                encode.set_cond(Condition::Parity, R11);
+                encode.zero_extend_reg(R11, 1);
+                encode.or_reg_reg(result, R11);
+            }
+            _ => self.flags = Some((gen_value.get_id(), condition)),
+        }
+        allocator.write_value(encode, gen_value, result);
+    }
+
+    fn scratch_registers(value: &Value) -> (X86Register, X86Register) {
+        if value.get_type().is_float() {
+            (XMM15, XMM14)
+        } else {
+            (RAX, R11)
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn gen_instr(&mut self, instr: &mut Instr, position: usize, allocator: &X86_64Allocator, frame: &X86_64Frame, block_params: &[Vec<Value>], encode: &mut X86_64Encoder,
+                 relocations: &mut Vec<Relocation>, block_offsets: &mut Vec<BlockOffset>) {
+        // every instruction but a comparison can change the flags
+        let flags = self.flags.take();
 
+        match instr {
             Instr::ConstInt64 { const_value, gen_value } => {
-                let const_reg = allocator.obtain_register_for_value(gen_value.clone());
                 if gen_value.get_type().is_float() {
                     encode.move_reg_i64(RAX, *const_value);
-                    encode.move_reg_to_xmm(RAX, const_reg);
+                    allocator.write_value(encode, gen_value, RAX);
                 } else {
+                    let const_reg = allocator.value_target(gen_value, RAX);
                     encode.move_reg_i64(const_reg, *const_value);
+                    allocator.write_value(encode, gen_value, const_reg);
                 }
             }
 
             Instr::ConstInt32 { const_value, gen_value } => {
-                let const_reg = allocator.obtain_register_for_value(gen_value.clone());
-                if gen_value.get_type().is_float() {} else {
+                if gen_value.get_type().is_float() {
+                    // the bits of a f32 go in the low half of the xmm register
+                    encode.move_reg_i64(RAX, *const_value as u32 as i64);
+                    allocator.write_value(encode, gen_value, RAX);
+                } else {
+                    let const_reg = allocator.value_target(gen_value, RAX);
                     encode.move_reg_i64(const_reg, *const_value as i64);
+                    allocator.write_value(encode, gen_value, const_reg);
                 }
             }
 
             Instr::ConstInt16 { const_value, gen_value } => {
-                let const_reg = allocator.obtain_register_for_value(gen_value.clone());
+                let const_reg = allocator.value_target(gen_value, RAX);
                 encode.move_reg_i64(const_reg, *const_value as i64);
+                allocator.write_value(encode, gen_value, const_reg);
             }
 
             Instr::ConstInt8 { const_value, gen_value } => {
-                let const_reg = allocator.obtain_register_for_value(gen_value.clone());
+                let const_reg = allocator.value_target(gen_value, RAX);
                 encode.move_reg_i64(const_reg, *const_value as i64);
+                allocator.write_value(encode, gen_value, const_reg);
             }
 
             Instr::ConstPtr { const_value, gen_value } => {
-                let const_reg = allocator.obtain_register_for_value(gen_value.clone());
+                let const_reg = allocator.value_target(gen_value, RAX);
                 encode.move_reg_i64(const_reg, *const_value as i64);
+                allocator.write_value(encode, gen_value, const_reg);
+            }
+
+            Instr::GlobalAddr { name, gen_value } => {
+                let address_reg = allocator.value_target(gen_value, RAX);
+                relocations.push(Relocation { target: RelocationTarget::Global(name.clone()), offset: encode.lea_rip_to_reg(address_reg) });
+                allocator.write_value(encode, gen_value, address_reg);
             }
 
             Instr::Add { left_value, right_value, gen_value } => {
-                let left_reg = allocator.obtain_register_for_value(left_value.clone());
-                let right_reg = allocator.obtain_register_for_value(right_value.clone());
-                let result_reg = allocator.allocate_register(gen_value.clone()).unwrap();
-                if left_reg.is_xmm() {
-                    encode.add_xmm_xmm(left_reg, right_reg);
-                    encode.mov_reg_to_reg(left_reg, result_reg);
+                if gen_value.get_type().is_float() {
+                    let double = gen_value.get_type() == Type::f64();
+                    self.gen_two_operands(left_value, right_value, gen_value, allocator, encode, |encode, left, right| encode.add_float(left, right, double));
+                } else {
+                    self.gen_two_operands(left_value, right_value, gen_value, allocator, encode, X86_64Encoder::add_reg_reg);
+                }
+            }
+
+            Instr::Sub { left_value, right_value, gen_value } => {
+                if gen_value.get_type().is_float() {
+                    let double = gen_value.get_type() == Type::f64();
+                    self.gen_two_operands(left_value, right_value, gen_value, allocator, encode, |encode, left, right| encode.sub_float(left, right, double));
+                } else {
+                    self.gen_two_operands(left_value, right_value, gen_value, allocator, encode, X86_64Encoder::sub_reg_reg);
+                }
+            }
+
+            Instr::Div { left_value, right_value, gen_value } => {
+                if gen_value.get_type().is_float() {
+                    let double = gen_value.get_type() == Type::f64();
+                    self.gen_two_operands(left_value, right_value, gen_value, allocator, encode, |encode, left, right| encode.div_float(left, right, double));
+                } else {
+                    self.gen_division(left_value, right_value, gen_value, true, false, allocator, encode);
+                }
+            }
+
+            Instr::Mul { left_value, right_value, gen_value } => {
+                if gen_value.get_type().is_float() {
+                    let double = gen_value.get_type() == Type::f64();
+                    self.gen_two_operands(left_value, right_value, gen_value, allocator, encode, |encode, left, right| encode.mul_float(left, right, double));
+                } else {
+                    self.gen_two_operands(left_value, right_value, gen_value, allocator, encode, X86_64Encoder::mul_reg_reg);
+                }
+            }
+
+            Instr::Neg { value, gen_value } => {
+                let value_type = gen_value.get_type();
+                let (scratch, _) = Self::scratch_registers(gen_value);
+                let reg = allocator.read_value(encode, value, scratch);
+                let result = allocator.value_target(gen_value, scratch);
+                emit_move(encode, Location::Register(reg), Location::Register(result));
+
+                if value_type.is_float() {
+                    // the sign bit is flipped, which also gives -0.0 for 0.0
+                    let sign_mask = if value_type == Type::f64() { i64::MIN } else { 0x80000000 };
+                    encode.move_reg_i64(RAX, sign_mask);
+                    encode.move_reg_to_xmm(RAX, XMM14);
+                    encode.xor_float(result, XMM14);
                 } else {
-                    encode.add_reg_reg(left_reg, right_reg);
-                    encode.mov_reg_to_reg(left_reg, result_reg);
+                    encode.neg_reg(result);
+                    encode.sign_extend_reg(result, value_type.size());
                 }
+                allocator.write_value(encode, gen_value, result);
+            }
+
+            Instr::Abs { value, gen_value } => {
+                let reg = allocator.read_value(encode, value, XMM15);
+                let result = allocator.value_target(gen_value, XMM15);
+                emit_move(encode, Location::Register(reg), Location::Register(result));
+
+                let value_mask = if gen_value.get_type() == Type::f64() { i64::MAX } else { 0x7FFFFFFF };
+                encode.move_reg_i64(RAX, value_mask);
+                encode.move_reg_to_xmm(RAX, XMM14);
+                encode.and_float(result, XMM14);
+                allocator.write_value(encode, gen_value, result);
+            }
+
+            Instr::UDiv { left_value, right_value, gen_value } => {
+                self.gen_division(left_value, right_value, gen_value, false, false, allocator, encode);
+            }
+
+            Instr::SRem { left_value, right_value, gen_value } => {
+                self.gen_division(left_value, right_value, gen_value, true, true, allocator, encode);
+            }
+
+            Instr::URem { left_value, right_value, gen_value } => {
+                self.gen_division(left_value, right_value, gen_value, false, true, allocator, encode);
+            }
+
+            Instr::Eq { left_value, right_value, gen_value } => {
+                self.gen_compare(left_value, right_value, gen_value, Condition::Equal, allocator, encode);
+            }
+
+            Instr::Diff { left_value, right_value, gen_value } => {
+                self.gen_compare(left_value, right_value, gen_value, Condition::NotEqual, allocator, encode);
+            }
+
+            Instr::Larger { left_value, right_value, gen_value } => {
+                self.gen_compare(left_value, right_value, gen_value, Condition::Greater, allocator, encode);
+            }
+
+            Instr::LargerEq { left_value, right_value, gen_value } => {
+                self.gen_compare(left_value, right_value, gen_value, Condition::GreaterEqual, allocator, encode);
+            }
+
+            Instr::Smaller { left_value, right_value, gen_value } => {
+                self.gen_compare(left_value, right_value, gen_value, Condition::Less, allocator, encode);
+            }
+
+            Instr::SmallerEq { left_value, right_value, gen_value } => {
+                self.gen_compare(left_value, right_value, gen_value, Condition::LessEqual, allocator, encode);
+            }
+
+            Instr::Ordered { left_value, right_value, gen_value } => {
+                self.gen_compare(left_value, right_value, gen_value, Condition::NotParity, allocator, encode);
+            }
+
+            Instr::Unordered { left_value, right_value, gen_value } => {
+                self.gen_compare(left_value, right_value, gen_value, Condition::Parity, allocator, encode);
+            }
+
+            // sign extending keeps the unsigned order of the narrow values, the 64 bits comparison is enough
+            Instr::ULarger { left_value, right_value, gen_value } => {
+                self.gen_compare(left_value, right_value, gen_value, Condition::Above, allocator, encode);
+            }
+
+            Instr::ULargerEq { left_value, right_value, gen_value } => {
+                self.gen_compare(left_value, right_value, gen_value, Condition::AboveEqual, allocator, encode);
+            }
+
+            Instr::USmaller { left_value, right_value, gen_value } => {
+                self.gen_compare(left_value, right_value, gen_value, Condition::Below, allocator, encode);
+            }
+
+            Instr::USmallerEq { left_value, right_value, gen_value } => {
+                self.gen_compare(left_value, right_value, gen_value, Condition::BelowEqual, allocator, encode);
+            }
+
+            Instr::LShr { left_value, right_value, gen_value } => {
+                self.gen_shift(left_value, right_value, gen_value, allocator, encode, X86_64Encoder::shr_reg_cl);
+            }
+
+            Instr::AShr { left_value, right_value, gen_value } => {
+                self.gen_shift(left_value, right_value, gen_value, allocator, encode, X86_64Encoder::sar_reg_cl);
+            }
+
+            Instr::Shl { left_value, right_value, gen_value } => {
+                self.gen_shift(left_value, right_value, gen_value, allocator, encode, X86_64Encoder::shl_reg_cl);
+            }
+
+            Instr::Rotl { left_value, right_value, gen_value } => {
+                self.gen_shift(left_value, right_value, gen_value, allocator, encode, X86_64Encoder::rol_reg_cl);
+            }
+
+            Instr::Rotr { left_value, right_value, gen_value } => {
+                self.gen_shift(left_value, right_value, gen_value, allocator, encode, X86_64Encoder::ror_reg_cl);
             }
 
-            Instr::Sub { .. } => {}
+            Instr::ShlImm { value, amount, gen_value } => {
+                self.gen_shift_imm(value, *amount, gen_value, allocator, encode, X86_64Encoder::shl_reg_imm);
+            }
 
-            Instr::Div { .. } => {}
+            Instr::LShrImm { value, amount, gen_value } => {
+                self.gen_shift_imm(value, *amount, gen_value, allocator, encode, X86_64Encoder::shr_reg_imm);
+            }
 
-            Instr::Mul { .. } => {}
+            Instr::AShrImm { value, amount, gen_value } => {
+                self.gen_shift_imm(value, *amount, gen_value, allocator, encode, X86_64Encoder::sar_reg_imm);
+            }
 
-            Instr::Eq { .. } => {}
+            Instr::RotlImm { value, amount, gen_value } => {
+                self.gen_shift_imm(value, *amount, gen_value, allocator, encode, X86_64Encoder::rol_reg_imm);
+            }
+
+            Instr::RotrImm { value, amount, gen_value } => {
+                self.gen_shift_imm(value, *amount, gen_value, allocator, encode, X86_64Encoder::ror_reg_imm);
+            }
+
+            Instr::And { left_value, right_value, gen_value } => {
+                self.gen_two_operands(left_value, right_value, gen_value, allocator, encode, X86_64Encoder::and_reg_reg);
+            }
 
-            Instr::Diff { .. } => {}
+            Instr::Or { left_value, right_value, gen_value } => {
+                self.gen_two_operands(left_value, right_value, gen_value, allocator, encode, X86_64Encoder::or_reg_reg);
+            }
 
-            Instr::Larger { .. } => {}
+            Instr::Xor { left_value, right_value, gen_value } => {
+                self.gen_two_operands(left_value, right_value, gen_value, allocator, encode, X86_64Encoder::xor_reg_reg);
+            }
 
-            Instr::LargerEq { .. } => {}
+            Instr::Not { value, gen_value } => {
+                let reg = allocator.read_value(encode, value, RAX);
+                let result = allocator.value_target(gen_value, RAX);
+                emit_move(encode, Location::Register(reg), Location::Register(result));
 
-            Instr::Smaller { .. } => {}
+                // a bool is 0 or 1, only its lowest bit is flipped
+                if gen_value.get_type() == Type::bool() {
+                    encode.xor_reg_imm(result, 1);
+                } else {
+                    encode.not_reg(result);
+                }
+                allocator.write_value(encode, gen_value, result);
+            }
 
-            Instr::SmallerEq { .. } => {}
+            Instr::Cast { cast, value, gen_value } => {
+                self.gen_cast(*cast, value, gen_value, allocator, encode);
+            }
 
-            Instr::Not { .. } => {}
+            Instr::StackSlot { gen_value, .. } => {
+                let result = allocator.value_target(gen_value, RAX);
+                encode.lea_mem_disp_to_reg(RBP, frame.stack_slot_offset(gen_value), result);
+                allocator.write_value(encode, gen_value, result);
+            }
 
-            Instr::Load { value_to_load, gen_value } => {
-                let mem_reg = allocator.obtain_register_for_value(value_to_load.clone());
-                let reg = allocator.obtain_register_for_value(gen_value.clone());
-                encode.mov_mem_to_reg(mem_reg, reg);
+            Instr::Alloca { size_value, gen_value } => {
+                // the size is unsigned, a narrow one is kept sign extended in its register and is zero extended again
+                emit_move(encode, allocator.location(size_value), Location::Register(RAX));
+                encode.zero_extend_reg(RAX, size_value.get_type().size());
+                X86_64Frame::emit_stack_alloc(encode, RAX);
+                allocator.write_value(encode, gen_value, RSP);
             }
 
-            Instr::Store { value_ptr, value_to_store } => {
-                let reg = allocator.obtain_register_for_value(value_ptr.clone());
-                let value = allocator.obtain_register_for_value(value_to_store.clone());
-                encode.mov_reg_to_mem(value, reg);
+            Instr::Load { value_to_load, offset, mem_type, zero_extend, gen_value } => {
+                let base = allocator.read_value(encode, value_to_load, R11);
+                let (scratch, _) = Self::scratch_registers(gen_value);
+                let result = allocator.value_target(gen_value, scratch);
+
+                if *mem_type == Type::f64() {
+                    encode.movsd_mem_disp_to_xmm(base, *offset, result);
+                } else if *mem_type == Type::f32() {
+                    encode.movss_mem_disp_to_xmm(base, *offset, result);
+                } else {
+                    // a bool is stored as 0 or 1, it is loaded as it is
+                    encode.mov_mem_to_reg(base, *offset, result, mem_type.size(), *zero_extend || mem_type.is_bool());
+                }
+                allocator.write_value(encode, gen_value, result);
             }
 
-            Instr::Br { block_to_br } => {
+            Instr::Store { value_ptr, value_to_store, offset } => {
+                let base = allocator.read_value(encode, value_ptr, R11);
+                let (scratch, _) = Self::scratch_registers(value_to_store);
+                let value = allocator.read_value(encode, value_to_store, scratch);
+                let value_type = value_to_store.get_type();
+
+                if value_type == Type::f64() {
+                    encode.movsd_xmm_to_mem_disp(value, base, *offset);
+                } else if value_type == Type::f32() {
+                    encode.movss_xmm_to_mem_disp(value, base, *offset);
+                } else {
+                    encode.mov_reg_to_mem(value, base, *offset, value_type.size());
+                }
+            }
+
+            Instr::Br { block_to_br, args } => {
+                self.gen_branch_args(args, &block_params[block_to_br.get_id()], allocator, encode);
                 let offset = BlockOffset { block: block_to_br.get_id(), offset: encode.jmp() };
                 block_offsets.push(offset);
             }
 
-            Instr::CondBr { value_cond, block_to_br_true, block_to_br_false } => {
-                let true_offset = encode.cond_jmp(allocator.obtain_register_for_value(value_cond.clone()));
-                block_offsets.push(BlockOffset { block: block_to_br_false.get_id(), offset: true_offset });
+            Instr::CondBr { value_cond, block_to_br_true, true_args, block_to_br_false, false_args } => {
+                // the jump is taken when the condition is true for the flags and when it is false for a register
+                let (jump_offset, jump_block, jump_args, fall_block, fall_args) = match flags {
+                    // the condition was just compared, the flags still hold its result
+                    Some((value_id, condition)) if value_id == value_cond.get_id() => {
+                        (encode.jmp_cond(condition), block_to_br_true, true_args, block_to_br_false, false_args)
+                    }
+                    _ => {
+                        let cond_reg = allocator.read_value(encode, value_cond, RAX);
+                        (encode.cond_jmp(cond_reg), block_to_br_false, false_args, block_to_br_true, true_args)
+                    }
+                };
+
+                self.gen_branch_args(fall_args, &block_params[fall_block.get_id()], allocator, encode);
+                block_offsets.push(BlockOffset { block: fall_block.get_id(), offset: encode.jmp() });
 
-                let false_offset = encode.jmp();
-                block_offsets.push(BlockOffset { block: block_to_br_true.get_id(), offset: false_offset });
+                // the arguments of the jump are moved on its own edge, after the fall through path
+                if jump_args.is_empty() {
+                    block_offsets.push(BlockOffset { block: jump_block.get_id(), offset: jump_offset });
+                } else {
+                    encode.patch_jump_here(jump_offset);
+                    self.gen_branch_args(jump_args, &block_params[jump_block.get_id()], allocator, encode);
+                    block_offsets.push(BlockOffset { block: jump_block.get_id(), offset: encode.jmp() });
+                }
             }
 
-            Instr::CallPtr { ptr_to_call, args, return_type, gen_value } => {
+            Instr::CallPtr { ptr_to_call, args, return_type: _, gen_value } => {
                 let caller = X86_64Caller::new();
-                let call_reg = allocator.obtain_register_for_value(ptr_to_call.clone());
-                encode.mov_reg_to_reg(call_reg, RAX);
-
-                let regs_to_pop = caller.generate_call(encode, allocator, args);
-                encode.push_shadow();
+                let cleanup = caller.generate_call(encode, allocator, args, &allocator.registers_live_across(position), Some(ptr_to_call));
                 encode.call(RAX);
-                encode.pop_shadow();
 
-                for reg in regs_to_pop.iter().rev() { encode.pop_reg(reg.clone()) };
+                // the result is taken out of rax or xmm0 before the saved registers are restored over it
+                self.gen_call_result(gen_value, allocator, encode);
+                caller.finish_call(encode, cleanup);
+            }
 
-                let ret_reg = allocator.obtain_register_for_value(gen_value.clone());
+            Instr::CallFunc { func_to_call, args, gen_value } => {
+                let caller = X86_64Caller::new();
+                let cleanup = caller.generate_call(encode, allocator, args, &allocator.registers_live_across(position), None);
 
-                if return_type.is_float() {
-                    encode.mov_xmm_to_xmm(XMM0, ret_reg);
+                // host functions can be anywhere in the address space, out of the reach of a rel32
+                if let Some(address) = self.externs.get(func_to_call) {
+                    encode.move_reg_i64(RAX, *address as i64);
+                    encode.call(RAX);
                 } else {
-                    encode.mov_reg_to_reg(RAX, ret_reg);
+                    relocations.push(Relocation { target: RelocationTarget::Func(func_to_call.clone()), offset: encode.call_rel32() });
                 }
-            }
 
-            Instr::CallFunc { .. } => {}
+                // the result is taken out of rax or xmm0 before the saved registers are restored over it
+                self.gen_call_result(gen_value, allocator, encode);
+                caller.finish_call(encode, cleanup);
+            }
 
             Instr::Ret { value_to_return } => {
-                let reg_to_return = allocator.obtain_register_for_value(value_to_return.clone());
-                if value_to_return.get_type().is_float() {
-                    encode.mov_xmm_to_xmm(reg_to_return, X86Register::XMM0);
-                } else {
-                    encode.mov_reg_to_reg(reg_to_return, X86Register::RAX);
-                }
+                let return_reg = if value_to_return.get_type().is_float() { XMM0 } else { RAX };
+                emit_move(encode, allocator.location(value_to_return), Location::Register(return_reg));
+                frame.emit_epilogue(encode);
+            }
 
-                encode.ret();
+            Instr::RetVoid => {
+                frame.emit_epilogue(encode);
             }
+        }
+    }
+
+    fn gen_call_result(&mut self, gen_value: &Value, allocator: &X86_64Allocator, encode: &mut X86_64Encoder) {
+        let return_type = gen_value.get_type();
+        if return_type == Type::void() {
+            return;
+        }
+
+        let return_reg = if return_type.is_float() { XMM0 } else { RAX };
+        if !return_type.is_float() {
+            encode.sign_extend_reg(return_reg, return_type.size());
+        }
+        allocator.write_value(encode, gen_value, return_reg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::Compiler;
+    use crate::lang::builder::Builder;
+    use crate::lang::lang_type::Type;
+    use crate::lang::value::Value;
+
+    extern "C" fn add3(a: i64, b: i64, c: i64) -> i64 {
+        a * 100 + b * 10 + c
+    }
+
+    extern "C" fn mix(a: i64, x: f64, b: i64, y: f64, c: i64, d: i64, e: i64, f: i64, g: i64) -> f64 {
+        (a + b + c + d + e + f + g) as f64 * x - y
+    }
+
+    type BinaryOp = fn(&mut Builder, Value, Value) -> Value;
+
+    // the sum of the len bytes at ptr, or -1 when ptr is not 16 bytes aligned
+    extern "C" fn sum_aligned_bytes(ptr: *const u8, len: i64) -> i64 {
+        if !(ptr as usize).is_multiple_of(16) {
+            return -1;
+        }
+        unsafe { std::slice::from_raw_parts(ptr, len as usize) }.iter().map(|byte| *byte as i64).sum()
+    }
+
+    extern "C" fn fill_ones(ptr: *mut u8, len: i64) {
+        unsafe { std::slice::from_raw_parts_mut(ptr, len as usize) }.fill(1);
+    }
+
+    // a function "f" giving op of its two params
+    fn binary_func(param_type: Type, return_type: Type, op: BinaryOp) -> Compiler {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[param_type.clone(), param_type], return_type).unwrap().builder();
+        let (left, right) = (builder.param(0).unwrap(), builder.param(1).unwrap());
+        let result = op(builder, left, right);
+        builder.ret(result);
+        compiler.jit().unwrap();
+        compiler
+    }
+
+    #[test]
+    fn call_ptr_with_the_pointer_in_an_argument_register() {
+        let mut compiler = Compiler::new().unwrap();
+        let func = compiler.add_func("f", &[Type::ptr(), Type::i64(), Type::i64(), Type::i64()], Type::i64()).unwrap();
+        let builder = func.builder();
+        let (ptr, a, b, c) = (builder.param(0).unwrap(), builder.param(1).unwrap(), builder.param(2).unwrap(), builder.param(3).unwrap());
+        // the pointer arrives in the first argument register and is not used after the call
+        let result = builder.call_ptr(ptr, &[a, b, c], Type::i64());
+        builder.ret(result);
+
+        compiler.jit().unwrap();
+        let f = compiler.get_typed::<fn(*const u8, i64, i64, i64) -> i64>("f").unwrap();
+        assert_eq!(f.call(add3 as *const u8, 1, 2, 3), 123);
+    }
+
+    #[test]
+    fn call_ptr_with_stack_and_float_arguments() {
+        let mut compiler = Compiler::new().unwrap();
+        let func = compiler.add_func("f", &[Type::ptr(), Type::i64()], Type::f64()).unwrap();
+        let builder = func.builder();
+        let (ptr, a) = (builder.param(0).unwrap(), builder.param(1).unwrap());
+        let x = builder.const_f64(2.0);
+        let y = builder.const_f64(0.5);
+        let args = [a.clone(), x, a.clone(), y, a.clone(), a.clone(), a.clone(), a.clone(), a];
+        let result = builder.call_ptr(ptr, &args, Type::f64());
+        builder.ret(result);
+
+        compiler.jit().unwrap();
+        let f = compiler.get_typed::<fn(*const u8, i64) -> f64>("f").unwrap();
+        assert_eq!(f.call(mix as *const u8, 3), 41.5);
+    }
 
-            Instr::RetVoid => {}
+    #[test]
+    fn params_are_read_from_registers_and_from_the_stack() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &vec![Type::i64(); 10], Type::i64()).unwrap().builder();
+        let mut sum = builder.const_i64(0);
+        for index in 0..10 {
+            let weight = builder.const_i64(index as i64 + 1);
+            let weighted = builder.mul(builder.param(index).unwrap(), weight);
+            sum = builder.add(sum, weighted);
         }
+        builder.ret(sum);
+
+        compiler.jit().unwrap();
+        let f = compiler.get_typed::<fn(i64, i64, i64, i64, i64, i64, i64, i64, i64, i64) -> i64>("f").unwrap();
+        assert_eq!(f.call(1, 2, 3, 4, 5, 6, 7, 8, 9, 10), 385);
+    }
+
+    #[test]
+    fn float_and_int_params_are_mixed() {
+        let mut compiler = Compiler::new().unwrap();
+        let mut args = vec![Type::f64(); 9];
+        args.extend([Type::i64(), Type::f32(), Type::i64()]);
+        let builder = compiler.add_func("f", &args, Type::f64()).unwrap().builder();
+        let mut sum = builder.const_f64(0.0);
+        for index in 0..9 {
+            sum = builder.add(sum, builder.param(index).unwrap());
+        }
+        let ints = builder.sub(builder.param(9).unwrap(), builder.param(11).unwrap());
+        let ints = builder.sitofp(ints, Type::f64()).unwrap();
+        let single = builder.fpext(builder.param(10).unwrap(), Type::f64()).unwrap();
+        let sum = builder.add(sum, ints);
+        let sum = builder.add(sum, single);
+        builder.ret(sum);
+
+        compiler.jit().unwrap();
+        let f = compiler.get_typed::<fn(f64, f64, f64, f64, f64, f64, f64, f64, f64, i64, f32, i64) -> f64>("f").unwrap();
+        assert_eq!(f.call(1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.5, 100, 0.25, 1), 144.75);
+    }
+
+    #[test]
+    fn recursive_calls() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("fact", &[Type::i64()], Type::i64()).unwrap().builder();
+        let n = builder.param(0).unwrap();
+        let (base, recurse) = (builder.create_block(), builder.create_block());
+        let one = builder.const_i64(1);
+        let done = builder.smaller_eq(n.clone(), one.clone());
+        builder.cond_br(done, base, &[], recurse, &[]).unwrap();
+
+        builder.set_current_block(base).unwrap();
+        builder.ret(one.clone());
+
+        builder.set_current_block(recurse).unwrap();
+        let previous = builder.sub(n.clone(), one);
+        let rest = builder.call("fact", &[previous]).unwrap();
+        let result = builder.mul(n, rest);
+        builder.ret(result);
+
+        compiler.jit().unwrap();
+        assert_eq!(compiler.get_typed::<fn(i64) -> i64>("fact").unwrap().call(10), 3628800);
+    }
+
+    #[test]
+    fn calls_with_stack_arguments_keep_the_live_values() {
+        let mut compiler = Compiler::new().unwrap();
+        // the callee is built after its caller
+        compiler.add_func("weigh", &vec![Type::i64(); 9], Type::i64()).unwrap();
+
+        let builder = compiler.add_func("caller", &[Type::i64(), Type::i64()], Type::i64()).unwrap().builder();
+        let (a, b) = (builder.param(0).unwrap(), builder.param(1).unwrap());
+        let args: Vec<_> = (0..9).map(|index| builder.const_i64(index + 1)).collect();
+        let first = builder.call("weigh", &args).unwrap();
+        let second = builder.call("weigh", &args).unwrap();
+        // the params and the result of the first call are still needed after the second call
+        let sum = builder.add(first, second);
+        let sum = builder.add(sum, a);
+        let sum = builder.add(sum, b);
+        builder.ret(sum);
+
+        let builder = compiler.get_func_mut_by_name("weigh").unwrap().builder();
+        let mut sum = builder.const_i64(0);
+        for index in 0..9 {
+            let weight = builder.const_i64(10i64.pow(index as u32));
+            let weighted = builder.mul(builder.param(index).unwrap(), weight);
+            sum = builder.add(sum, weighted);
+        }
+        builder.ret(sum);
+
+        compiler.jit().unwrap();
+        assert_eq!(compiler.get_typed::<fn(i64, i64) -> i64>("caller").unwrap().call(1000, 7), 2 * 987654321 + 1007);
+    }
+
+    #[test]
+    fn calls_between_functions_of_every_return_type() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("half", &[Type::f64()], Type::f64()).unwrap().builder();
+        let two = builder.const_f64(2.0);
+        let half = builder.div(builder.param(0).unwrap(), two);
+        builder.ret(half);
+
+        let builder = compiler.add_func("narrow", &[Type::i32()], Type::i8()).unwrap().builder();
+        let narrow = builder.trunc(builder.param(0).unwrap(), Type::i8()).unwrap();
+        builder.ret(narrow);
+
+        let builder = compiler.add_func("nothing", &[], Type::void()).unwrap().builder();
+        builder.ret_void();
+
+        let builder = compiler.add_func("caller", &[Type::f64(), Type::i32()], Type::f64()).unwrap().builder();
+        builder.call("nothing", &[]).unwrap();
+        let half = builder.call("half", &[builder.param(0).unwrap()]).unwrap();
+        let narrow = builder.call("narrow", &[builder.param(1).unwrap()]).unwrap();
+        let narrow = builder.sitofp(narrow, Type::f64()).unwrap();
+        let result = builder.add(half, narrow);
+        builder.ret(result);
+
+        compiler.jit().unwrap();
+        // 0x1ff is truncated to -1
+        assert_eq!(compiler.get_typed::<fn(f64, i32) -> f64>("caller").unwrap().call(5.0, 0x1ff), 1.5);
+    }
+
+    #[test]
+    fn signed_arithmetic_with_negative_values() {
+        let compiler = binary_func(Type::i64(), Type::i64(), Builder::sub);
+        assert_eq!(compiler.get_typed::<fn(i64, i64) -> i64>("f").unwrap().call(5, 12), -7);
+
+        let compiler = binary_func(Type::i64(), Type::i64(), Builder::mul);
+        assert_eq!(compiler.get_typed::<fn(i64, i64) -> i64>("f").unwrap().call(-7, 6), -42);
+
+        // division rounds toward zero and the remainder has the sign of the dividend
+        let compiler = binary_func(Type::i64(), Type::i64(), Builder::div);
+        let f = compiler.get_typed::<fn(i64, i64) -> i64>("f").unwrap();
+        assert_eq!(f.call(-7, 2), -3);
+        assert_eq!(f.call(7, -2), -3);
+        let compiler = binary_func(Type::i64(), Type::i64(), Builder::srem);
+        let f = compiler.get_typed::<fn(i64, i64) -> i64>("f").unwrap();
+        assert_eq!(f.call(-7, 2), -1);
+        assert_eq!(f.call(7, -2), 1);
+    }
+
+    #[test]
+    fn narrow_arithmetic_wraps_and_divides_the_narrow_values() {
+        let compiler = binary_func(Type::i32(), Type::i32(), Builder::mul);
+        assert_eq!(compiler.get_typed::<fn(i32, i32) -> i32>("f").unwrap().call(0x10000, 0x10001), 0x10000);
+
+        let compiler = binary_func(Type::i8(), Type::i8(), Builder::mul);
+        assert_eq!(compiler.get_typed::<fn(i8, i8) -> i8>("f").unwrap().call(100, 3), 44);
+
+        let compiler = binary_func(Type::i16(), Type::i16(), Builder::sub);
+        assert_eq!(compiler.get_typed::<fn(i16, i16) -> i16>("f").unwrap().call(-32768, 1), 32767);
+
+        let compiler = binary_func(Type::i16(), Type::i16(), Builder::div);
+        assert_eq!(compiler.get_typed::<fn(i16, i16) -> i16>("f").unwrap().call(-300, 7), -42);
+
+        let compiler = binary_func(Type::i8(), Type::i8(), Builder::srem);
+        assert_eq!(compiler.get_typed::<fn(i8, i8) -> i8>("f").unwrap().call(-100, 3), -1);
+    }
+
+    #[test]
+    fn division_keeps_the_values_living_in_rax_and_rdx() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &vec![Type::i64(); 3], Type::i64()).unwrap().builder();
+        let (a, b, c) = (builder.param(0).unwrap(), builder.param(1).unwrap(), builder.param(2).unwrap());
+        // with system v the third param arrives in rdx, which the division overwrites
+        let quotient = builder.div(a.clone(), b.clone());
+        let remainder = builder.srem(a.clone(), b);
+        let sum = builder.add(quotient, remainder);
+        let sum = builder.add(sum, c);
+        let sum = builder.add(sum, a);
+        builder.ret(sum);
+
+        compiler.jit().unwrap();
+        assert_eq!(compiler.get_typed::<fn(i64, i64, i64) -> i64>("f").unwrap().call(47, 10, 1000), 4 + 7 + 1000 + 47);
+    }
+
+    #[test]
+    fn signed_comparisons_give_bools() {
+        let cases: [(BinaryOp, [bool; 3]); 6] = [
+            (Builder::eq, [false, true, false]),
+            (Builder::diff, [true, false, true]),
+            (Builder::smaller, [true, false, false]),
+            (Builder::smaller_eq, [true, true, false]),
+            (Builder::larger, [false, false, true]),
+            (Builder::larger_eq, [false, true, true]),
+        ];
+        for (op, expected) in cases {
+            let compiler = binary_func(Type::i64(), Type::bool(), op);
+            let f = compiler.get_typed::<fn(i64, i64) -> bool>("f").unwrap();
+            assert_eq!([f.call(-5, 3), f.call(-5, -5), f.call(3, -5)], expected);
+
+            let compiler = binary_func(Type::i8(), Type::bool(), op);
+            let f = compiler.get_typed::<fn(i8, i8) -> bool>("f").unwrap();
+            assert_eq!([f.call(-128, 127), f.call(-1, -1), f.call(127, -128)], expected);
+        }
+    }
+
+    #[test]
+    fn a_comparison_used_by_a_branch_and_by_a_value() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[Type::i32(), Type::i32()], Type::i32()).unwrap().builder();
+        let (a, b) = (builder.param(0).unwrap(), builder.param(1).unwrap());
+        let (left, right) = (builder.create_block(), builder.create_block());
+        let less = builder.smaller(a.clone(), b.clone());
+        builder.cond_br(less.clone(), left, &[], right, &[]).unwrap();
+
+        builder.set_current_block(left).unwrap();
+        builder.ret(a);
+
+        // the bool is still needed after the branch
+        builder.set_current_block(right).unwrap();
+        let less = builder.zext(less, Type::i32()).unwrap();
+        let result = builder.add(b, less);
+        builder.ret(result);
+
+        compiler.jit().unwrap();
+        let f = compiler.get_typed::<fn(i32, i32) -> i32>("f").unwrap();
+        assert_eq!(f.call(-3, 4), -3);
+        assert_eq!(f.call(9, 4), 4);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn unsigned_division_of_values_with_the_top_bit_set() {
+        let compiler = binary_func(Type::i64(), Type::i64(), Builder::udiv);
+        assert_eq!(compiler.get_typed::<fn(u64, u64) -> u64>("f").unwrap().call(u64::MAX, 3), u64::MAX / 3);
+        let compiler = binary_func(Type::i64(), Type::i64(), Builder::urem);
+        assert_eq!(compiler.get_typed::<fn(u64, u64) -> u64>("f").unwrap().call(u64::MAX, 10), 5);
+
+        let compiler = binary_func(Type::i8(), Type::i8(), Builder::udiv);
+        assert_eq!(compiler.get_typed::<fn(u8, u8) -> u8>("f").unwrap().call(200, 3), 66);
+        let compiler = binary_func(Type::i16(), Type::i16(), Builder::urem);
+        assert_eq!(compiler.get_typed::<fn(u16, u16) -> u16>("f").unwrap().call(65535, 1000), 535);
+        let compiler = binary_func(Type::i32(), Type::i32(), Builder::udiv);
+        assert_eq!(compiler.get_typed::<fn(u32, u32) -> u32>("f").unwrap().call(0x80000000, 2), 0x40000000);
+    }
+
+    #[test]
+    fn unsigned_comparisons_give_bools() {
+        let cases: [(BinaryOp, [bool; 3]); 4] = [
+            (Builder::ult, [true, false, false]),
+            (Builder::ule, [true, true, false]),
+            (Builder::ugt, [false, false, true]),
+            (Builder::uge, [false, true, true]),
+        ];
+        for (op, expected) in cases {
+            let compiler = binary_func(Type::i64(), Type::bool(), op);
+            let f = compiler.get_typed::<fn(u64, u64) -> bool>("f").unwrap();
+            assert_eq!([f.call(1, u64::MAX), f.call(7, 7), f.call(u64::MAX, 1)], expected);
+
+            let compiler = binary_func(Type::i8(), Type::bool(), op);
+            let f = compiler.get_typed::<fn(u8, u8) -> bool>("f").unwrap();
+            assert_eq!([f.call(1, 255), f.call(128, 128), f.call(255, 1)], expected);
+        }
+    }
+
+    #[test]
+    fn logical_and_arithmetic_right_shifts() {
+        let compiler = binary_func(Type::i64(), Type::i64(), Builder::lshr);
+        assert_eq!(compiler.get_typed::<fn(i64, i64) -> i64>("f").unwrap().call(-16, 60), 15);
+        let compiler = binary_func(Type::i64(), Type::i64(), Builder::ashr);
+        assert_eq!(compiler.get_typed::<fn(i64, i64) -> i64>("f").unwrap().call(-16, 2), -4);
+
+        // the narrow shifts only see the bits of their width
+        let compiler = binary_func(Type::i8(), Type::i8(), Builder::lshr);
+        assert_eq!(compiler.get_typed::<fn(i8, i8) -> i8>("f").unwrap().call(-128, 7), 1);
+        let compiler = binary_func(Type::i16(), Type::i16(), Builder::ashr);
+        assert_eq!(compiler.get_typed::<fn(i16, i16) -> i16>("f").unwrap().call(-32768, 15), -1);
+        let compiler = binary_func(Type::i32(), Type::i32(), Builder::lshr);
+        assert_eq!(compiler.get_typed::<fn(i32, i32) -> i32>("f").unwrap().call(-1, 28), 15);
+    }
+
+    #[test]
+    fn bitwise_operations() {
+        let compiler = binary_func(Type::i64(), Type::i64(), Builder::and);
+        assert_eq!(compiler.get_typed::<fn(i64, i64) -> i64>("f").unwrap().call(0b1100, 0b1010), 0b1000);
+        let compiler = binary_func(Type::i64(), Type::i64(), Builder::or);
+        assert_eq!(compiler.get_typed::<fn(i64, i64) -> i64>("f").unwrap().call(0b1100, 0b1010), 0b1110);
+        let compiler = binary_func(Type::i32(), Type::i32(), Builder::xor);
+        assert_eq!(compiler.get_typed::<fn(i32, i32) -> i32>("f").unwrap().call(-1, 0xff), -256);
+        let compiler = binary_func(Type::i8(), Type::i8(), |builder, value, _| builder.not(value));
+        assert_eq!(compiler.get_typed::<fn(i8, i8) -> i8>("f").unwrap().call(0x0f, 0), -16);
+    }
+
+    #[test]
+    fn shifts_and_rotates_by_a_value() {
+        let compiler = binary_func(Type::i8(), Type::i8(), Builder::shl);
+        assert_eq!(compiler.get_typed::<fn(i8, i8) -> i8>("f").unwrap().call(0x41, 1), -126);
+        let compiler = binary_func(Type::i8(), Type::i8(), Builder::rotl);
+        assert_eq!(compiler.get_typed::<fn(u8, u8) -> u8>("f").unwrap().call(0x81, 1), 0x03);
+        let compiler = binary_func(Type::i16(), Type::i16(), Builder::rotr);
+        assert_eq!(compiler.get_typed::<fn(u16, u16) -> u16>("f").unwrap().call(0x0001, 4), 0x1000);
+        let compiler = binary_func(Type::i32(), Type::i32(), Builder::rotl);
+        assert_eq!(compiler.get_typed::<fn(u32, u32) -> u32>("f").unwrap().call(0x80000001, 4), 0x18);
+        let compiler = binary_func(Type::i64(), Type::i64(), Builder::rotr);
+        assert_eq!(compiler.get_typed::<fn(u64, u64) -> u64>("f").unwrap().call(1, 1), 1 << 63);
+    }
+
+    #[test]
+    fn shifts_and_rotates_by_an_immediate() {
+        let compiler = binary_func(Type::i64(), Type::i64(), |builder, value, _| builder.shl_imm(value, 60));
+        assert_eq!(compiler.get_typed::<fn(i64, i64) -> i64>("f").unwrap().call(0x1f, 0), -0x1000000000000000);
+        let compiler = binary_func(Type::i32(), Type::i32(), |builder, value, _| builder.lshr_imm(value, 31));
+        assert_eq!(compiler.get_typed::<fn(i32, i32) -> i32>("f").unwrap().call(-1, 0), 1);
+        let compiler = binary_func(Type::i16(), Type::i16(), |builder, value, _| builder.ashr_imm(value, 8));
+        assert_eq!(compiler.get_typed::<fn(i16, i16) -> i16>("f").unwrap().call(-512, 0), -2);
+        let compiler = binary_func(Type::i8(), Type::i8(), |builder, value, _| builder.rotl_imm(value, 4));
+        assert_eq!(compiler.get_typed::<fn(u8, u8) -> u8>("f").unwrap().call(0x12, 0), 0x21);
+        let compiler = binary_func(Type::i64(), Type::i64(), |builder, value, _| builder.rotr_imm(value, 8));
+        assert_eq!(compiler.get_typed::<fn(u64, u64) -> u64>("f").unwrap().call(0xab, 0), 0xab << 56);
+    }
+
+    #[test]
+    fn a_shift_keeps_the_value_living_in_rcx() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &vec![Type::i64(); 4], Type::i64()).unwrap().builder();
+        let (a, b, d) = (builder.param(0).unwrap(), builder.param(1).unwrap(), builder.param(3).unwrap());
+        // with system v the fourth param arrives in rcx, which holds the shift amount
+        let shifted = builder.shl(a, b);
+        let result = builder.add(shifted, d);
+        builder.ret(result);
+
+        compiler.jit().unwrap();
+        assert_eq!(compiler.get_typed::<fn(i64, i64, i64, i64) -> i64>("f").unwrap().call(3, 4, 0, 5), 53);
+    }
+
+    #[test]
+    fn integer_width_casts() {
+        let compiler = binary_func(Type::i8(), Type::i64(), |builder, value, _| builder.sext(value, Type::i64()).unwrap());
+        assert_eq!(compiler.get_typed::<fn(i8, i8) -> i64>("f").unwrap().call(-5, 0), -5);
+        let compiler = binary_func(Type::i8(), Type::i64(), |builder, value, _| builder.zext(value, Type::i64()).unwrap());
+        assert_eq!(compiler.get_typed::<fn(i8, i8) -> i64>("f").unwrap().call(-1, 0), 255);
+        let compiler = binary_func(Type::i32(), Type::i64(), |builder, value, _| builder.zext(value, Type::i64()).unwrap());
+        assert_eq!(compiler.get_typed::<fn(i32, i32) -> i64>("f").unwrap().call(-1, 0), 0xffffffff);
+        let compiler = binary_func(Type::i64(), Type::i16(), |builder, value, _| builder.trunc(value, Type::i16()).unwrap());
+        assert_eq!(compiler.get_typed::<fn(i64, i64) -> i16>("f").unwrap().call(0x12345678, 0), 0x5678);
+
+        // a bool extends to 0 or 1 with zext and to 0 or -1 with sext
+        let compiler = binary_func(Type::i64(), Type::i32(), |builder, left, right| {
+            let less = builder.smaller(left, right);
+            builder.sext(less, Type::i32()).unwrap()
+        });
+        let f = compiler.get_typed::<fn(i64, i64) -> i32>("f").unwrap();
+        assert_eq!([f.call(1, 2), f.call(2, 1)], [-1, 0]);
+        let compiler = binary_func(Type::i64(), Type::i16(), |builder, left, right| {
+            let less = builder.smaller(left, right);
+            builder.zext(less, Type::i16()).unwrap()
+        });
+        let f = compiler.get_typed::<fn(i64, i64) -> i16>("f").unwrap();
+        assert_eq!([f.call(1, 2), f.call(2, 1)], [1, 0]);
+    }
+
+    #[test]
+    fn int_and_float_casts() {
+        let compiler = binary_func(Type::i32(), Type::f64(), |builder, value, _| builder.sitofp(value, Type::f64()).unwrap());
+        assert_eq!(compiler.get_typed::<fn(i32, i32) -> f64>("f").unwrap().call(-3, 0), -3.0);
+        let compiler = binary_func(Type::i64(), Type::f64(), |builder, value, _| builder.uitofp(value, Type::f64()).unwrap());
+        assert_eq!(compiler.get_typed::<fn(u64, u64) -> f64>("f").unwrap().call(u64::MAX, 0), u64::MAX as f64);
+        let compiler = binary_func(Type::i8(), Type::f32(), |builder, value, _| builder.uitofp(value, Type::f32()).unwrap());
+        assert_eq!(compiler.get_typed::<fn(u8, u8) -> f32>("f").unwrap().call(200, 0), 200.0);
+
+        let compiler = binary_func(Type::f64(), Type::i32(), |builder, value, _| builder.fptosi(value, Type::i32()).unwrap());
+        assert_eq!(compiler.get_typed::<fn(f64, f64) -> i32>("f").unwrap().call(-2.9, 0.0), -2);
+        let compiler = binary_func(Type::f64(), Type::i64(), |builder, value, _| builder.fptoui(value, Type::i64()).unwrap());
+        assert_eq!(compiler.get_typed::<fn(f64, f64) -> u64>("f").unwrap().call(1e19, 0.0), 10_000_000_000_000_000_000);
+        let compiler = binary_func(Type::f32(), Type::i8(), |builder, value, _| builder.fptoui(value, Type::i8()).unwrap());
+        assert_eq!(compiler.get_typed::<fn(f32, f32) -> u8>("f").unwrap().call(250.7, 0.0), 250);
+    }
+
+    #[test]
+    fn float_width_bit_and_pointer_casts() {
+        let compiler = binary_func(Type::f32(), Type::f64(), |builder, value, _| builder.fpext(value, Type::f64()).unwrap());
+        assert_eq!(compiler.get_typed::<fn(f32, f32) -> f64>("f").unwrap().call(0.1, 0.0), 0.1f32 as f64);
+        let compiler = binary_func(Type::f64(), Type::f32(), |builder, value, _| builder.fptrunc(value, Type::f32()).unwrap());
+        assert_eq!(compiler.get_typed::<fn(f64, f64) -> f32>("f").unwrap().call(1.0 / 3.0, 0.0), 1.0 / 3.0);
+
+        let compiler = binary_func(Type::f64(), Type::i64(), |builder, value, _| builder.bitcast(value, Type::i64()).unwrap());
+        assert_eq!(compiler.get_typed::<fn(f64, f64) -> u64>("f").unwrap().call(1.0, 0.0), 0x3ff0000000000000);
+        let compiler = binary_func(Type::i32(), Type::f32(), |builder, value, _| builder.bitcast(value, Type::f32()).unwrap());
+        assert_eq!(compiler.get_typed::<fn(u32, u32) -> f32>("f").unwrap().call(0x40490fdb, 0), std::f32::consts::PI);
+
+        // a narrow integer is zero extended to the size of the pointer
+        let compiler = binary_func(Type::i32(), Type::ptr(), |builder, value, _| builder.inttoptr(value, Type::ptr()).unwrap());
+        assert_eq!(compiler.get_typed::<fn(i32, i32) -> *const u8>("f").unwrap().call(-1, 0), 0xffffffff as *const u8);
+        let compiler = binary_func(Type::ptr(), Type::i16(), |builder, value, _| builder.ptrtoint(value, Type::i16()).unwrap());
+        assert_eq!(compiler.get_typed::<fn(*const u8, *const u8) -> i16>("f").unwrap().call(0x12345 as *const u8, std::ptr::null()), 0x2345);
+    }
+
+    #[test]
+    fn float_arithmetic_of_both_widths() {
+        let cases: [(BinaryOp, f64); 4] = [(Builder::add, 7.5), (Builder::sub, 2.5), (Builder::mul, 12.5), (Builder::div, 2.0)];
+        for (op, expected) in cases {
+            let compiler = binary_func(Type::f64(), Type::f64(), op);
+            assert_eq!(compiler.get_typed::<fn(f64, f64) -> f64>("f").unwrap().call(5.0, 2.5), expected);
+            let compiler = binary_func(Type::f32(), Type::f32(), op);
+            assert_eq!(compiler.get_typed::<fn(f32, f32) -> f32>("f").unwrap().call(5.0, 2.5), expected as f32);
+        }
+    }
+
+    #[test]
+    fn float_neg_and_abs_only_touch_the_sign() {
+        let compiler = binary_func(Type::f64(), Type::f64(), |builder, value, _| builder.neg(value));
+        let f = compiler.get_typed::<fn(f64, f64) -> f64>("f").unwrap();
+        assert_eq!(f.call(1.5, 0.0), -1.5);
+        assert!(f.call(0.0, 0.0).is_sign_negative());
+
+        let compiler = binary_func(Type::f32(), Type::f32(), |builder, value, _| builder.abs(value).unwrap());
+        let f = compiler.get_typed::<fn(f32, f32) -> f32>("f").unwrap();
+        assert_eq!(f.call(-1.5, 0.0), 1.5);
+        assert!(f.call(-0.0, 0.0).is_sign_positive());
+        assert_eq!(f.call(f32::NEG_INFINITY, 0.0), f32::INFINITY);
+    }
+
+    #[test]
+    fn float_comparisons_with_nan() {
+        // only diff and unordered are true when a float is nan
+        let cases: [(BinaryOp, [bool; 4]); 8] = [
+            (Builder::eq, [false, true, false, false]),
+            (Builder::diff, [true, false, true, true]),
+            (Builder::smaller, [true, false, false, false]),
+            (Builder::smaller_eq, [true, true, false, false]),
+            (Builder::larger, [false, false, true, false]),
+            (Builder::larger_eq, [false, true, true, false]),
+            (|builder, left, right| builder.ordered(left, right).unwrap(), [true, true, true, false]),
+            (|builder, left, right| builder.unordered(left, right).unwrap(), [false, false, false, true]),
+        ];
+        for (op, expected) in cases {
+            let compiler = binary_func(Type::f64(), Type::bool(), op);
+            let f = compiler.get_typed::<fn(f64, f64) -> bool>("f").unwrap();
+            assert_eq!([f.call(-1.0, 2.0), f.call(2.0, 2.0), f.call(2.0, -1.0), f.call(2.0, f64::NAN)], expected);
+
+            let compiler = binary_func(Type::f32(), Type::bool(), op);
+            let f = compiler.get_typed::<fn(f32, f32) -> bool>("f").unwrap();
+            assert_eq!([f.call(-1.0, 2.0), f.call(2.0, 2.0), f.call(2.0, -1.0), f.call(f32::NAN, 2.0)], expected);
+        }
+    }
+
+    #[test]
+    fn branches_on_float_comparisons_with_nan() {
+        let cases: [(BinaryOp, [i64; 3]); 3] = [(Builder::eq, [1, 0, 0]), (Builder::diff, [0, 1, 1]), (Builder::larger_eq, [1, 0, 0])];
+        for (op, expected) in cases {
+            let mut compiler = Compiler::new().unwrap();
+            let builder = compiler.add_func("f", &[Type::f64(), Type::f64()], Type::i64()).unwrap().builder();
+            let (left, right) = (builder.param(0).unwrap(), builder.param(1).unwrap());
+            let (yes, no) = (builder.create_block(), builder.create_block());
+            let condition = op(builder, left, right);
+            builder.cond_br(condition, yes, &[], no, &[]).unwrap();
+            builder.set_current_block(yes).unwrap();
+            let one = builder.const_i64(1);
+            builder.ret(one);
+            builder.set_current_block(no).unwrap();
+            let zero = builder.const_i64(0);
+            builder.ret(zero);
+
+            compiler.jit().unwrap();
+            let f = compiler.get_typed::<fn(f64, f64) -> i64>("f").unwrap();
+            assert_eq!([f.call(3.0, 3.0), f.call(3.0, 4.0), f.call(f64::NAN, f64::NAN)], expected);
+        }
+    }
+
+    #[test]
+    fn stores_only_write_the_bytes_of_their_size() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[Type::ptr(), Type::i64()], Type::void()).unwrap().builder();
+        let (ptr, value) = (builder.param(0).unwrap(), builder.param(1).unwrap());
+        for (ty, offset) in [(Type::i8(), 1), (Type::i16(), 2), (Type::i32(), 4)] {
+            let narrow = builder.trunc(value.clone(), ty).unwrap();
+            builder.store(ptr.clone(), narrow, offset).unwrap();
+        }
+        builder.store(ptr, value, 8).unwrap();
+        builder.ret_void();
+
+        compiler.jit().unwrap();
+        let mut bytes = [0xAAu8; 24];
+        compiler.get_typed::<fn(*mut u8, i64)>("f").unwrap().call(bytes.as_mut_ptr(), 0x1122334455667788);
+        let mut expected = [0xAAu8; 24];
+        expected[1] = 0x88;
+        expected[2..4].copy_from_slice(&[0x88, 0x77]);
+        expected[4..8].copy_from_slice(&[0x88, 0x77, 0x66, 0x55]);
+        expected[8..16].copy_from_slice(&0x1122334455667788u64.to_le_bytes());
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn loads_extend_with_the_sign_or_with_zeros() {
+        type Load = fn(&mut Builder, Value) -> Value;
+        let cases: [(Load, i64); 6] = [
+            (|builder, ptr| builder.load_sext(Type::i8(), Type::i64(), ptr, -8).unwrap(), -1),
+            (|builder, ptr| builder.load_zext(Type::i8(), Type::i64(), ptr, -8).unwrap(), 0xff),
+            (|builder, ptr| builder.load_sext(Type::i16(), Type::i64(), ptr, -2).unwrap(), -0x7f01),
+            (|builder, ptr| builder.load_zext(Type::i16(), Type::i64(), ptr, -2).unwrap(), 0x80ff),
+            (|builder, ptr| builder.load_sext(Type::i32(), Type::i64(), ptr, 4).unwrap(), -0x7fffff00),
+            (|builder, ptr| builder.load_zext(Type::i32(), Type::i64(), ptr, 4).unwrap(), 0x80000100),
+        ];
+        let mut bytes = [0u8; 16];
+        bytes[0] = 0xff;
+        bytes[6..8].copy_from_slice(&[0xff, 0x80]);
+        bytes[12..16].copy_from_slice(&0x80000100u32.to_le_bytes());
+        for (load, expected) in cases {
+            let mut compiler = Compiler::new().unwrap();
+            let builder = compiler.add_func("f", &[Type::ptr()], Type::i64()).unwrap().builder();
+            let ptr = builder.param(0).unwrap();
+            let result = load(builder, ptr);
+            builder.ret(result);
+
+            compiler.jit().unwrap();
+            // the pointer is in the middle of the bytes so the offsets can be negative
+            let f = compiler.get_typed::<fn(*const u8) -> i64>("f").unwrap();
+            assert_eq!(f.call(bytes[8..].as_ptr()), expected);
+        }
+    }
+
+    #[test]
+    fn float_loads_and_stores() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[Type::ptr()], Type::f32()).unwrap().builder();
+        let ptr = builder.param(0).unwrap();
+        let single = builder.load(Type::f32(), ptr.clone(), 4).unwrap();
+        let double = builder.load(Type::f64(), ptr.clone(), 8).unwrap();
+        let wide = builder.fpext(single.clone(), Type::f64()).unwrap();
+        let sum = builder.add(wide, double);
+        builder.store(ptr.clone(), sum, 16).unwrap();
+        builder.store(ptr, single.clone(), 0).unwrap();
+        builder.ret(single);
+
+        compiler.jit().unwrap();
+        let mut words = [0u64; 3];
+        words[0] = (1.5f32.to_bits() as u64) << 32;
+        words[1] = 0.25f64.to_bits();
+        assert_eq!(compiler.get_typed::<fn(*mut u64) -> f32>("f").unwrap().call(words.as_mut_ptr()), 1.5);
+        assert_eq!(words, [(1.5f32.to_bits() as u64) << 32 | 1.5f32.to_bits() as u64, 0.25f64.to_bits(), 1.75f64.to_bits()]);
+    }
+
+    #[test]
+    fn stack_slots_keep_their_alignment_whatever_registers_are_saved() {
+        for live_count in 0..4 {
+            let mut compiler = Compiler::new().unwrap();
+            compiler.register_extern("sum", sum_aligned_bytes as *const u8, &[Type::ptr(), Type::i64()], Type::i64()).unwrap();
+            let builder = compiler.add_func("f", &[Type::i64()], Type::i64()).unwrap().builder();
+            let byte = builder.param(0).unwrap();
+            // values live across the calls are kept in callee-saved registers, which moves the slots down
+            let live: Vec<Value> = (0..live_count).map(|value| builder.const_i64(value)).collect();
+            let small = builder.stack_slot(1, 1).unwrap();
+            let slot = builder.stack_slot(16, 16).unwrap();
+            let byte = builder.trunc(byte, Type::i8()).unwrap();
+            for offset in 0..16 {
+                builder.store(slot.clone(), byte.clone(), offset).unwrap();
+            }
+            builder.store(small, byte, 0).unwrap();
+            let len = builder.const_i64(16);
+            let mut sum = builder.call("sum", &[slot, len]).unwrap();
+            for value in live {
+                sum = builder.add(sum, value);
+            }
+            builder.ret(sum);
+
+            compiler.jit().unwrap();
+            let f = compiler.get_typed::<fn(i64) -> i64>("f").unwrap();
+            assert_eq!(f.call(3), 48 + live_count * (live_count - 1) / 2);
+        }
+    }
+
+    #[test]
+    fn allocas_give_separate_aligned_memory_until_the_return() {
+        let mut compiler = Compiler::new().unwrap();
+        compiler.register_extern("sum", sum_aligned_bytes as *const u8, &[Type::ptr(), Type::i64()], Type::i64()).unwrap();
+        let builder = compiler.add_func("f", &[Type::i64(), Type::i64()], Type::i64()).unwrap().builder();
+        let (first_len, second_len) = (builder.param(0).unwrap(), builder.param(1).unwrap());
+        let kept = builder.const_i64(1000);
+        let first = builder.alloca(first_len.clone()).unwrap();
+        let ones = builder.const_i64(0x0101010101010101);
+        builder.store(first.clone(), ones.clone(), 0).unwrap();
+        builder.store(first.clone(), ones, 8).unwrap();
+        // a size that is not a multiple of 16 is rounded up
+        let second = builder.alloca(second_len.clone()).unwrap();
+        let five = builder.const_i8(5);
+        builder.store(second.clone(), five, 0).unwrap();
+
+        let first_sum = builder.call("sum", &[first, first_len]).unwrap();
+        let second_sum = builder.call("sum", &[second, second_len]).unwrap();
+        let sum = builder.add(first_sum, second_sum);
+        let sum = builder.add(sum, kept);
+        builder.ret(sum);
+
+        compiler.jit().unwrap();
+        let f = compiler.get_typed::<fn(i64, i64) -> i64>("f").unwrap();
+        // the stack pointer is given back, calling again many times does not run out of stack
+        for _ in 0..100_000 {
+            assert_eq!(f.call(16, 1), 16 + 5 + 1000);
+        }
+    }
+
+    #[test]
+    fn the_size_of_an_alloca_is_unsigned() {
+        let mut compiler = Compiler::new().unwrap();
+        compiler.register_extern("fill", fill_ones as *const u8, &[Type::ptr(), Type::i64()], Type::void()).unwrap();
+        compiler.register_extern("sum", sum_aligned_bytes as *const u8, &[Type::ptr(), Type::i64()], Type::i64()).unwrap();
+        let builder = compiler.add_func("f", &[Type::i8()], Type::i64()).unwrap().builder();
+        let len = builder.param(0).unwrap();
+        let memory = builder.alloca(len.clone()).unwrap();
+        let len = builder.zext(len, Type::i64()).unwrap();
+        builder.call("fill", &[memory.clone(), len.clone()]).unwrap();
+        let sum = builder.call("sum", &[memory, len]).unwrap();
+        builder.ret(sum);
+
+        compiler.jit().unwrap();
+        let f = compiler.get_typed::<fn(i8) -> i64>("f").unwrap();
+        // -56 is 200 bytes
+        assert_eq!(f.call(-56), 200);
+        assert_eq!(f.call(-1), 255);
+        assert_eq!(f.call(3), 3);
+    }
+
+    #[test]
+    fn frames_and_allocas_bigger_than_a_page() {
+        let mut compiler = Compiler::new().unwrap();
+        compiler.register_extern("fill", fill_ones as *const u8, &[Type::ptr(), Type::i64()], Type::void()).unwrap();
+        compiler.register_extern("sum", sum_aligned_bytes as *const u8, &[Type::ptr(), Type::i64()], Type::i64()).unwrap();
+        let builder = compiler.add_func("f", &[Type::i64()], Type::i64()).unwrap().builder();
+        let slot = builder.stack_slot(64 * 1024, 16).unwrap();
+        let slot_len = builder.const_i64(64 * 1024);
+        let len = builder.param(0).unwrap();
+        let memory = builder.alloca(len.clone()).unwrap();
+        for (ptr, len) in [(slot.clone(), slot_len.clone()), (memory.clone(), len.clone())] {
+            builder.call("fill", &[ptr, len]).unwrap();
+        }
+        let slot_sum = builder.call("sum", &[slot, slot_len]).unwrap();
+        let sum = builder.call("sum", &[memory, len]).unwrap();
+        let sum = builder.add(sum, slot_sum);
+        builder.ret(sum);
+
+        compiler.jit().unwrap();
+        let f = compiler.get_typed::<fn(i64) -> i64>("f").unwrap();
+        assert_eq!(f.call(256 * 1024 + 5), 320 * 1024 + 5);
+        assert_eq!(f.call(0), 64 * 1024);
+    }
+
+    #[test]
+    fn a_loop_swapping_its_block_params() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &vec![Type::i64(); 3], Type::i64()).unwrap().builder();
+        let header = builder.create_block_with_params(&vec![Type::i64(); 3]).unwrap();
+        let body = builder.create_block();
+        let exit = builder.create_block_with_params(&[Type::i64(), Type::i64()]).unwrap();
+        let params = builder.params().clone();
+        builder.br(header, &params).unwrap();
+
+        builder.set_current_block(header).unwrap();
+        let (a, b, count) = (builder.block_param(header, 0).unwrap(), builder.block_param(header, 1).unwrap(), builder.block_param(header, 2).unwrap());
+        let zero = builder.const_i64(0);
+        let done = builder.eq(count.clone(), zero);
+        builder.cond_br(done, exit, &[a.clone(), b.clone()], body, &[]).unwrap();
+
+        // the two values trade their registers on every edge
+        builder.set_current_block(body).unwrap();
+        let one = builder.const_i64(1);
+        let count = builder.sub(count, one);
+        builder.br(header, &[b, a, count]).unwrap();
+
+        builder.set_current_block(exit).unwrap();
+        let ten = builder.const_i64(10);
+        let high = builder.mul(builder.block_param(exit, 0).unwrap(), ten);
+        let result = builder.add(high, builder.block_param(exit, 1).unwrap());
+        builder.ret(result);
+
+        compiler.jit().unwrap();
+        let f = compiler.get_typed::<fn(i64, i64, i64) -> i64>("f").unwrap();
+        assert_eq!(f.call(1, 2, 3), 21);
+        assert_eq!(f.call(1, 2, 4), 12);
+    }
+
+    #[test]
+    fn a_loop_rotating_more_block_params_than_registers() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[Type::i64(), Type::f64(), Type::f64()], Type::i64()).unwrap().builder();
+        let mut param_types = vec![Type::i64(); 13];
+        param_types.extend([Type::f64(), Type::f64()]);
+        let header = builder.create_block_with_params(&param_types).unwrap();
+        let body = builder.create_block();
+        let exit = builder.create_block();
+        let mut args: Vec<Value> = (0..12).map(|value| builder.const_i64(value)).collect();
+        args.extend(builder.params().clone());
+        builder.br(header, &args).unwrap();
+
+        builder.set_current_block(header).unwrap();
+        let values = builder.block_params(header).unwrap().clone();
+        let zero = builder.const_i64(0);
+        let done = builder.eq(values[12].clone(), zero);
+        builder.cond_br(done, exit, &[], body, &[]).unwrap();
+
+        // the twelve integers rotate by one and the floats are swapped, some of them live on the stack
+        builder.set_current_block(body).unwrap();
+        let one = builder.const_i64(1);
+        let count = builder.sub(values[12].clone(), one);
+        let mut args: Vec<Value> = (1..=12).map(|index| values[index % 12].clone()).collect();
+        args.extend([count, values[14].clone(), values[13].clone()]);
+        builder.br(header, &args).unwrap();
+
+        builder.set_current_block(exit).unwrap();
+        let thirteen = builder.const_i64(13);
+        let mut result = builder.const_i64(0);
+        for value in values[..12].iter().rev() {
+            result = builder.mul(result, thirteen.clone());
+            result = builder.add(result, value.clone());
+        }
+        let ten = builder.const_f64(10.0);
+        let floats = builder.mul(values[13].clone(), ten);
+        let floats = builder.add(floats, values[14].clone());
+        let floats = builder.fptosi(floats, Type::i64()).unwrap();
+        let result = builder.mul(result, thirteen);
+        let result = builder.add(result, floats);
+        builder.ret(result);
+
+        compiler.jit().unwrap();
+        let f = compiler.get_typed::<fn(i64, f64, f64) -> i64>("f").unwrap();
+        for count in [0, 1, 5, 12, 13, 30] {
+            let rotated: i64 = (0..12).rev().fold(0, |sum, index| sum * 13 + (index + count) % 12);
+            let floats = if count % 2 == 0 { 12 } else { 21 };
+            assert_eq!(f.call(count, 1.0, 2.0), rotated * 13 + floats);
+        }
+    }
+}
@@ -1,4 +1,5 @@
 pub(crate) mod gen;
 pub(crate) mod x86_64_encoder;
 pub(crate) mod x86_64_allocator;
-pub(crate) mod x86_64_caller;
\ No newline at end of file
+pub(crate) mod x86_64_caller;
+pub(crate) mod x86_64_moves;pub(crate) mod x86_64_frame;
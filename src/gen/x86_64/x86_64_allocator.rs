@@ -1,7 +1,12 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use crate::gen::x86_64::x86_64_encoder::X86_64Encoder;
+use crate::gen::x86_64::x86_64_moves::emit_move;
+use crate::lang::builder::Builder;
+use crate::lang::lang_type::Type;
 use crate::lang::value::Value;
 
-#[derive(PartialEq, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub(crate) enum X86Register {
     // return value for int / ptr
     RAX,
@@ -41,93 +46,324 @@ pub(crate) enum X86Register {
     XMM15,
 }
 
+// where a value lives for its whole life
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub(crate) enum Location {
+    Register(X86Register),
+    // offset from the base pointer
+    Stack(i32),
+}
+
+#[derive(Clone, Copy)]
+enum Assignment {
+    Register(X86Register),
+    Slot(usize),
+}
+
+// the range of positions in which a value is live, uses are at even positions and definitions right after
+#[derive(Clone, Copy)]
+struct Interval {
+    value_id: usize,
+    is_float: bool,
+    start: usize,
+    end: usize,
+    // the value stays live after a call, a volatile register would have to be saved around it
+    crosses_call: bool,
+}
+
+// linear scan register allocator: every value gets a register or a stack slot for its whole life,
+// a register is given back as soon as the value it holds is dead
 pub(crate) struct X86_64Allocator {
-    free_registers: Vec<X86Register>,
-    allocated_registers: HashMap<usize, X86Register>,
+    assignments: HashMap<usize, Assignment>,
+    intervals: Vec<Interval>,
+    slot_count: usize,
+    // bytes between the base pointer and the first spill slot
+    frame_base: i32,
 }
 
 impl X86_64Allocator {
-    pub(crate) fn new() -> Self {
-        let registers = vec![X86Register::RCX,
-                             X86Register::RDX, X86Register::RBX, X86Register::RDI, X86Register::RSI, X86Register::R8,
-                             X86Register::R9, X86Register::R10, X86Register::R11,
-                             X86Register::R12, X86Register::R13, X86Register::R14, X86Register::R15,
-                             X86Register::XMM1, X86Register::XMM2, X86Register::XMM3,
-                             X86Register::XMM4, X86Register::XMM5, X86Register::XMM6, X86Register::XMM7,
-                             X86Register::XMM8, X86Register::XMM9, X86Register::XMM10, X86Register::XMM11,
-                             X86Register::XMM12, X86Register::XMM13, X86Register::XMM14,
-                             X86Register::XMM15, ];
+    pub(crate) fn new(builder: &Builder, volatiles: &[X86Register]) -> Self {
+        let intervals = Self::build_intervals(builder);
+
+        let mut allocator = X86_64Allocator { assignments: HashMap::new(), intervals, slot_count: 0, frame_base: 0 };
+        allocator.linear_scan(volatiles);
+        allocator
+    }
 
-        X86_64Allocator { free_registers: registers, allocated_registers: HashMap::new() }
+    // position of the use of the operands of an instruction, its value is defined at the next position
+    pub(crate) fn instr_position(instr_index: usize) -> usize {
+        2 * (instr_index + 1)
     }
 
-    pub(crate) fn obtain_register_for_value(&mut self, value: Value) -> X86Register {
+    fn build_intervals(builder: &Builder) -> Vec<Interval> {
+        let blocks = builder.blocks();
 
-        // the case where a register is available to put the value in
-        if self.is_value_allocated(value.clone()) {
-            let id = value.get_id();
-            return *self.allocated_registers.get(&id).unwrap();
+        // linear position of the first instruction of every block
+        let mut block_starts = vec![];
+        let mut instr_count = 0;
+        for block in blocks {
+            block_starts.push(Self::instr_position(instr_count));
+            instr_count += block.instructions().len();
         }
+        let block_end = |block: usize| Self::instr_position(block_starts[block] / 2 - 1 + blocks[block].instructions().len());
 
-        let allocation = self.allocate_register(value);
-        if allocation.is_some() {
-            return allocation.unwrap();
+        // liveness of every block, iterated until nothing changes since loops feed values back to earlier blocks
+        let mut uses: Vec<HashSet<usize>> = vec![];
+        let mut defs: Vec<HashSet<usize>> = vec![];
+        let mut successors: Vec<Vec<usize>> = vec![];
+        for block in blocks {
+            let mut block_uses = HashSet::new();
+            let mut block_defs: HashSet<usize> = block.params().iter().map(|param| param.get_id()).collect();
+            let mut block_successors = vec![];
+            for instr in block.instructions() {
+                for value in instr.used_values() {
+                    if !block_defs.contains(&value.get_id()) {
+                        block_uses.insert(value.get_id());
+                    }
+                }
+                if let Some(value) = instr.gen_value() {
+                    block_defs.insert(value.get_id());
+                }
+                block_successors.extend(instr.successors().iter().map(|block| block.get_id()));
+            }
+            uses.push(block_uses);
+            defs.push(block_defs);
+            successors.push(block_successors);
         }
 
+        let mut live_in: Vec<HashSet<usize>> = vec![HashSet::new(); blocks.len()];
+        let mut live_out: Vec<HashSet<usize>> = vec![HashSet::new(); blocks.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in (0..blocks.len()).rev() {
+                let out: HashSet<usize> = successors[block].iter().flat_map(|succ| live_in[*succ].iter().copied()).collect();
+                let mut input: HashSet<usize> = out.difference(&defs[block]).copied().collect();
+                input.extend(uses[block].iter().copied());
 
-        // the case where no register is available
-        return X86Register::RAX;
-    }
+                if input != live_in[block] || out != live_out[block] {
+                    changed = true;
+                    live_in[block] = input;
+                    live_out[block] = out;
+                }
+            }
+        }
 
-    pub(crate) fn allocate_register(&mut self, value: Value) -> Option<X86Register> {
+        // a single range per value covering its definition, its uses and every block it is live through
+        let mut ranges: HashMap<usize, Interval> = HashMap::new();
+        let mut extend = |value: &Value, position: usize| {
+            if value.get_type() == Type::void() {
+                return;
+            }
+            let interval = ranges.entry(value.get_id()).or_insert(Interval {
+                value_id: value.get_id(),
+                is_float: value.get_type().is_float(),
+                start: position,
+                end: position,
+                crosses_call: false,
+            });
+            interval.start = interval.start.min(position);
+            interval.end = interval.end.max(position);
+        };
+
+        let mut value_types: HashMap<usize, Value> = HashMap::new();
+        for param in builder.params() {
+            extend(param, 0);
+            value_types.insert(param.get_id(), param.clone());
+        }
 
-        //for floating values, we allocate a xmm register
-        if value.get_type().is_float() {
-            if let Some(idx) = self.free_registers.iter().position(|reg| reg.is_xmm()) {
-                return Some(self.setup_allocate_register(value, self.free_registers[idx]));
+        // the parameters of a block are defined right before its first instruction
+        for (block, block_start) in blocks.iter().zip(&block_starts) {
+            for param in block.params() {
+                extend(param, block_start - 1);
+                value_types.insert(param.get_id(), param.clone());
             }
-        } else {
-            if let Some(idx) = self.free_registers.iter().position(|reg| !reg.is_xmm()) {
-                return Some(self.setup_allocate_register(value, self.free_registers[idx]));
+        }
+
+        let mut call_positions = vec![];
+        let mut instr_index = 0;
+        for block in blocks {
+            for instr in block.instructions() {
+                let position = Self::instr_position(instr_index);
+                for value in instr.used_values() {
+                    extend(value, position);
+                }
+                // the parameters are written by the branches to their block, their register has to be kept for them there
+                for (target, _) in instr.branch_args() {
+                    for param in blocks[target.get_id()].params() {
+                        extend(param, position);
+                    }
+                }
+                if let Some(value) = instr.gen_value() {
+                    extend(value, position + 1);
+                    value_types.insert(value.get_id(), value.clone());
+                }
+                if instr.is_call() {
+                    call_positions.push(position);
+                }
+                instr_index += 1;
             }
         }
 
-        None
+        for (block, block_start) in block_starts.iter().enumerate() {
+            for value_id in &live_in[block] {
+                if let Some(value) = value_types.get(value_id) {
+                    extend(value, *block_start);
+                }
+            }
+            for value_id in &live_out[block] {
+                if let Some(value) = value_types.get(value_id) {
+                    extend(value, block_end(block));
+                }
+            }
+        }
+
+        let mut intervals: Vec<Interval> = ranges.into_values().collect();
+        for interval in intervals.iter_mut() {
+            interval.crosses_call = call_positions.iter().any(|call| interval.start < *call && interval.end > *call);
+        }
+        intervals.sort_by_key(|interval| (interval.start, interval.value_id));
+        intervals
     }
 
+    fn linear_scan(&mut self, volatiles: &[X86Register]) {
+        let mut free_registers = ALLOCATABLE_REGISTERS.to_vec();
+        let mut active: Vec<Interval> = vec![];
+
+        for index in 0..self.intervals.len() {
+            let current = self.intervals[index];
+
+            // the registers of the values that died before this one starts are free again
+            active.retain(|interval| {
+                if interval.end < current.start {
+                    if let Some(Assignment::Register(reg)) = self.assignments.get(&interval.value_id) {
+                        free_registers.push(*reg);
+                    }
+                    false
+                } else {
+                    true
+                }
+            });
+
+            // a value live across a call prefers a register the callee has to preserve
+            let candidates = free_registers.iter().filter(|reg| reg.is_xmm() == current.is_float);
+            let chosen = if current.crosses_call {
+                candidates.min_by_key(|reg| volatiles.contains(reg))
+            } else {
+                candidates.min_by_key(|reg| !volatiles.contains(reg))
+            }.copied();
+
+            if let Some(reg) = chosen {
+                free_registers.retain(|free| *free != reg);
+                self.assignments.insert(current.value_id, Assignment::Register(reg));
+                active.push(current);
+                continue;
+            }
+
+            // no register left, the value that lives the longest goes to the stack
+            let spill_candidate = active.iter()
+                .filter(|interval| interval.is_float == current.is_float)
+                .max_by_key(|interval| interval.end)
+                .copied();
 
-    pub(crate) fn free_register_from_value(&mut self, value: Value) -> bool {
-        let id = value.get_id();
-        if self.allocated_registers.contains_key(&id) {
-            let register_to_free = self.allocated_registers.get(&id).unwrap();
-            self.free_registers.push(register_to_free.clone());
-            return true;
+            match spill_candidate {
+                Some(spilled) if spilled.end > current.end => {
+                    let reg = self.assignments[&spilled.value_id];
+                    let slot = self.new_slot();
+                    self.assignments.insert(spilled.value_id, Assignment::Slot(slot));
+                    self.assignments.insert(current.value_id, reg);
+                    active.retain(|interval| interval.value_id != spilled.value_id);
+                    active.push(current);
+                }
+                _ => {
+                    let slot = self.new_slot();
+                    self.assignments.insert(current.value_id, Assignment::Slot(slot));
+                }
+            }
         }
+    }
 
-        false
+    fn new_slot(&mut self) -> usize {
+        self.slot_count += 1;
+        self.slot_count - 1
     }
 
-    pub(crate) fn is_value_allocated(&mut self, value: Value) -> bool {
-        for register_key in self.allocated_registers.keys() {
-            if *register_key == value.get_id() {
-                return true;
-            }
+    pub(crate) fn location(&self, value: &Value) -> Location {
+        match self.assignments.get(&value.get_id()) {
+            Some(Assignment::Register(reg)) => Location::Register(*reg),
+            Some(Assignment::Slot(slot)) => Location::Stack(-self.frame_base - 8 * (*slot as i32 + 1)),
+            None => panic!("value {} is never defined", value.get_id()),
         }
+    }
 
-        return false;
+    // bytes of stack needed for the spill slots
+    pub(crate) fn spill_size(&self) -> usize {
+        8 * self.slot_count
     }
 
-    pub(crate) fn is_register_allocated(&mut self, reg: X86Register) -> bool {
-        return self.allocated_registers.values().any(|&val| val == reg);
+    pub(crate) fn set_frame_base(&mut self, frame_base: i32) {
+        self.frame_base = frame_base;
     }
 
-    fn setup_allocate_register(&mut self, value: Value, reg: X86Register) -> X86Register {
-        self.free_registers.retain(|&current| current != reg);
-        self.allocated_registers.insert(value.get_id(), reg);
-        reg
+    // every register handed out to a value
+    pub(crate) fn used_registers(&self) -> Vec<X86Register> {
+        let mut registers: Vec<X86Register> = self.assignments.values().filter_map(|assignment| match assignment {
+            Assignment::Register(reg) => Some(*reg),
+            Assignment::Slot(_) => None,
+        }).collect();
+        registers.sort_by_key(|reg| (reg.is_xmm(), reg.encode()));
+        registers.dedup();
+        registers
+    }
+
+    // the registers holding a value that is still needed once the call at this position returns
+    pub(crate) fn registers_live_across(&self, position: usize) -> Vec<X86Register> {
+        let mut registers: Vec<X86Register> = self.intervals.iter()
+            .filter(|interval| interval.start < position && interval.end > position)
+            .filter_map(|interval| match self.assignments.get(&interval.value_id) {
+                Some(Assignment::Register(reg)) => Some(*reg),
+                _ => None,
+            })
+            .collect();
+        registers.sort_by_key(|reg| (reg.is_xmm(), reg.encode()));
+        registers
+    }
+
+    // the register holding the value, a value living on the stack is loaded in the scratch register
+    pub(crate) fn read_value(&self, encoder: &mut X86_64Encoder, value: &Value, scratch: X86Register) -> X86Register {
+        match self.location(value) {
+            Location::Register(reg) => reg,
+            Location::Stack(offset) => {
+                emit_move(encoder, Location::Stack(offset), Location::Register(scratch));
+                scratch
+            }
+        }
+    }
+
+    // the register a value has to be computed in, write_value has to be called once it is computed
+    pub(crate) fn value_target(&self, value: &Value, scratch: X86Register) -> X86Register {
+        match self.location(value) {
+            Location::Register(reg) => reg,
+            Location::Stack(_) => scratch,
+        }
+    }
+
+    // store a value computed in the register returned by value_target
+    pub(crate) fn write_value(&self, encoder: &mut X86_64Encoder, value: &Value, reg: X86Register) {
+        emit_move(encoder, Location::Register(reg), self.location(value));
     }
 }
 
+// the registers the allocator can hand out, rax, rcx, rdx and r11 as well as xmm0, xmm14 and xmm15
+// are kept as scratch registers for the instructions that need fixed or temporary registers
+const ALLOCATABLE_REGISTERS: [X86Register; 23] = [
+    X86Register::RBX, X86Register::RSI, X86Register::RDI, X86Register::R8, X86Register::R9, X86Register::R10,
+    X86Register::R12, X86Register::R13, X86Register::R14, X86Register::R15,
+    X86Register::XMM1, X86Register::XMM2, X86Register::XMM3, X86Register::XMM4, X86Register::XMM5,
+    X86Register::XMM6, X86Register::XMM7, X86Register::XMM8, X86Register::XMM9, X86Register::XMM10,
+    X86Register::XMM11, X86Register::XMM12, X86Register::XMM13,
+];
+
 impl X86Register {
     pub(crate) fn is_xmm(&self) -> bool {
         vec![X86Register::XMM0, X86Register::XMM1, X86Register::XMM2, X86Register::XMM3,
@@ -145,8 +381,8 @@ impl X86Register {
             X86Register::RBX => { 3 }
             X86Register::RSP => { 4 }
             X86Register::RBP => { 5 }
-            X86Register::RDI => { 6 }
-            X86Register::RSI => { 7 }
+            X86Register::RDI => { 7 }
+            X86Register::RSI => { 6 }
             X86Register::R8 => { 8 }
             X86Register::R9 => { 9 }
             X86Register::R10 => { 10 }
@@ -173,4 +409,110 @@ impl X86Register {
             X86Register::XMM15 => { 15 }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::gen::x86_64::x86_64_caller::X86_64Caller;
+
+    // count values all live at once, summed at the end, and their sum
+    fn add_pressure_func(compiler: &mut Compiler, name: &str, count: usize, value_type: Type) {
+        let builder = compiler.add_func(name, &[], value_type.clone()).unwrap().builder();
+        let values: Vec<Value> = (1..=count).map(|value| match value_type.is_float() {
+            true => builder.const_f64(value as f64),
+            false => builder.const_i64(value as i64),
+        }).collect();
+        let mut sum = values[0].clone();
+        for value in &values[1..] {
+            sum = builder.add(sum, value.clone());
+        }
+        builder.ret(sum);
+    }
+
+    fn allocate(compiler: &mut Compiler, name: &str) -> X86_64Allocator {
+        let builder = compiler.get_func_mut_by_name(name).unwrap().builder();
+        X86_64Allocator::new(builder, X86_64Caller::new().volatiles())
+    }
+
+    // two values live at the same time never share a register
+    fn assert_no_shared_register(allocator: &X86_64Allocator) {
+        for (index, first) in allocator.intervals.iter().enumerate() {
+            for second in &allocator.intervals[index + 1..] {
+                let overlap = first.start <= second.end && second.start <= first.end;
+                if let (Some(Assignment::Register(a)), Some(Assignment::Register(b))) =
+                    (allocator.assignments.get(&first.value_id), allocator.assignments.get(&second.value_id)) {
+                    assert!(!overlap || a != b, "values {} and {} share {:?}", first.value_id, second.value_id, a);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dead_values_give_their_register_back() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[Type::i64()], Type::i64()).unwrap().builder();
+        let mut value = builder.param(0).unwrap();
+        for _ in 0..50 {
+            let one = builder.const_i64(1);
+            value = builder.add(value, one);
+        }
+        builder.ret(value);
+
+        // a hundred values, never more than two live at once
+        let allocator = allocate(&mut compiler, "f");
+        assert_eq!(allocator.spill_size(), 0);
+        assert_no_shared_register(&allocator);
+    }
+
+    #[test]
+    fn values_that_do_not_fit_in_registers_are_spilled() {
+        let mut compiler = Compiler::new().unwrap();
+        add_pressure_func(&mut compiler, "ints", 30, Type::i64());
+        add_pressure_func(&mut compiler, "floats", 30, Type::f64());
+
+        for name in ["ints", "floats"] {
+            let allocator = allocate(&mut compiler, name);
+            assert!(allocator.spill_size() > 0);
+            assert_no_shared_register(&allocator);
+        }
+
+        compiler.jit().unwrap();
+        assert_eq!(compiler.get_typed::<fn() -> i64>("ints").unwrap().call(), 465);
+        assert_eq!(compiler.get_typed::<fn() -> f64>("floats").unwrap().call(), 465.0);
+    }
+
+    #[test]
+    fn the_value_living_the_longest_is_spilled() {
+        let mut compiler = Compiler::new().unwrap();
+        add_pressure_func(&mut compiler, "f", 12, Type::i64());
+        let allocator = allocate(&mut compiler, "f");
+
+        // the constants are used in the order they are defined, the last ones wait the longest
+        let spilled: Vec<usize> = allocator.intervals.iter()
+            .filter(|interval| matches!(allocator.assignments[&interval.value_id], Assignment::Slot(_)))
+            .map(|interval| interval.value_id)
+            .collect();
+        assert_eq!(spilled, vec![10, 11]);
+    }
+
+    #[test]
+    fn values_live_across_a_call_prefer_preserved_registers() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("g", &[], Type::void()).unwrap().builder();
+        builder.ret_void();
+
+        let builder = compiler.add_func("f", &[Type::i64()], Type::i64()).unwrap().builder();
+        let kept = builder.param(0).unwrap();
+        builder.call("g", &[]).unwrap();
+        builder.ret(kept.clone());
+
+        let volatiles = X86_64Caller::new().volatiles().clone();
+        let allocator = allocate(&mut compiler, "f");
+        match allocator.location(&kept) {
+            Location::Register(reg) => assert!(!volatiles.contains(&reg)),
+            Location::Stack(_) => panic!("the parameter should be in a register"),
+        }
+    }
+}
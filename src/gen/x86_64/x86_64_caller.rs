@@ -1,11 +1,34 @@
-use crate::gen::x86_64::x86_64_allocator::{X86_64Allocator, X86Register};
+use crate::gen::x86_64::x86_64_allocator::{Location, X86_64Allocator, X86Register};
 use crate::gen::x86_64::x86_64_encoder::X86_64Encoder;
+use crate::gen::x86_64::x86_64_moves::emit_move;
+use crate::lang::lang_type::Type;
 use crate::lang::value::Value;
 
+// where an argument is found when a function is entered
+#[derive(Clone, Copy)]
+pub(crate) enum ArgLocation {
+    Register(X86Register),
+    // offset from the stack pointer at the entry of the function, the return address is at 0
+    Stack(i32),
+}
+
+// distance between the stack pointer and a 16 bytes boundary once the frame of a function is set up
+const FRAME_STACK_OFFSET: i32 = 0;
+
+// everything generate_call pushed that has to be popped after the call
+pub(crate) struct CallCleanup {
+    stack_bytes: i32,
+    saved: Vec<X86Register>,
+}
+
 pub(crate) struct X86_64Caller {
     args_register: Vec<X86Register>,
     args_xmm: Vec<X86Register>,
     volatiles: Vec<X86Register>,
+    // win64 gives every argument a position in both register lists, sysv counts int and float arguments apart
+    positional_args: bool,
+    // space the caller reserves above the return address before the stack arguments
+    shadow_space: i32,
 }
 
 impl X86_64Caller {
@@ -14,11 +37,13 @@ impl X86_64Caller {
         let mut xmms = vec![];
         let mut volatiles = vec![];
 
-        let os = if cfg!(target_os = "windows") {
+        if cfg!(target_os = "windows") {
             args = vec![X86Register::RCX, X86Register::RDX, X86Register::R8, X86Register::R9];
             xmms = vec![X86Register::XMM0, X86Register::XMM1, X86Register::XMM2, X86Register::XMM3];
             volatiles = vec![X86Register::RAX, X86Register::RCX, X86Register::RDX, X86Register::R8,
-                             X86Register::R9, X86Register::R10, X86Register::R11];
+                             X86Register::R9, X86Register::R10, X86Register::R11,
+                             X86Register::XMM0, X86Register::XMM1, X86Register::XMM2, X86Register::XMM3,
+                             X86Register::XMM4, X86Register::XMM5];
         } else { // we suppose that every other platform use the linux calling convention
             args = vec![X86Register::RDI, X86Register::RSI, X86Register::RDX, X86Register::RCX, X86Register::R8, X86Register::R9];
             xmms = vec![X86Register::XMM0, X86Register::XMM1, X86Register::XMM2, X86Register::XMM3,
@@ -32,38 +57,149 @@ impl X86_64Caller {
                              X86Register::XMM13, X86Register::XMM14, X86Register::XMM15];
         };
 
-        Self { args_register: args, args_xmm: xmms, volatiles }
+        let windows = cfg!(target_os = "windows");
+        Self { args_register: args, args_xmm: xmms, volatiles, positional_args: windows, shadow_space: if windows { 32 } else { 0 } }
     }
 
-    pub(crate) fn generate_call(&self, encoder: &mut X86_64Encoder, allocator: &mut X86_64Allocator, values: &Vec<Value>) -> Vec<X86Register> {
-        let mut pushed_register = vec![];
-
-        // to keep track of what argument we are working on at the moment
+    pub(crate) fn arg_locations(&self, args: &[Type]) -> Vec<ArgLocation> {
+        let mut locations = vec![];
         let mut arg_index = 0;
         let mut xmm_index = 0;
+        let mut stack_offset = 8 + self.shadow_space;
 
-        for value in values {
-            let reg = allocator.obtain_register_for_value(value.clone());
+        for arg in args {
+            let register = if arg.is_float() {
+                self.args_xmm.get(if self.positional_args { arg_index } else { xmm_index })
+            } else {
+                self.args_register.get(arg_index)
+            };
 
-            if reg.is_xmm() {
-                let reg_to_mov = self.args_xmm[xmm_index];
-                if allocator.is_register_allocated(reg_to_mov) && reg_to_mov != reg {
-                    encoder.push_reg(reg_to_mov);
-                    pushed_register.push(reg_to_mov);
+            match register {
+                Some(reg) => locations.push(ArgLocation::Register(*reg)),
+                None => {
+                    locations.push(ArgLocation::Stack(stack_offset));
+                    stack_offset += 8;
                 }
-                encoder.mov_xmm_to_xmm(reg, reg_to_mov);
+            }
+
+            if arg.is_float() && !self.positional_args {
                 xmm_index += 1;
             } else {
-                let reg_to_mov = self.args_register[arg_index];
-                if allocator.is_register_allocated(reg_to_mov) && reg_to_mov != reg {
-                    encoder.push_reg(reg_to_mov);
-                    pushed_register.push(reg_to_mov);
-                }
-                encoder.mov_reg_to_reg(reg, reg_to_mov);
                 arg_index += 1;
             }
         }
 
-        pushed_register
+        locations
+    }
+
+    pub(crate) fn volatiles(&self) -> &Vec<X86Register> {
+        &self.volatiles
     }
-}
\ No newline at end of file
+
+    // move the arguments where the callee expects them, finish_call undoes the rest once the callee returned.
+    // live_registers are the registers holding values still needed after the call, a pointer to call ends up in rax
+    pub(crate) fn generate_call(&self, encoder: &mut X86_64Encoder, allocator: &X86_64Allocator, values: &[Value],
+                                live_registers: &[X86Register], ptr_to_call: Option<&Value>) -> CallCleanup {
+        // the callee is free to overwrite the volatile registers, the ones holding a live value are saved around the call
+        let saved: Vec<X86Register> = live_registers.iter().filter(|reg| self.volatiles.contains(reg)).copied().collect();
+        for reg in &saved {
+            encoder.push_reg(*reg);
+        }
+
+        let types: Vec<Type> = values.iter().map(|value| value.get_type()).collect();
+        let locations = self.arg_locations(&types);
+        let stack_args: Vec<&Value> = values.iter().zip(&locations)
+            .filter(|(_, location)| matches!(location, ArgLocation::Stack(_)))
+            .map(|(value, _)| value)
+            .collect();
+
+        // the stack has to be 16 bytes aligned on the call
+        let stack_bytes = (stack_args.len() * 8) as i32 + self.shadow_space;
+        let pushed_bytes = (saved.len() * 8) as i32 + stack_bytes + FRAME_STACK_OFFSET;
+        let padding = if pushed_bytes % 16 != 0 { 8 } else { 0 };
+        if padding != 0 {
+            encoder.sub_rsp_imm(padding);
+        }
+
+        for value in stack_args.iter().rev() {
+            Self::push_value(encoder, allocator, value);
+        }
+
+        // the registers of the arguments can hold other arguments, every argument goes through the stack
+        // so that moving one argument in place never overwrites an argument that is not moved yet
+        let register_args: Vec<(&Value, X86Register)> = values.iter().zip(&locations)
+            .filter_map(|(value, location)| match location {
+                ArgLocation::Register(arg_reg) => Some((value, *arg_reg)),
+                ArgLocation::Stack(_) => None,
+            })
+            .collect();
+        for (value, _) in &register_args {
+            Self::push_value(encoder, allocator, value);
+        }
+
+        // the pointer can be in an argument register, it is read before the arguments are popped over it.
+        // rax is never an argument register and a live value it held is already saved
+        if let Some(ptr) = ptr_to_call {
+            emit_move(encoder, allocator.location(ptr), Location::Register(X86Register::RAX));
+        }
+        for (_, arg_reg) in register_args.iter().rev() {
+            encoder.pop_reg(*arg_reg);
+        }
+
+        if self.shadow_space != 0 {
+            encoder.sub_rsp_imm(self.shadow_space);
+        }
+
+        CallCleanup { stack_bytes: stack_bytes + padding, saved }
+    }
+
+    fn push_value(encoder: &mut X86_64Encoder, allocator: &X86_64Allocator, value: &Value) {
+        match allocator.location(value) {
+            Location::Register(reg) => encoder.push_reg(reg),
+            Location::Stack(offset) => encoder.push_mem_disp(X86Register::RBP, offset),
+        }
+    }
+
+    pub(crate) fn finish_call(&self, encoder: &mut X86_64Encoder, cleanup: CallCleanup) {
+        if cleanup.stack_bytes != 0 {
+            encoder.add_rsp_imm(cleanup.stack_bytes);
+        }
+
+        for reg in cleanup.saved.iter().rev() {
+            encoder.pop_reg(*reg);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn describe(locations: &[ArgLocation]) -> Vec<String> {
+        locations.iter().map(|location| match location {
+            ArgLocation::Register(reg) => format!("{:?}", reg),
+            ArgLocation::Stack(offset) => format!("rsp+{}", offset),
+        }).collect()
+    }
+
+    #[test]
+    #[cfg(not(target_os = "windows"))]
+    fn sysv_counts_int_and_float_arguments_apart() {
+        let mut args = vec![Type::i64(), Type::f64(), Type::i32(), Type::f32()];
+        args.extend(vec![Type::i64(); 5]);
+        args.push(Type::f64());
+        let locations = X86_64Caller::new().arg_locations(&args);
+        assert_eq!(describe(&locations), vec!["RDI", "XMM0", "RSI", "XMM1", "RDX", "RCX", "R8", "R9", "rsp+8", "XMM2"]);
+
+        let locations = X86_64Caller::new().arg_locations(&vec![Type::f64(); 10]);
+        assert_eq!(describe(&locations[7..]), vec!["XMM7", "rsp+8", "rsp+16"]);
+    }
+
+    #[test]
+    #[cfg(target_os = "windows")]
+    fn win64_gives_every_argument_a_position() {
+        let args = [Type::i64(), Type::f64(), Type::i32(), Type::f32(), Type::i64(), Type::f64()];
+        let locations = X86_64Caller::new().arg_locations(&args);
+        assert_eq!(describe(&locations), vec!["RCX", "XMM1", "R8", "XMM3", "rsp+40", "rsp+48"]);
+    }
+}
@@ -1,6 +1,42 @@
 use crate::gen::x86_64::x86_64_allocator::X86Register;
 use crate::misc::byte_writer::ByteWriter;
 
+// the conditions of setcc and jcc
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Condition {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Below,
+    BelowEqual,
+    Above,
+    AboveEqual,
+    Parity,
+    NotParity,
+}
+
+impl Condition {
+    fn code(&self) -> u8 {
+        match self {
+            Condition::Equal => 0x4,
+            Condition::NotEqual => 0x5,
+            Condition::Less => 0xC,
+            Condition::GreaterEqual => 0xD,
+            Condition::LessEqual => 0xE,
+            Condition::Greater => 0xF,
+            Condition::Below => 0x2,
+            Condition::AboveEqual => 0x3,
+            Condition::BelowEqual => 0x6,
+            Condition::Above => 0x7,
+            Condition::Parity => 0xA,
+            Condition::NotParity => 0xB,
+        }
+    }
+}
+
 pub(crate) struct X86_64Encoder {
     writer: ByteWriter,
 }
@@ -11,7 +47,7 @@ impl X86_64Encoder {
     }
 
     pub(crate) fn move_reg_i64(&mut self, dest: X86Register, value: i64) -> usize {
-        self.writer.write_u8(0x48); // REX prefix
+        self.writer.write_u8(0x48 | ((dest.encode() & 8) >> 3)); // REX prefix, with REX.B for r8 to r15
         self.writer.write_u8(0xB8 | (dest.encode() & 0x7)); //src register
         self.writer.write_i64(value)
     }
@@ -38,14 +74,24 @@ impl X86_64Encoder {
         self.writer.write_u8(modrm);
     }
 
+    // movq xmm, r64
     pub(crate) fn move_reg_to_xmm(&mut self, src: X86Register, dst: X86Register) {
         self.writer.write_u8(0x66);
-        self.writer.write_u8(0x48);
+        self.writer.write_u8(0x48 | ((dst.encode() & 8) >> 1) | ((src.encode() & 8) >> 3));
         self.writer.write_u8(0x0F);
         self.writer.write_u8(0x6E);
         self.writer.write_u8(0xC0 | (dst.encode() & 0x7) << 3 | (src.encode() & 0x7));
     }
 
+    // movq r64, xmm
+    pub(crate) fn move_xmm_to_reg(&mut self, src: X86Register, dst: X86Register) {
+        self.writer.write_u8(0x66);
+        self.writer.write_u8(0x48 | ((src.encode() & 8) >> 1) | ((dst.encode() & 8) >> 3));
+        self.writer.write_u8(0x0F);
+        self.writer.write_u8(0x7E);
+        self.writer.write_u8(0xC0 | (src.encode() & 0x7) << 3 | (dst.encode() & 0x7));
+    }
+
     pub(crate) fn mov_xmm_to_xmm(&mut self, src: X86Register, dst: X86Register) {
         if src == dst {
             return;
@@ -54,7 +100,7 @@ impl X86_64Encoder {
         let src_reg: u8 = src.encode();
         let dest_reg: u8 = dst.encode();
 
-        if src_reg >= X86Register::XMM8.encode() || dest_reg > X86Register::XMM8.encode() {
+        if src_reg >= X86Register::XMM8.encode() || dest_reg >= X86Register::XMM8.encode() {
             self.writer.write_u8(0x40 | ((dest_reg & 8) >> 1) | ((src_reg & 8) >> 3));
             self.writer.write_u8(0x0F);
             self.writer.write_u8(0x28);
@@ -66,85 +112,508 @@ impl X86_64Encoder {
         }
     }
 
-    //load
-    pub(crate) fn mov_mem_to_reg(&mut self, mem_reg: X86Register, dest_reg: X86Register) {
-        self.writer.write_u8(0x48);
+    // mov dest, [base + disp]
+    pub(crate) fn mov_mem_disp_to_reg(&mut self, base: X86Register, disp: i32, dest: X86Register) {
+        self.writer.write_u8(0x48 | ((dest.encode() & 8) >> 1) | ((base.encode() & 8) >> 3));
         self.writer.write_u8(0x8B);
-        self.writer.write_u8(0x00 | ((mem_reg.encode() & 0x07) << 3) | (dest_reg.encode() & 0x07));
+        self.write_mem_operand(dest, base, disp);
     }
 
-    //store
-    pub(crate) fn mov_reg_to_mem(&mut self, reg: X86Register, mem_reg: X86Register) {
-        self.writer.write_u8(0x48);
+    // mov [base + disp], src
+    pub(crate) fn mov_reg_to_mem_disp(&mut self, src: X86Register, base: X86Register, disp: i32) {
+        self.writer.write_u8(0x48 | ((src.encode() & 8) >> 1) | ((base.encode() & 8) >> 3));
         self.writer.write_u8(0x89);
-        self.writer.write_u8(0x00 | ((reg.encode() & 0x07) << 3) | (mem_reg.encode() & 0x07));
+        self.write_mem_operand(src, base, disp);
+    }
+
+    // push qword [base + disp]
+    pub(crate) fn push_mem_disp(&mut self, base: X86Register, disp: i32) {
+        if base.encode() >= 8 {
+            self.writer.write_u8(0x41);
+        }
+        self.writer.write_u8(0xFF);
+        self.write_mem_operand(X86Register::RSI, base, disp); // rsi encodes the /6 extension of push
+    }
+
+    // lea dest, [base + disp]
+    pub(crate) fn lea_mem_disp_to_reg(&mut self, base: X86Register, disp: i32, dest: X86Register) {
+        self.writer.write_u8(0x48 | ((dest.encode() & 8) >> 1) | ((base.encode() & 8) >> 3));
+        self.writer.write_u8(0x8D);
+        self.write_mem_operand(dest, base, disp);
+    }
+
+    // lea dest, [rip + disp32], the offset of the displacement is returned to be resolved once the code is placed
+    pub(crate) fn lea_rip_to_reg(&mut self, dest: X86Register) -> usize {
+        self.writer.write_u8(0x48 | ((dest.encode() & 8) >> 1));
+        self.writer.write_u8(0x8D);
+        self.writer.write_u8(((dest.encode() & 7) << 3) | 0x05);
+        self.writer.write_i32(0)
+    }
+
+    // movsd dest, [base + disp]
+    pub(crate) fn movsd_mem_disp_to_xmm(&mut self, base: X86Register, disp: i32, dest: X86Register) {
+        self.writer.write_u8(0xF2);
+        let rex = 0x40 | ((dest.encode() & 8) >> 1) | ((base.encode() & 8) >> 3);
+        if rex != 0x40 {
+            self.writer.write_u8(rex);
+        }
+        self.writer.write_u8(0x0F);
+        self.writer.write_u8(0x10);
+        self.write_mem_operand(dest, base, disp);
+    }
+
+    // movsd [base + disp], src
+    pub(crate) fn movsd_xmm_to_mem_disp(&mut self, src: X86Register, base: X86Register, disp: i32) {
+        self.writer.write_u8(0xF2);
+        let rex = 0x40 | ((src.encode() & 8) >> 1) | ((base.encode() & 8) >> 3);
+        if rex != 0x40 {
+            self.writer.write_u8(rex);
+        }
+        self.writer.write_u8(0x0F);
+        self.writer.write_u8(0x11);
+        self.write_mem_operand(src, base, disp);
+    }
+
+    // movdqu dest, [base + disp]
+    pub(crate) fn movdqu_mem_disp_to_xmm(&mut self, base: X86Register, disp: i32, dest: X86Register) {
+        self.writer.write_u8(0xF3);
+        let rex = 0x40 | ((dest.encode() & 8) >> 1) | ((base.encode() & 8) >> 3);
+        if rex != 0x40 {
+            self.writer.write_u8(rex);
+        }
+        self.writer.write_u8(0x0F);
+        self.writer.write_u8(0x6F);
+        self.write_mem_operand(dest, base, disp);
+    }
+
+    // movdqu [base + disp], src
+    pub(crate) fn movdqu_xmm_to_mem_disp(&mut self, src: X86Register, base: X86Register, disp: i32) {
+        self.writer.write_u8(0xF3);
+        let rex = 0x40 | ((src.encode() & 8) >> 1) | ((base.encode() & 8) >> 3);
+        if rex != 0x40 {
+            self.writer.write_u8(rex);
+        }
+        self.writer.write_u8(0x0F);
+        self.writer.write_u8(0x7F);
+        self.write_mem_operand(src, base, disp);
+    }
+
+    // modrm (and sib) for [base + disp32], the extension bits of both registers go in the rex prefix
+    fn write_mem_operand(&mut self, reg: X86Register, base: X86Register, disp: i32) {
+        self.writer.write_u8(0x80 | ((reg.encode() & 7) << 3) | (base.encode() & 7));
+
+        // rsp and r12 can only be used as a base through a sib byte
+        if base.encode() & 7 == 4 {
+            self.writer.write_u8(0x24);
+        }
+
+        self.writer.write_i32(disp);
+    }
+
+    // load of size bytes from [base + disp], extended to 64 bits with the sign or with zeros
+    pub(crate) fn mov_mem_to_reg(&mut self, base: X86Register, disp: i32, dest: X86Register, size: usize, zero_extend: bool) {
+        let rex = 0x40 | ((dest.encode() & 8) >> 1) | ((base.encode() & 8) >> 3);
+        match (size, zero_extend) {
+            (1, false) => self.write_bytes(&[rex | 0x08, 0x0F, 0xBE]),
+            (1, true) => self.write_bytes(&[rex | 0x08, 0x0F, 0xB6]),
+            (2, false) => self.write_bytes(&[rex | 0x08, 0x0F, 0xBF]),
+            (2, true) => self.write_bytes(&[rex | 0x08, 0x0F, 0xB7]),
+            (4, false) => self.write_bytes(&[rex | 0x08, 0x63]),
+            // a 32 bits load clears the upper half of the register
+            (4, true) => {
+                if rex != 0x40 {
+                    self.writer.write_u8(rex);
+                }
+                self.writer.write_u8(0x8B);
+            }
+            _ => self.write_bytes(&[rex | 0x08, 0x8B]),
+        }
+        self.write_mem_operand(dest, base, disp);
+    }
+
+    // store of the low size bytes of src to [base + disp]
+    pub(crate) fn mov_reg_to_mem(&mut self, src: X86Register, base: X86Register, disp: i32, size: usize) {
+        if size == 2 {
+            self.writer.write_u8(0x66);
+        }
+
+        let mut rex = 0x40 | ((src.encode() & 8) >> 1) | ((base.encode() & 8) >> 3);
+        if size == 8 {
+            rex |= 0x08;
+        }
+        // without a rex prefix the low bytes of rsp, rbp, rsi and rdi would be ah, ch, dh and bh
+        if rex != 0x40 || (size == 1 && src.encode() >= 4) {
+            self.writer.write_u8(rex);
+        }
+
+        self.writer.write_u8(if size == 1 { 0x88 } else { 0x89 });
+        self.write_mem_operand(src, base, disp);
+    }
+
+    // movss dest, [base + disp]
+    pub(crate) fn movss_mem_disp_to_xmm(&mut self, base: X86Register, disp: i32, dest: X86Register) {
+        self.writer.write_u8(0xF3);
+        let rex = 0x40 | ((dest.encode() & 8) >> 1) | ((base.encode() & 8) >> 3);
+        if rex != 0x40 {
+            self.writer.write_u8(rex);
+        }
+        self.writer.write_u8(0x0F);
+        self.writer.write_u8(0x10);
+        self.write_mem_operand(dest, base, disp);
+    }
+
+    // movss [base + disp], src
+    pub(crate) fn movss_xmm_to_mem_disp(&mut self, src: X86Register, base: X86Register, disp: i32) {
+        self.writer.write_u8(0xF3);
+        let rex = 0x40 | ((src.encode() & 8) >> 1) | ((base.encode() & 8) >> 3);
+        if rex != 0x40 {
+            self.writer.write_u8(rex);
+        }
+        self.writer.write_u8(0x0F);
+        self.writer.write_u8(0x11);
+        self.write_mem_operand(src, base, disp);
     }
 
     pub(crate) fn add_reg_reg(&mut self, left: X86Register, right: X86Register) {
+        self.writer.write_u8(0x48 | ((right.encode() & 8) >> 1) | ((left.encode() & 8) >> 3)); // REX.W, REX.R and REX.B
         self.writer.write_u8(0x01);
         let mut modrm: u8 = 0;
         modrm |= 3 << 6; // set operation to 11 (register-to-register)
-        modrm |= right.encode() << 3; // set destination register
-        modrm |= left.encode(); // set source register
+        modrm |= (right.encode() & 7) << 3; // set destination register
+        modrm |= left.encode() & 7; // set source register
         self.writer.write_u8(modrm);
     }
 
-    pub(crate) fn add_xmm_xmm(&mut self, left: X86Register, right: X86Register) {
-        let mut rex: u8 = 0x40;
-        if left.encode() >= 8 { rex |= 0x04; };
-        if right.encode() >= 8 { rex |= 0x01; };
+    // cvtsi2sd or cvtsi2ss dest, src, from a 64 bits integer
+    pub(crate) fn int_to_float(&mut self, src: X86Register, dest: X86Register, double: bool) {
+        self.write_sse(Self::float_prefix(double), 0x2A, dest, src, true);
+    }
+
+    // cvttsd2si or cvttss2si dest, src, to a 64 bits integer rounded toward zero
+    pub(crate) fn float_to_int(&mut self, src: X86Register, dest: X86Register, double: bool) {
+        self.write_sse(Self::float_prefix(double), 0x2C, dest, src, true);
+    }
+
+    // cvtss2sd dest, src
+    pub(crate) fn float_to_double(&mut self, src: X86Register, dest: X86Register) {
+        self.write_sse(Some(0xF3), 0x5A, dest, src, false);
+    }
+
+    // cvtsd2ss dest, src
+    pub(crate) fn double_to_float(&mut self, src: X86Register, dest: X86Register) {
+        self.write_sse(Some(0xF2), 0x5A, dest, src, false);
+    }
+
+    // addsd or addss left, right
+    pub(crate) fn add_float(&mut self, left: X86Register, right: X86Register, double: bool) {
+        self.write_sse(Self::float_prefix(double), 0x58, left, right, false);
+    }
+
+    // subsd or subss left, right
+    pub(crate) fn sub_float(&mut self, left: X86Register, right: X86Register, double: bool) {
+        self.write_sse(Self::float_prefix(double), 0x5C, left, right, false);
+    }
+
+    // mulsd or mulss left, right
+    pub(crate) fn mul_float(&mut self, left: X86Register, right: X86Register, double: bool) {
+        self.write_sse(Self::float_prefix(double), 0x59, left, right, false);
+    }
+
+    // divsd or divss left, right
+    pub(crate) fn div_float(&mut self, left: X86Register, right: X86Register, double: bool) {
+        self.write_sse(Self::float_prefix(double), 0x5E, left, right, false);
+    }
+
+    // xorps left, right, a bitwise xor of the whole registers
+    pub(crate) fn xor_float(&mut self, left: X86Register, right: X86Register) {
+        self.write_sse(None, 0x57, left, right, false);
+    }
+
+    // andps left, right, a bitwise and of the whole registers
+    pub(crate) fn and_float(&mut self, left: X86Register, right: X86Register) {
+        self.write_sse(None, 0x54, left, right, false);
+    }
+
+    // ucomisd or ucomiss left, right, sets the flags like an unsigned comparison
+    pub(crate) fn ucomi(&mut self, left: X86Register, right: X86Register, double: bool) {
+        self.write_sse(if double { Some(0x66) } else { None }, 0x2E, left, right, false);
+    }
+
+    // the scalar double instructions have the f2 prefix, the scalar single ones have f3
+    fn float_prefix(double: bool) -> Option<u8> {
+        if double { Some(0xF2) } else { Some(0xF3) }
+    }
+
+    // prefix, rex, 0f, opcode and modrm of the register to register sse instructions
+    fn write_sse(&mut self, prefix: Option<u8>, opcode: u8, reg: X86Register, rm: X86Register, wide: bool) {
+        if let Some(prefix) = prefix {
+            self.writer.write_u8(prefix);
+        }
+
+        let mut rex = 0x40 | ((reg.encode() & 8) >> 1) | ((rm.encode() & 8) >> 3);
+        if wide {
+            rex |= 0x08;
+        }
+        if rex != 0x40 {
+            self.writer.write_u8(rex);
+        }
 
-        self.writer.write_u8(rex);
-        self.writer.write_u8(0x66);
         self.writer.write_u8(0x0F);
-        self.writer.write_u8(0x58);
-        self.writer.write_u8(0xC0 | ((left.encode() & 0x07) << 3) | (right.encode() & 0x07));
+        self.writer.write_u8(opcode);
+        self.writer.write_u8(0xC0 | ((reg.encode() & 7) << 3) | (rm.encode() & 7));
     }
 
     pub(crate) fn sub_reg_reg(&mut self, left: X86Register, right: X86Register) {
+        self.writer.write_u8(0x48 | ((right.encode() & 8) >> 1) | ((left.encode() & 8) >> 3)); // REX.W, REX.R and REX.B
         self.writer.write_u8(0x29);
         let mut modrm: u8 = 0;
         modrm |= 3 << 6; // set operation to 11 (register-to-register)
-        modrm |= right.encode() << 3; // set destination register
-        modrm |= left.encode(); // set source register
+        modrm |= (right.encode() & 7) << 3; // set source register
+        modrm |= left.encode() & 7; // set destination register
         self.writer.write_u8(modrm);
     }
 
     pub(crate) fn mul_reg_reg(&mut self, left: X86Register, right: X86Register) {
+        // imul takes its destination in the reg field, unlike add and sub
+        self.writer.write_u8(0x48 | ((left.encode() & 8) >> 1) | ((right.encode() & 8) >> 3));
         self.writer.write_u8(0x0F);
         self.writer.write_u8(0xAF);
         let mut modrm: u8 = 0;
         modrm |= 3 << 6; // set operation to 11 (register-to-register)
-        modrm |= right.encode() << 3; // set destination register
-        modrm |= left.encode(); // set source register
+        modrm |= (left.encode() & 7) << 3; // set destination register
+        modrm |= right.encode() & 7; // set source register
         self.writer.write_u8(modrm);
     }
 
+    // idiv divisor, divides rdx:rax, the quotient goes in rax and the remainder in rdx
     pub(crate) fn div_reg_reg(&mut self, divisor: X86Register) {
-        // idiv only works on rax and rdx
+        self.div_extension(divisor, 7);
+    }
+
+    // div divisor, the unsigned version of idiv
+    pub(crate) fn udiv_reg_reg(&mut self, divisor: X86Register) {
+        self.div_extension(divisor, 6);
+    }
+
+    fn div_extension(&mut self, divisor: X86Register, extension: u8) {
+        self.writer.write_u8(0x48 | ((divisor.encode() & 8) >> 3));
         self.writer.write_u8(0xF7);
         let mut modrm: u8 = 0;
         modrm |= 3 << 6; // set operation to 11 (register-to-register)
-        modrm |= 7 << 3; // set the idiv operation within the 0xF7 opcode
-        modrm |= divisor.encode(); // set divisor register
+        modrm |= extension << 3; // set the operation within the 0xF7 opcode
+        modrm |= divisor.encode() & 7; // set divisor register
         self.writer.write_u8(modrm);
     }
 
-    pub(crate) fn eq_reg_reg(&mut self, left: X86Register, right: X86Register) {
+    // shl reg, cl on the low size bytes of reg
+    pub(crate) fn shl_reg_cl(&mut self, reg: X86Register, size: usize) {
+        self.write_shift(reg, 4, size, None);
+    }
+
+    // shr reg, cl on the low size bytes of reg
+    pub(crate) fn shr_reg_cl(&mut self, reg: X86Register, size: usize) {
+        self.write_shift(reg, 5, size, None);
+    }
+
+    // sar reg, cl on the low size bytes of reg
+    pub(crate) fn sar_reg_cl(&mut self, reg: X86Register, size: usize) {
+        self.write_shift(reg, 7, size, None);
+    }
+
+    // rol reg, cl on the low size bytes of reg
+    pub(crate) fn rol_reg_cl(&mut self, reg: X86Register, size: usize) {
+        self.write_shift(reg, 0, size, None);
+    }
+
+    // ror reg, cl on the low size bytes of reg
+    pub(crate) fn ror_reg_cl(&mut self, reg: X86Register, size: usize) {
+        self.write_shift(reg, 1, size, None);
+    }
+
+    // shl reg, imm8 on the low size bytes of reg
+    pub(crate) fn shl_reg_imm(&mut self, reg: X86Register, size: usize, amount: u8) {
+        self.write_shift(reg, 4, size, Some(amount));
+    }
+
+    // shr reg, imm8 on the low size bytes of reg
+    pub(crate) fn shr_reg_imm(&mut self, reg: X86Register, size: usize, amount: u8) {
+        self.write_shift(reg, 5, size, Some(amount));
+    }
+
+    // sar reg, imm8 on the low size bytes of reg
+    pub(crate) fn sar_reg_imm(&mut self, reg: X86Register, size: usize, amount: u8) {
+        self.write_shift(reg, 7, size, Some(amount));
+    }
+
+    // rol reg, imm8 on the low size bytes of reg
+    pub(crate) fn rol_reg_imm(&mut self, reg: X86Register, size: usize, amount: u8) {
+        self.write_shift(reg, 0, size, Some(amount));
+    }
+
+    // ror reg, imm8 on the low size bytes of reg
+    pub(crate) fn ror_reg_imm(&mut self, reg: X86Register, size: usize, amount: u8) {
+        self.write_shift(reg, 1, size, Some(amount));
+    }
+
+    fn write_shift(&mut self, reg: X86Register, extension: u8, size: usize, amount: Option<u8>) {
+        if size == 2 {
+            self.writer.write_u8(0x66);
+        }
+
+        let mut rex = 0x40 | ((reg.encode() & 8) >> 3);
+        if size == 8 {
+            rex |= 0x08;
+        }
+        // without a rex prefix the low bytes of rsp, rbp, rsi and rdi would be ah, ch, dh and bh
+        if rex != 0x40 || (size == 1 && reg.encode() >= 4) {
+            self.writer.write_u8(rex);
+        }
+
+        let opcode = match (size, amount) {
+            (1, None) => 0xD2,
+            (_, None) => 0xD3,
+            (1, Some(_)) => 0xC0,
+            (_, Some(_)) => 0xC1,
+        };
+        self.writer.write_u8(opcode);
+        self.writer.write_u8(0xC0 | (extension << 3) | (reg.encode() & 7));
+
+        if let Some(amount) = amount {
+            self.writer.write_u8(amount);
+        }
+    }
+
+    // and left, right
+    pub(crate) fn and_reg_reg(&mut self, left: X86Register, right: X86Register) {
+        self.write_reg_reg(0x21, left, right);
+    }
+
+    // or left, right
+    pub(crate) fn or_reg_reg(&mut self, left: X86Register, right: X86Register) {
+        self.write_reg_reg(0x09, left, right);
+    }
+
+    // xor left, right
+    pub(crate) fn xor_reg_reg(&mut self, left: X86Register, right: X86Register) {
+        self.write_reg_reg(0x31, left, right);
+    }
+
+    fn write_reg_reg(&mut self, opcode: u8, left: X86Register, right: X86Register) {
+        self.writer.write_u8(0x48 | ((right.encode() & 8) >> 1) | ((left.encode() & 8) >> 3)); // REX.W, REX.R and REX.B
+        self.writer.write_u8(opcode);
+        self.writer.write_u8(0xC0 | ((right.encode() & 7) << 3) | (left.encode() & 7));
+    }
+
+    // neg reg
+    pub(crate) fn neg_reg(&mut self, reg: X86Register) {
+        self.writer.write_u8(0x48 | ((reg.encode() & 8) >> 3));
+        self.writer.write_u8(0xF7);
+        self.writer.write_u8(0xD8 | (reg.encode() & 7));
+    }
+
+    // test left, right
+    pub(crate) fn test_reg_reg(&mut self, left: X86Register, right: X86Register) {
+        self.write_reg_reg(0x85, left, right);
+    }
+
+    // and reg, imm8
+    pub(crate) fn and_reg_imm(&mut self, reg: X86Register, value: i8) {
+        self.writer.write_u8(0x48 | ((reg.encode() & 8) >> 3));
+        self.writer.write_u8(0x83);
+        self.writer.write_u8(0xE0 | (reg.encode() & 7));
+        self.writer.write_i8(value);
+    }
+
+    // not reg
+    pub(crate) fn not_reg(&mut self, reg: X86Register) {
+        self.writer.write_u8(0x48 | ((reg.encode() & 8) >> 3));
+        self.writer.write_u8(0xF7);
+        self.writer.write_u8(0xD0 | (reg.encode() & 7));
+    }
+
+    // xor reg, imm8
+    pub(crate) fn xor_reg_imm(&mut self, reg: X86Register, value: i8) {
+        self.writer.write_u8(0x48 | ((reg.encode() & 8) >> 3));
+        self.writer.write_u8(0x83);
+        self.writer.write_u8(0xF0 | (reg.encode() & 7));
+        self.writer.write_i8(value);
+    }
+
+    // cqo, sign extends rax into rdx before a signed division
+    pub(crate) fn cqo(&mut self) {
+        self.writer.write_u8(0x48);
+        self.writer.write_u8(0x99);
+    }
+
+    // xor edx, edx, clears the high half of the dividend before an unsigned division
+    pub(crate) fn clear_rdx(&mut self) {
+        self.writer.write_u8(0x31);
+        self.writer.write_u8(0xD2);
+    }
+
+    // movsx reg, low bytes of reg
+    pub(crate) fn sign_extend_reg(&mut self, reg: X86Register, size: usize) {
+        let rex = 0x48 | ((reg.encode() & 8) >> 1) | ((reg.encode() & 8) >> 3);
+        let modrm = 0xC0 | ((reg.encode() & 7) << 3) | (reg.encode() & 7);
+        match size {
+            1 => self.write_bytes(&[rex, 0x0F, 0xBE, modrm]),
+            2 => self.write_bytes(&[rex, 0x0F, 0xBF, modrm]),
+            4 => self.write_bytes(&[rex, 0x63, modrm]),
+            _ => {}
+        }
+    }
+
+    // movzx reg, low bytes of reg
+    pub(crate) fn zero_extend_reg(&mut self, reg: X86Register, size: usize) {
+        let rex = 0x48 | ((reg.encode() & 8) >> 1) | ((reg.encode() & 8) >> 3);
+        let modrm = 0xC0 | ((reg.encode() & 7) << 3) | (reg.encode() & 7);
+        match size {
+            1 => self.write_bytes(&[rex, 0x0F, 0xB6, modrm]),
+            2 => self.write_bytes(&[rex, 0x0F, 0xB7, modrm]),
+            // writing a 32 bits register clears the upper half
+            4 => {
+                if rex & 0x05 != 0 {
+                    self.writer.write_u8(rex & !0x08);
+                }
+                self.write_bytes(&[0x89, modrm]);
+            }
+            _ => {}
+        }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.writer.write_u8(*byte);
+        }
+    }
+
+    // cmp left, right
+    pub(crate) fn cmp_reg_reg(&mut self, left: X86Register, right: X86Register) {
+        self.writer.write_u8(0x48 | ((right.encode() & 8) >> 1) | ((left.encode() & 8) >> 3)); // REX.W, REX.R and REX.B
         self.writer.write_u8(0x39);
         let mut modrm: u8 = 0;
         modrm |= 3 << 6; // set operation to 11 (register-to-register)
-        modrm |= right.encode() << 3; // set destination register
-        modrm |= left.encode(); // set source register
+        modrm |= (right.encode() & 7) << 3; // set right register
+        modrm |= left.encode() & 7; // set left register
         self.writer.write_u8(modrm);
     }
 
+    // setcc on the low byte of reg, the upper bytes are left untouched
+    pub(crate) fn set_cond(&mut self, condition: Condition, reg: X86Register) {
+        // without a rex prefix the low bytes of rsp, rbp, rsi and rdi would be ah, ch, dh and bh
+        if reg.encode() >= 4 {
+            self.writer.write_u8(0x40 | ((reg.encode() & 8) >> 3));
+        }
+        self.writer.write_u8(0x0F);
+        self.writer.write_u8(0x90 | condition.code());
+        self.writer.write_u8(0xC0 | (reg.encode() & 7));
+    }
+
     pub(crate) fn push_reg(&mut self, reg: X86Register) {
         if reg.is_xmm() {
-            self.writer.write_u8(0x48); // rex.w prefix
-            self.writer.write_u8(0x83); // opcode for 'sub'
-            self.writer.write_u8(0xEC); // modR/M byte for 'sub' with RSP
-            self.writer.write_u8(0x10); //Immediate 16
+            // there is no push for xmm registers, the stack pointer is moved by hand
+            self.sub_rsp_imm(8);
+            self.movsd_xmm_to_mem_disp(reg, X86Register::RSP, 0);
         } else {
 
             // below r8 can have a for condense way to be pushed
@@ -159,17 +628,8 @@ impl X86_64Encoder {
 
     pub(crate) fn pop_reg(&mut self, reg: X86Register) {
         if reg.is_xmm() {
-            // movaps xmmN, [rsp]
-            self.writer.write_u8(0x48);
-            self.writer.write_u8(0x0F);
-            self.writer.write_u8(0x28);
-            self.writer.write_u8(0x04 | (reg.encode() << 3));
-
-            // movaps [rsp], xmm
-            self.writer.write_u8(0x48);
-            self.writer.write_u8(0x83);
-            self.writer.write_u8(0xC4);
-            self.writer.write_u8(0x10);
+            self.movsd_mem_disp_to_xmm(X86Register::RSP, 0, reg);
+            self.add_rsp_imm(8);
         } else {
             // below r8 have a condense way to be pushed
             if reg.encode() < X86Register::R8.encode() {
@@ -181,14 +641,63 @@ impl X86_64Encoder {
         }
     }
 
+    // sub rsp, imm32
+    pub(crate) fn sub_rsp_imm(&mut self, value: i32) {
+        self.writer.write_u8(0x48);
+        self.writer.write_u8(0x81);
+        self.writer.write_u8(0xEC);
+        self.writer.write_i32(value);
+    }
+
+    // add rsp, imm32
+    pub(crate) fn add_rsp_imm(&mut self, value: i32) {
+        self.writer.write_u8(0x48);
+        self.writer.write_u8(0x81);
+        self.writer.write_u8(0xC4);
+        self.writer.write_i32(value);
+    }
+
+    // sub reg, imm32
+    pub(crate) fn sub_reg_imm(&mut self, reg: X86Register, value: i32) {
+        self.writer.write_u8(0x48 | ((reg.encode() & 8) >> 3));
+        self.writer.write_u8(0x81);
+        self.writer.write_u8(0xE8 | (reg.encode() & 7));
+        self.writer.write_i32(value);
+    }
+
+    // cmp reg, imm32
+    pub(crate) fn cmp_reg_imm(&mut self, reg: X86Register, value: i32) {
+        self.writer.write_u8(0x48 | ((reg.encode() & 8) >> 3));
+        self.writer.write_u8(0x81);
+        self.writer.write_u8(0xF8 | (reg.encode() & 7));
+        self.writer.write_i32(value);
+    }
+
+    // or qword [rsp], 0, touches the memory at the stack pointer without changing it
+    pub(crate) fn probe_stack(&mut self) {
+        self.write_bytes(&[0x48, 0x83, 0x0C, 0x24, 0x00]);
+    }
+
     pub(crate) fn jmp(&mut self) -> usize {
         self.writer.write_u8(0xE9);
         self.writer.write_i32(0)
     }
 
+    // jmp rel32 to an offset already written
+    pub(crate) fn jmp_to(&mut self, target: usize) {
+        let offset = self.jmp();
+        self.writer.rewrite_i32(offset, target as i32 - offset as i32 - 4);
+    }
+
+    // make the rel32 at the offset jump to the current end of the code
+    pub(crate) fn patch_jump_here(&mut self, offset: usize) {
+        let target = self.writer.len();
+        self.writer.rewrite_i32(offset, target as i32 - offset as i32 - 4);
+    }
+
     pub(crate) fn cond_jmp(&mut self, reg: X86Register) -> usize {
         // cmp reg,0
-        self.writer.write_u8(0x48);
+        self.writer.write_u8(0x48 | ((reg.encode() & 8) >> 3));
         self.writer.write_u8(0x83);
         self.writer.write_u8(0xF8 | (reg.encode() & 7));
         self.writer.write_u8(0x00);
@@ -199,22 +708,21 @@ impl X86_64Encoder {
         self.writer.write_i32(0)
     }
 
-    pub(crate) fn ret(&mut self) {
-        self.writer.write_u8(0xC3);
+    // jcc rel32 on the flags set by the last comparison
+    pub(crate) fn jmp_cond(&mut self, condition: Condition) -> usize {
+        self.writer.write_u8(0x0F);
+        self.writer.write_u8(0x80 | condition.code());
+        self.writer.write_i32(0)
     }
 
-    pub(crate) fn push_shadow(&mut self) {
-        self.writer.write_u8(0x48);
-        self.writer.write_u8(0x83);
-        self.writer.write_u8(0xec);
-        self.writer.write_u8(0x20);
+    pub(crate) fn ret(&mut self) {
+        self.writer.write_u8(0xC3);
     }
 
-    pub(crate) fn pop_shadow(&mut self) {
-        self.writer.write_u8(0x48);
-        self.writer.write_u8(0x83);
-        self.writer.write_u8(0xc4);
-        self.writer.write_u8(0x20);
+    // call rel32, returns the offset of the displacement to fix once the target is placed
+    pub(crate) fn call_rel32(&mut self) -> usize {
+        self.writer.write_u8(0xE8);
+        self.writer.write_i32(0)
     }
 
     pub(crate) fn call(&mut self, reg: X86Register) {
@@ -226,4 +734,130 @@ impl X86_64Encoder {
     pub(crate) fn bytes(&self) -> &Vec<u8> {
         self.writer.bytes()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gen::x86_64::x86_64_allocator::X86Register::*;
+
+    fn encode(write: impl FnOnce(&mut X86_64Encoder)) -> Vec<u8> {
+        let mut encoder = X86_64Encoder::new();
+        write(&mut encoder);
+        encoder.bytes().clone()
+    }
+
+    #[test]
+    fn integer_arithmetic() {
+        assert_eq!(encode(|e| e.sub_reg_reg(RAX, R9)), [0x4C, 0x29, 0xC8]);
+        // imul has its destination in the reg field
+        assert_eq!(encode(|e| e.mul_reg_reg(R10, RCX)), [0x4C, 0x0F, 0xAF, 0xD1]);
+        assert_eq!(encode(|e| e.mul_reg_reg(RAX, R11)), [0x49, 0x0F, 0xAF, 0xC3]);
+        assert_eq!(encode(|e| e.div_reg_reg(RCX)), [0x48, 0xF7, 0xF9]);
+        assert_eq!(encode(|e| e.div_reg_reg(R11)), [0x49, 0xF7, 0xFB]);
+        assert_eq!(encode(|e| e.cqo()), [0x48, 0x99]);
+    }
+
+    #[test]
+    fn comparisons_and_conditions() {
+        assert_eq!(encode(|e| e.cmp_reg_reg(RBX, R12)), [0x4C, 0x39, 0xE3]);
+        assert_eq!(encode(|e| e.set_cond(Condition::Less, RAX)), [0x0F, 0x9C, 0xC0]);
+        // sil needs an empty rex prefix and r9b needs rex.b
+        assert_eq!(encode(|e| e.set_cond(Condition::Equal, RSI)), [0x40, 0x0F, 0x94, 0xC6]);
+        assert_eq!(encode(|e| e.set_cond(Condition::Greater, R9)), [0x41, 0x0F, 0x9F, 0xC1]);
+        assert_eq!(encode(|e| { e.jmp_cond(Condition::NotEqual); }), [0x0F, 0x85, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn stack_probes() {
+        assert_eq!(encode(|e| e.sub_reg_imm(RAX, 4096)), [0x48, 0x81, 0xE8, 0x00, 0x10, 0x00, 0x00]);
+        assert_eq!(encode(|e| e.cmp_reg_imm(R11, 4096)), [0x49, 0x81, 0xFB, 0x00, 0x10, 0x00, 0x00]);
+        assert_eq!(encode(|e| e.probe_stack()), [0x48, 0x83, 0x0C, 0x24, 0x00]);
+        // a jump back to the start of the code counts from the end of the jump
+        assert_eq!(encode(|e| { e.ret(); e.jmp_to(0); }), [0xC3, 0xE9, 0xFA, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn unsigned_division_and_right_shifts() {
+        assert_eq!(encode(|e| e.udiv_reg_reg(RBX)), [0x48, 0xF7, 0xF3]);
+        assert_eq!(encode(|e| e.udiv_reg_reg(R8)), [0x49, 0xF7, 0xF0]);
+        assert_eq!(encode(|e| e.clear_rdx()), [0x31, 0xD2]);
+        assert_eq!(encode(|e| e.shr_reg_cl(RAX, 8)), [0x48, 0xD3, 0xE8]);
+        assert_eq!(encode(|e| e.sar_reg_cl(R13, 8)), [0x49, 0xD3, 0xFD]);
+    }
+
+    #[test]
+    fn bitwise_operations() {
+        assert_eq!(encode(|e| e.and_reg_reg(RAX, R10)), [0x4C, 0x21, 0xD0]);
+        assert_eq!(encode(|e| e.or_reg_reg(R11, RBX)), [0x49, 0x09, 0xDB]);
+        assert_eq!(encode(|e| e.xor_reg_reg(RCX, RDX)), [0x48, 0x31, 0xD1]);
+        assert_eq!(encode(|e| e.not_reg(R14)), [0x49, 0xF7, 0xD6]);
+        assert_eq!(encode(|e| e.xor_reg_imm(RAX, -1)), [0x48, 0x83, 0xF0, 0xFF]);
+    }
+
+    #[test]
+    fn shifts_of_every_size() {
+        assert_eq!(encode(|e| e.shl_reg_cl(RBX, 4)), [0xD3, 0xE3]);
+        assert_eq!(encode(|e| e.rol_reg_cl(RSI, 1)), [0x40, 0xD2, 0xC6]);
+        assert_eq!(encode(|e| e.ror_reg_cl(R9, 2)), [0x66, 0x41, 0xD3, 0xC9]);
+        assert_eq!(encode(|e| e.shl_reg_imm(RDI, 8, 3)), [0x48, 0xC1, 0xE7, 0x03]);
+        assert_eq!(encode(|e| e.ror_reg_imm(RAX, 1, 1)), [0xC0, 0xC8, 0x01]);
+        assert_eq!(encode(|e| e.sar_reg_imm(R12, 2, 15)), [0x66, 0x41, 0xC1, 0xFC, 0x0F]);
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(encode(|e| e.int_to_float(RAX, XMM1, true)), [0xF2, 0x48, 0x0F, 0x2A, 0xC8]);
+        assert_eq!(encode(|e| e.float_to_int(XMM9, R10, false)), [0xF3, 0x4D, 0x0F, 0x2C, 0xD1]);
+        assert_eq!(encode(|e| e.float_to_double(XMM2, XMM3)), [0xF3, 0x0F, 0x5A, 0xDA]);
+        assert_eq!(encode(|e| e.double_to_float(XMM10, XMM0)), [0xF2, 0x41, 0x0F, 0x5A, 0xC2]);
+        assert_eq!(encode(|e| e.sign_extend_reg(RAX, 1)), [0x48, 0x0F, 0xBE, 0xC0]);
+        assert_eq!(encode(|e| e.sign_extend_reg(R9, 4)), [0x4D, 0x63, 0xC9]);
+        assert_eq!(encode(|e| e.zero_extend_reg(RSI, 2)), [0x48, 0x0F, 0xB7, 0xF6]);
+        // a 32 bits move only keeps the rex prefix for r8 to r15
+        assert_eq!(encode(|e| e.zero_extend_reg(R8, 4)), [0x45, 0x89, 0xC0]);
+        assert_eq!(encode(|e| e.zero_extend_reg(RBX, 4)), [0x89, 0xDB]);
+    }
+
+    #[test]
+    fn scalar_float_operations() {
+        assert_eq!(encode(|e| e.add_float(XMM1, XMM2, true)), [0xF2, 0x0F, 0x58, 0xCA]);
+        assert_eq!(encode(|e| e.sub_float(XMM8, XMM0, false)), [0xF3, 0x44, 0x0F, 0x5C, 0xC0]);
+        assert_eq!(encode(|e| e.mul_float(XMM3, XMM12, true)), [0xF2, 0x41, 0x0F, 0x59, 0xDC]);
+        assert_eq!(encode(|e| e.div_float(XMM0, XMM1, false)), [0xF3, 0x0F, 0x5E, 0xC1]);
+        assert_eq!(encode(|e| e.xor_float(XMM1, XMM15)), [0x41, 0x0F, 0x57, 0xCF]);
+        assert_eq!(encode(|e| e.and_float(XMM2, XMM3)), [0x0F, 0x54, 0xD3]);
+        assert_eq!(encode(|e| e.ucomi(XMM0, XMM1, true)), [0x66, 0x0F, 0x2E, 0xC1]);
+        assert_eq!(encode(|e| e.ucomi(XMM9, XMM2, false)), [0x44, 0x0F, 0x2E, 0xCA]);
+    }
+
+    #[test]
+    fn sized_loads() {
+        assert_eq!(encode(|e| e.mov_mem_to_reg(RBP, -8, RAX, 8, false)), [0x48, 0x8B, 0x85, 0xF8, 0xFF, 0xFF, 0xFF]);
+        // r12 and rsp as a base need a sib byte
+        assert_eq!(encode(|e| e.mov_mem_to_reg(R12, 16, RBX, 1, false)), [0x49, 0x0F, 0xBE, 0x9C, 0x24, 0x10, 0, 0, 0]);
+        assert_eq!(encode(|e| e.mov_mem_to_reg(RSP, 0, R9, 2, true)), [0x4C, 0x0F, 0xB7, 0x8C, 0x24, 0, 0, 0, 0]);
+        assert_eq!(encode(|e| e.mov_mem_to_reg(RCX, 4, RDX, 4, true)), [0x8B, 0x91, 4, 0, 0, 0]);
+        assert_eq!(encode(|e| e.mov_mem_to_reg(R13, 0, RAX, 4, false)), [0x49, 0x63, 0x85, 0, 0, 0, 0]);
+        assert_eq!(encode(|e| e.movss_mem_disp_to_xmm(RAX, 4, XMM9)), [0xF3, 0x44, 0x0F, 0x10, 0x88, 4, 0, 0, 0]);
+    }
+
+    #[test]
+    fn sized_stores() {
+        // sil needs an empty rex prefix
+        assert_eq!(encode(|e| e.mov_reg_to_mem(RSI, RAX, 1, 1)), [0x40, 0x88, 0xB0, 1, 0, 0, 0]);
+        assert_eq!(encode(|e| e.mov_reg_to_mem(R8, RDI, -4, 2)), [0x66, 0x44, 0x89, 0x87, 0xFC, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(encode(|e| e.mov_reg_to_mem(RAX, RBX, 0, 4)), [0x89, 0x83, 0, 0, 0, 0]);
+        assert_eq!(encode(|e| e.mov_reg_to_mem(R10, R12, 8, 8)), [0x4D, 0x89, 0x94, 0x24, 8, 0, 0, 0]);
+        assert_eq!(encode(|e| e.movss_xmm_to_mem_disp(XMM1, R11, 0)), [0xF3, 0x41, 0x0F, 0x11, 0x8B, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn rip_relative_addresses() {
+        let mut offset = 0;
+        // the displacement is patched once the distance to the global is known
+        assert_eq!(encode(|e| offset = e.lea_rip_to_reg(R9)), [0x4C, 0x8D, 0x0D, 0, 0, 0, 0]);
+        assert_eq!(offset, 3);
+        assert_eq!(encode(|e| { e.lea_rip_to_reg(RAX); }), [0x48, 0x8D, 0x05, 0, 0, 0, 0]);
+    }
+}
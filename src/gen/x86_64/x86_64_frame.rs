@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use crate::gen::x86_64::x86_64_allocator::{X86_64Allocator, X86Register};
+use crate::gen::x86_64::x86_64_allocator::X86Register::{RAX, RBP, RSP};
+use crate::gen::x86_64::x86_64_encoder::{Condition, X86_64Encoder};
+use crate::lang::builder::Builder;
+use crate::lang::instr::Instr;
+use crate::lang::value::Value;
+
+// the stack only grows one guard page at a time, a frame or an alloca bigger than a page touches every page on its way
+const STACK_PROBE_SIZE: i32 = 4096;
+
+// layout of the stack frame of a function, from the base pointer down:
+// the callee-saved integer registers it pushes, the callee-saved xmm registers it stores, its stack slots and its spill slots
+pub(crate) struct X86_64Frame {
+    pushed_registers: Vec<X86Register>,
+    // the whole 16 bytes of an xmm register have to be preserved, not only the part the function uses
+    stored_xmm: Vec<X86Register>,
+    // offset from the base pointer of the memory of every stack slot, by the id of its value
+    stack_slots: HashMap<usize, i32>,
+    // bytes reserved below the pushed registers
+    frame_size: i32,
+}
+
+impl X86_64Frame {
+    // only the registers the function uses and the calling convention asks to preserve are saved
+    pub(crate) fn new(allocator: &mut X86_64Allocator, volatiles: &[X86Register], builder: &Builder) -> Self {
+        let callee_saved: Vec<X86Register> = allocator.used_registers().into_iter()
+            .filter(|reg| !volatiles.contains(reg))
+            .collect();
+        let pushed_registers: Vec<X86Register> = callee_saved.iter().filter(|reg| !reg.is_xmm()).copied().collect();
+        let stored_xmm: Vec<X86Register> = callee_saved.iter().filter(|reg| reg.is_xmm()).copied().collect();
+
+        let pushed_bytes = 8 * pushed_registers.len() as i32;
+        let mut frame_base = pushed_bytes + 16 * stored_xmm.len() as i32;
+
+        // the base pointer is 16 bytes aligned, an offset aligned from it gives an aligned address.
+        // the verifier keeps the stack slots under MAX_STACK_SLOTS_SIZE, the frame only overflows with hundreds of millions of spilled values
+        let overflow = "the frame of the function does not fit in an i32";
+        let mut stack_slots = HashMap::new();
+        for block in builder.blocks() {
+            for instr in block.instructions() {
+                if let Instr::StackSlot { size, align, gen_value } = instr {
+                    let end = i32::try_from(*size).ok().and_then(|size| frame_base.checked_add(size));
+                    frame_base = end.and_then(|end| align_up(end, *align as i32)).expect(overflow);
+                    stack_slots.insert(gen_value.get_id(), -frame_base);
+                }
+            }
+        }
+        frame_base = align_up(frame_base, 8).expect(overflow);
+        allocator.set_frame_base(frame_base);
+
+        // the stack is 16 bytes aligned once rbp is pushed, it has to stay aligned for the calls of the function
+        let frame_end = i32::try_from(allocator.spill_size()).ok()
+            .and_then(|spill_size| frame_base.checked_add(spill_size))
+            .and_then(|end| align_up(end, 16))
+            .expect(overflow);
+
+        X86_64Frame { pushed_registers, stored_xmm, stack_slots, frame_size: frame_end - pushed_bytes }
+    }
+
+    pub(crate) fn stack_slot_offset(&self, slot: &Value) -> i32 {
+        self.stack_slots[&slot.get_id()]
+    }
+
+    fn pushed_bytes(&self) -> i32 {
+        8 * self.pushed_registers.len() as i32
+    }
+
+    fn xmm_offset(&self, index: usize) -> i32 {
+        -self.pushed_bytes() - 16 * (index as i32 + 1)
+    }
+
+    pub(crate) fn emit_prologue(&self, encoder: &mut X86_64Encoder) {
+        encoder.push_reg(RBP);
+        encoder.mov_reg_to_reg(RSP, RBP);
+
+        for reg in &self.pushed_registers {
+            encoder.push_reg(*reg);
+        }
+
+        // rax is not used to pass arguments, it is free until the parameters are moved
+        if self.frame_size > STACK_PROBE_SIZE {
+            encoder.move_reg_i64(RAX, self.frame_size as i64);
+            Self::emit_stack_alloc(encoder, RAX);
+        } else if self.frame_size != 0 {
+            encoder.sub_rsp_imm(self.frame_size);
+        }
+
+        for (index, reg) in self.stored_xmm.iter().enumerate() {
+            encoder.movdqu_xmm_to_mem_disp(*reg, RBP, self.xmm_offset(index));
+        }
+    }
+
+    // move the stack pointer down by the unsigned size in the register and align it on 16 bytes, the register is clobbered.
+    // a page is touched before the stack pointer goes past it, a size bigger than the stack stops on its guard page
+    pub(crate) fn emit_stack_alloc(encoder: &mut X86_64Encoder, size: X86Register) {
+        encoder.probe_stack();
+        let loop_start = encoder.bytes().len();
+        encoder.cmp_reg_imm(size, STACK_PROBE_SIZE);
+        let done = encoder.jmp_cond(Condition::Below);
+        encoder.sub_rsp_imm(STACK_PROBE_SIZE);
+        encoder.probe_stack();
+        encoder.sub_reg_imm(size, STACK_PROBE_SIZE);
+        encoder.jmp_to(loop_start);
+
+        encoder.patch_jump_here(done);
+        encoder.sub_reg_reg(RSP, size);
+        encoder.and_reg_imm(RSP, -16);
+        encoder.probe_stack();
+    }
+
+    // emitted before every ret, the stack pointer can be anywhere below the frame
+    pub(crate) fn emit_epilogue(&self, encoder: &mut X86_64Encoder) {
+        for (index, reg) in self.stored_xmm.iter().enumerate() {
+            encoder.movdqu_mem_disp_to_xmm(RBP, self.xmm_offset(index), *reg);
+        }
+
+        if self.pushed_registers.is_empty() {
+            encoder.mov_reg_to_reg(RBP, RSP);
+        } else {
+            encoder.lea_mem_disp_to_reg(RBP, -self.pushed_bytes(), RSP);
+        }
+
+        for reg in self.pushed_registers.iter().rev() {
+            encoder.pop_reg(*reg);
+        }
+
+        encoder.pop_reg(RBP);
+        encoder.ret();
+    }
+}
+
+// the offset rounded up to the power of two alignment, None when it does not fit in an i32
+fn align_up(offset: i32, align: i32) -> Option<i32> {
+    Some(offset.checked_add(align - 1)? & !(align - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::gen::x86_64::x86_64_caller::X86_64Caller;
+    use crate::lang::lang_type::Type;
+
+    fn frame(compiler: &mut Compiler, name: &str) -> X86_64Frame {
+        let volatiles = X86_64Caller::new().volatiles().clone();
+        let builder = compiler.get_func_mut_by_name(name).unwrap().builder();
+        let mut allocator = X86_64Allocator::new(builder, &volatiles);
+        X86_64Frame::new(&mut allocator, &volatiles, builder)
+    }
+
+    // a function keeping count values live across a call to g
+    fn add_caller(compiler: &mut Compiler, name: &str, count: i64) {
+        let builder = compiler.add_func(name, &[], Type::i64()).unwrap().builder();
+        let values: Vec<Value> = (1..=count).map(|value| builder.const_i64(value)).collect();
+        let mut sum = builder.call("g", &[]).unwrap();
+        for value in values {
+            sum = builder.add(sum, value);
+        }
+        builder.ret(sum);
+    }
+
+    #[test]
+    fn a_leaf_function_only_saves_the_base_pointer() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("g", &[], Type::i64()).unwrap().builder();
+        let value = builder.const_i64(1);
+        builder.ret(value);
+
+        let frame = frame(&mut compiler, "g");
+        assert!(frame.pushed_registers.is_empty() && frame.stored_xmm.is_empty());
+        assert_eq!(frame.frame_size, 0);
+
+        let mut encoder = X86_64Encoder::new();
+        frame.emit_prologue(&mut encoder);
+        // push rbp; mov rbp, rsp
+        assert_eq!(*encoder.bytes(), vec![0x55, 0x48, 0x89, 0xE5]);
+    }
+
+    #[test]
+    fn the_used_callee_saved_registers_are_saved_and_the_stack_stays_aligned() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("g", &[], Type::i64()).unwrap().builder();
+        let value = builder.const_i64(1000);
+        builder.ret(value);
+        for count in 1..8 {
+            add_caller(&mut compiler, &format!("f{}", count), count);
+        }
+
+        let volatiles = X86_64Caller::new().volatiles().clone();
+        for count in 1..8 {
+            let frame = frame(&mut compiler, &format!("f{}", count));
+            assert!(!frame.pushed_registers.is_empty());
+            assert!(frame.pushed_registers.iter().all(|reg| !volatiles.contains(reg)));
+            assert_eq!((frame.pushed_bytes() + frame.frame_size) % 16, 0);
+        }
+
+        compiler.jit().unwrap();
+        for count in 1..8 {
+            assert_eq!(compiler.get_typed::<fn() -> i64>(&format!("f{}", count)).unwrap().call(), 1000 + count * (count + 1) / 2);
+        }
+    }
+
+    #[test]
+    fn a_callee_gives_back_the_registers_of_its_caller() {
+        let mut compiler = Compiler::new().unwrap();
+        // g needs every register, the values of f stay in callee-saved registers during the call
+        let builder = compiler.add_func("g", &[], Type::i64()).unwrap().builder();
+        let values: Vec<Value> = (0..20).map(|value| builder.const_i64(value * 1000)).collect();
+        let mut sum = builder.const_i64(0);
+        for value in values {
+            sum = builder.add(sum, value);
+        }
+        builder.ret(sum);
+        add_caller(&mut compiler, "f", 6);
+
+        compiler.jit().unwrap();
+        assert_eq!(compiler.get_typed::<fn() -> i64>("f").unwrap().call(), 190000 + 21);
+    }
+
+    #[test]
+    fn stack_slots_are_aligned_below_the_saved_registers() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[], Type::void()).unwrap().builder();
+        let slots: Vec<Value> = [(1, 1), (8, 8), (16, 16), (3, 4)].iter()
+            .map(|(size, align)| builder.stack_slot(*size, *align).unwrap())
+            .collect();
+        builder.ret_void();
+
+        let frame = frame(&mut compiler, "f");
+        let offsets: Vec<i32> = slots.iter().map(|slot| frame.stack_slot_offset(slot)).collect();
+        assert_eq!(offsets, [-1, -16, -32, -36]);
+        assert_eq!(frame.frame_size, 48);
+    }
+
+    #[test]
+    fn a_frame_bigger_than_a_page_is_probed() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[], Type::void()).unwrap().builder();
+        builder.stack_slot(3 * STACK_PROBE_SIZE as u32, 16).unwrap();
+        builder.ret_void();
+
+        let frame = frame(&mut compiler, "f");
+        assert_eq!(frame.frame_size, 3 * STACK_PROBE_SIZE);
+        let mut encoder = X86_64Encoder::new();
+        frame.emit_prologue(&mut encoder);
+        // push rbp; mov rbp, rsp; mov rax, frame size, then the probes instead of a single sub rsp
+        let bytes = encoder.bytes();
+        assert_eq!(bytes[..6], [0x55, 0x48, 0x89, 0xE5, 0x48, 0xB8]);
+        assert_eq!(bytes[6..14], (3 * STACK_PROBE_SIZE as i64).to_le_bytes());
+        assert!(bytes.windows(5).any(|probe| probe == [0x48, 0x83, 0x0C, 0x24, 0x00]));
+    }
+}
@@ -0,0 +1,124 @@
+use crate::gen::x86_64::x86_64_allocator::{Location, X86Register};
+use crate::gen::x86_64::x86_64_encoder::X86_64Encoder;
+
+// copy 8 bytes between any two locations, integer and float registers included
+pub(crate) fn emit_move(encoder: &mut X86_64Encoder, src: Location, dst: Location) {
+    match (src, dst) {
+        (Location::Register(src), Location::Register(dst)) => {
+            match (src.is_xmm(), dst.is_xmm()) {
+                (false, false) => encoder.mov_reg_to_reg(src, dst),
+                (true, true) => encoder.mov_xmm_to_xmm(src, dst),
+                (false, true) => encoder.move_reg_to_xmm(src, dst),
+                (true, false) => encoder.move_xmm_to_reg(src, dst),
+            }
+        }
+
+        (Location::Register(src), Location::Stack(offset)) => {
+            if src.is_xmm() {
+                encoder.movsd_xmm_to_mem_disp(src, X86Register::RBP, offset);
+            } else {
+                encoder.mov_reg_to_mem_disp(src, X86Register::RBP, offset);
+            }
+        }
+
+        (Location::Stack(offset), Location::Register(dst)) => {
+            if dst.is_xmm() {
+                encoder.movsd_mem_disp_to_xmm(X86Register::RBP, offset, dst);
+            } else {
+                encoder.mov_mem_disp_to_reg(X86Register::RBP, offset, dst);
+            }
+        }
+
+        (Location::Stack(src_offset), Location::Stack(dst_offset)) => {
+            if src_offset != dst_offset {
+                encoder.mov_mem_disp_to_reg(X86Register::RBP, src_offset, MEMORY_SCRATCH);
+                encoder.mov_reg_to_mem_disp(MEMORY_SCRATCH, X86Register::RBP, dst_offset);
+            }
+        }
+    }
+}
+
+// used to go from memory to memory
+const MEMORY_SCRATCH: X86Register = X86Register::R11;
+// used to break the cycles of moves, like two values swapping their registers
+const CYCLE_SCRATCH: X86Register = X86Register::RAX;
+
+// do every move as if they all happened at the same time: a move never overwrites a location
+// that another move still has to read, cycles are broken by going through a scratch register
+pub(crate) fn emit_parallel_moves(encoder: &mut X86_64Encoder, moves: &[(Location, Location)]) {
+    let mut pending: Vec<(Location, Location)> = moves.iter().filter(|(src, dst)| src != dst).copied().collect();
+
+    while !pending.is_empty() {
+        let ready = pending.iter().position(|(_, dst)| !pending.iter().any(|(src, _)| src == dst));
+
+        match ready {
+            Some(index) => {
+                let (src, dst) = pending.remove(index);
+                emit_move(encoder, src, dst);
+            }
+            None => {
+                // every destination is still read by another move, one of them is saved aside
+                let (_, blocked) = pending[0];
+                emit_move(encoder, blocked, Location::Register(CYCLE_SCRATCH));
+                for (src, _) in pending.iter_mut() {
+                    if *src == blocked {
+                        *src = Location::Register(CYCLE_SCRATCH);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gen::x86_64::x86_64_allocator::X86Register::*;
+
+    fn reg(reg: X86Register) -> Location {
+        Location::Register(reg)
+    }
+
+    fn parallel(moves: &[(Location, Location)]) -> Vec<u8> {
+        let mut encoder = X86_64Encoder::new();
+        emit_parallel_moves(&mut encoder, moves);
+        encoder.bytes().clone()
+    }
+
+    fn sequence(moves: &[(X86Register, X86Register)]) -> Vec<u8> {
+        let mut encoder = X86_64Encoder::new();
+        for (src, dst) in moves {
+            encoder.mov_reg_to_reg(*src, *dst);
+        }
+        encoder.bytes().clone()
+    }
+
+    #[test]
+    fn a_move_waits_for_the_moves_reading_its_destination() {
+        let moves = [(reg(RBX), reg(RSI)), (reg(RSI), reg(RDI))];
+        assert_eq!(parallel(&moves), sequence(&[(RSI, RDI), (RBX, RSI)]));
+    }
+
+    #[test]
+    fn a_swap_goes_through_rax() {
+        let moves = [(reg(RBX), reg(RSI)), (reg(RSI), reg(RBX))];
+        assert_eq!(parallel(&moves), sequence(&[(RSI, RAX), (RBX, RSI), (RAX, RBX)]));
+    }
+
+    #[test]
+    fn a_cycle_with_a_branch_out_of_it() {
+        // r8 -> r9 -> r10 -> r8, and r8 is also copied to r12
+        let moves = [(reg(R8), reg(R9)), (reg(R9), reg(R10)), (reg(R10), reg(R8)), (reg(R8), reg(R12))];
+        assert_eq!(parallel(&moves), sequence(&[(R8, R12), (R9, RAX), (R8, R9), (R10, R8), (RAX, R10)]));
+    }
+
+    #[test]
+    fn moves_in_place_and_between_stack_slots() {
+        assert!(parallel(&[(reg(RBX), reg(RBX)), (Location::Stack(-8), Location::Stack(-8))]).is_empty());
+
+        let mut encoder = X86_64Encoder::new();
+        encoder.mov_mem_disp_to_reg(RBP, -8, R11);
+        encoder.mov_reg_to_mem_disp(R11, RBP, -16);
+        assert_eq!(parallel(&[(Location::Stack(-8), Location::Stack(-16))]), *encoder.bytes());
+    }
+}
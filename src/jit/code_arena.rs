@@ -0,0 +1,392 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use crate::error::CowError;
+use crate::jit::code_memory::CodeMemory;
+
+// the whole arena has to stay within the reach of a rel32 displacement
+const MAX_RESERVE_SIZE: usize = 1 << 31;
+
+#[derive(Clone)]
+pub struct ArenaConfig {
+    // alignment of the start of every function, must be a power of two
+    pub alignment: usize,
+    // size of the address space reserved up front, every function of a compiler lives in it
+    pub reserve_size: usize,
+}
+
+impl Default for ArenaConfig {
+    fn default() -> Self {
+        ArenaConfig { alignment: 16, reserve_size: 256 * 1024 * 1024 }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PageUsage {
+    pub address: usize,
+    pub used_bytes: usize,
+    pub size: usize,
+}
+
+// packs the code of every function one after the other in a single code memory reservation,
+// which keeps small functions from wasting whole pages and keeps every function reachable with rel32
+pub(crate) struct CodeArena {
+    memory: CodeMemory,
+    alignment: usize,
+    top: usize,
+    // ranges below the top that were freed and can be handed out again, sorted by offset
+    free_ranges: Vec<(usize, usize)>,
+    // bytes of code stored in every page that was handed out
+    page_used: Vec<usize>,
+    // the data of the globals grows down from the end of the reservation, this is its lowest offset
+    data_bottom: usize,
+    // what is left of the last pages taken for mutable and for read only data
+    mutable_data: (usize, usize),
+    read_only_data: (usize, usize),
+}
+
+// where the data of the globals stood at some point, the data placed after it can be given back
+#[derive(Clone, Copy)]
+pub(crate) struct DataMark {
+    data_bottom: usize,
+    mutable_data: (usize, usize),
+    read_only_data: (usize, usize),
+}
+
+// the code of a single function inside of the arena, the space is given back to the arena on drop
+pub(crate) struct CodeRegion {
+    arena: Rc<RefCell<CodeArena>>,
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl CodeRegion {
+    pub(crate) fn ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+}
+
+impl Drop for CodeRegion {
+    fn drop(&mut self) {
+        self.arena.borrow_mut().free(self.ptr, self.len);
+    }
+}
+
+impl CodeArena {
+    pub(crate) fn new(config: &ArenaConfig) -> Result<Self, CowError> {
+        if !config.alignment.is_power_of_two() {
+            return Err(CowError::InvalidArenaConfig("the code alignment must be a power of two".to_string()));
+        }
+        if config.reserve_size >= MAX_RESERVE_SIZE {
+            return Err(CowError::InvalidArenaConfig("the code arena must fit in a rel32 displacement".to_string()));
+        }
+
+        let memory = CodeMemory::new(config.reserve_size).ok_or(CowError::ArenaReservation)?;
+        let data_bottom = memory.size();
+        Ok(CodeArena {
+            memory,
+            alignment: config.alignment,
+            top: 0,
+            free_ranges: vec![],
+            page_used: vec![],
+            data_bottom,
+            mutable_data: (data_bottom, data_bottom),
+            read_only_data: (data_bottom, data_bottom),
+        })
+    }
+
+    // copy the code in the arena, the code is not executable until finalize is called
+    pub(crate) fn allocate(arena: &Rc<RefCell<CodeArena>>, code: &[u8]) -> Option<CodeRegion> {
+        let ptr = arena.borrow_mut().allocate_bytes(code)?;
+        Some(CodeRegion { arena: arena.clone(), ptr, len: code.len() })
+    }
+
+    fn allocate_bytes(&mut self, code: &[u8]) -> Option<*mut u8> {
+        let reused = self.take_free_range(code.len());
+        let offset = reused.unwrap_or_else(|| self.top.next_multiple_of(self.alignment));
+        if offset + code.len() > self.data_bottom || !self.memory.write(offset, code) {
+            // a range taken from the freed ones stays free
+            if reused.is_some() {
+                self.insert_free_range(offset, code.len());
+            }
+            return None;
+        }
+
+        self.top = self.top.max(offset + code.len());
+        self.mark_used(offset, code.len());
+        Some(unsafe { self.memory.base().add(offset) })
+    }
+
+    // place a global of size bytes in the arena, starting with the initial bytes and zeroed after them.
+    // it stays there as long as the arena. mutable and read only data never share a page since their pages are protected differently
+    pub(crate) fn allocate_data(&mut self, init_bytes: &[u8], size: usize, align: usize, mutable: bool) -> Option<*mut u8> {
+        let page_size = self.memory.page_size();
+        assert!(align.is_power_of_two() && align <= page_size, "a global cannot be aligned on {} bytes", align);
+
+        // the size comes from the caller, it is checked against the free space before anything is taken
+        let (start, end) = if mutable { self.mutable_data } else { self.read_only_data };
+        let aligned = start.next_multiple_of(align);
+        let (offset, end, taken) = if aligned <= end && size <= end - aligned {
+            (aligned, end, 0)
+        } else {
+            let pages_size = size.max(1).checked_next_multiple_of(page_size)?;
+            if self.data_bottom - self.top < pages_size {
+                return None;
+            }
+            self.data_bottom -= pages_size;
+            (self.data_bottom, self.data_bottom + pages_size, pages_size)
+        };
+
+        if !self.memory.write_data(offset, init_bytes, size, mutable) {
+            // the pages taken for the data are given back
+            self.data_bottom += taken;
+            return None;
+        }
+
+        if mutable {
+            self.mutable_data = (offset + size, end);
+        } else {
+            self.read_only_data = (offset + size, end);
+        }
+        Some(unsafe { self.memory.base().add(offset) })
+    }
+
+    pub(crate) fn data_mark(&self) -> DataMark {
+        DataMark { data_bottom: self.data_bottom, mutable_data: self.mutable_data, read_only_data: self.read_only_data }
+    }
+
+    // forget the data placed since the mark, the pages taken for it go back to the os and can hold code again.
+    // the pointers to that data must not be used anymore
+    pub(crate) fn rewind_data(&mut self, mark: DataMark) {
+        let page_size = self.memory.page_size();
+        for page in self.data_bottom / page_size..mark.data_bottom / page_size {
+            self.memory.release_data_page(page);
+        }
+
+        self.data_bottom = mark.data_bottom;
+        self.mutable_data = mark.mutable_data;
+        self.read_only_data = mark.read_only_data;
+    }
+
+    // first fit search in the freed ranges, what is left of the range on both sides stays free
+    fn take_free_range(&mut self, len: usize) -> Option<usize> {
+        let alignment = self.alignment;
+        let index = self.free_ranges.iter().position(|(start, size)| {
+            start.next_multiple_of(alignment) + len <= start + size
+        })?;
+
+        let (start, size) = self.free_ranges.remove(index);
+        let offset = start.next_multiple_of(alignment);
+        let end = start + size;
+
+        if offset + len < end {
+            self.free_ranges.insert(index, (offset + len, end - offset - len));
+        }
+        if start < offset {
+            self.free_ranges.insert(index, (start, offset - start));
+        }
+
+        Some(offset)
+    }
+
+    fn free(&mut self, ptr: *mut u8, len: usize) {
+        if len == 0 {
+            return;
+        }
+
+        let offset = ptr as usize - self.memory.base() as usize;
+        self.mark_freed(offset, len);
+        self.insert_free_range(offset, len);
+    }
+
+    // insert the range and merge it with its neighbours
+    fn insert_free_range(&mut self, offset: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+
+        let index = self.free_ranges.partition_point(|(start, _)| *start < offset);
+        self.free_ranges.insert(index, (offset, len));
+
+        if index + 1 < self.free_ranges.len() {
+            let (next_start, next_len) = self.free_ranges[index + 1];
+            if offset + len == next_start {
+                self.free_ranges[index].1 += next_len;
+                self.free_ranges.remove(index + 1);
+            }
+        }
+
+        if index > 0 {
+            let (previous_start, previous_len) = self.free_ranges[index - 1];
+            if previous_start + previous_len == offset {
+                self.free_ranges[index - 1].1 += self.free_ranges[index].1;
+                self.free_ranges.remove(index);
+            }
+        }
+
+        // a free range that ends at the top simply lowers the top
+        if let Some(&(last_start, last_len)) = self.free_ranges.last() {
+            if last_start + last_len == self.top {
+                self.top = last_start;
+                self.free_ranges.pop();
+            }
+        }
+    }
+
+    pub(crate) fn page_size(&self) -> usize {
+        self.memory.page_size()
+    }
+
+    pub(crate) fn finalize(&mut self) -> bool {
+        self.memory.finalize()
+    }
+
+    // overwrite bytes of code placed in the arena, every page of code is executable again once it returns true
+    pub(crate) fn patch(&mut self, patches: &[(*mut u8, Vec<u8>)]) -> bool {
+        self.memory.patch(patches)
+    }
+
+    pub(crate) fn page_usage(&self) -> Vec<PageUsage> {
+        let page_size = self.memory.page_size();
+        self.page_used.iter().enumerate().map(|(page, used)| PageUsage {
+            address: self.memory.base() as usize + page * page_size,
+            used_bytes: *used,
+            size: page_size,
+        }).collect()
+    }
+
+    fn mark_freed(&mut self, offset: usize, len: usize) {
+        let page_size = self.memory.page_size();
+        let mut current = offset;
+        let end = offset + len;
+
+        while current < end {
+            let page = current / page_size;
+            let chunk = end.min((page + 1) * page_size) - current;
+
+            self.page_used[page] -= chunk;
+            if self.page_used[page] == 0 {
+                self.memory.release_page(page);
+            }
+            current += chunk;
+        }
+    }
+
+    fn mark_used(&mut self, offset: usize, len: usize) {
+        let page_size = self.memory.page_size();
+        let mut current = offset;
+        let end = offset + len;
+
+        while current < end {
+            let page = current / page_size;
+            let page_end = (page + 1) * page_size;
+            let chunk = end.min(page_end) - current;
+
+            if self.page_used.len() <= page {
+                self.page_used.resize(page + 1, 0);
+            }
+            self.page_used[page] += chunk;
+            current += chunk;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arena() -> Rc<RefCell<CodeArena>> {
+        let page_size = crate::jit::os_memory::page_size();
+        let config = ArenaConfig { alignment: 16, reserve_size: 4 * page_size };
+        Rc::new(RefCell::new(CodeArena::new(&config).unwrap()))
+    }
+
+    #[test]
+    fn functions_are_aligned_one_after_the_other() {
+        let arena = arena();
+        let first = CodeArena::allocate(&arena, &[0xC3; 20]).unwrap();
+        let second = CodeArena::allocate(&arena, &[0xC3; 20]).unwrap();
+        assert_eq!(second.ptr() as usize - first.ptr() as usize, 32);
+    }
+
+    #[test]
+    fn a_freed_range_is_reused() {
+        let arena = arena();
+        let first = CodeArena::allocate(&arena, &[0xC3; 100]).unwrap();
+        let _second = CodeArena::allocate(&arena, &[0xC3; 100]).unwrap();
+        let ptr = first.ptr();
+        drop(first);
+
+        let third = CodeArena::allocate(&arena, &[0xC3; 50]).unwrap();
+        assert_eq!(third.ptr(), ptr);
+        assert_eq!(arena.borrow().free_ranges, vec![(50, 50)]);
+    }
+
+    #[test]
+    fn neighbouring_free_ranges_are_merged() {
+        let arena = arena();
+        let first = CodeArena::allocate(&arena, &[0xC3; 16]).unwrap();
+        let second = CodeArena::allocate(&arena, &[0xC3; 16]).unwrap();
+        let third = CodeArena::allocate(&arena, &[0xC3; 16]).unwrap();
+        let _last = CodeArena::allocate(&arena, &[0xC3; 16]).unwrap();
+
+        drop(first);
+        drop(third);
+        assert_eq!(arena.borrow().free_ranges, vec![(0, 16), (32, 16)]);
+        drop(second);
+        assert_eq!(arena.borrow().free_ranges, vec![(0, 48)]);
+    }
+
+    #[test]
+    fn freeing_the_last_function_lowers_the_top() {
+        let arena = arena();
+        let first = CodeArena::allocate(&arena, &[0xC3; 16]).unwrap();
+        let second = CodeArena::allocate(&arena, &[0xC3; 16]).unwrap();
+
+        drop(first);
+        drop(second);
+        assert!(arena.borrow().free_ranges.is_empty());
+        assert_eq!(arena.borrow().top, 0);
+    }
+
+    #[test]
+    fn the_usage_of_every_page_is_tracked() {
+        let arena = arena();
+        let page_size = arena.borrow().page_size();
+        let first = CodeArena::allocate(&arena, &vec![0xC3; page_size - 16]).unwrap();
+        let second = CodeArena::allocate(&arena, &[0xC3; 32]).unwrap();
+
+        let used: Vec<usize> = arena.borrow().page_usage().iter().map(|usage| usage.used_bytes).collect();
+        assert_eq!(used, vec![page_size, 16]);
+
+        drop(first);
+        let used: Vec<usize> = arena.borrow().page_usage().iter().map(|usage| usage.used_bytes).collect();
+        assert_eq!(used, vec![16, 16]);
+        drop(second);
+    }
+
+    #[test]
+    fn code_and_data_cannot_overlap() {
+        let arena = arena();
+        let page_size = arena.borrow().page_size();
+        assert!(arena.borrow_mut().allocate_data(&[1; 8], 8, 8, false).is_some());
+        assert!(CodeArena::allocate(&arena, &vec![0xC3; 3 * page_size + 1]).is_none());
+        assert!(CodeArena::allocate(&arena, &vec![0xC3; 3 * page_size]).is_some());
+    }
+
+    #[test]
+    fn rewound_data_gives_its_memory_back() {
+        let arena = arena();
+        let page_size = arena.borrow().page_size();
+        let kept = arena.borrow_mut().allocate_data(&[1; 8], 8, 8, true).unwrap();
+        let mark = arena.borrow().data_mark();
+        let rewound = arena.borrow_mut().allocate_data(&[2; 8], 8, 8, true).unwrap();
+        assert!(arena.borrow_mut().allocate_data(&[], 2 * page_size, 8, false).is_some());
+
+        arena.borrow_mut().rewind_data(mark);
+        // the data after the mark is placed again at the same address, and its pages can hold code
+        assert_eq!(arena.borrow_mut().allocate_data(&[3; 8], 8, 8, true), Some(rewound));
+        assert!(CodeArena::allocate(&arena, &vec![0xC3; 3 * page_size]).is_some());
+        assert!(arena.borrow_mut().finalize());
+        assert_eq!(unsafe { *kept }, 1);
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::HashSet;
+use crate::jit::os_memory::{commit_pages, decommit_pages, free_pages, page_size, protect_pages, reserve_pages, round_to_page, Protection};
+
+// a single reservation of address space in which code is never writable and executable at the same time:
+// code is written into read-write pages, then every written page is flipped to read-execute on finalize.
+// the pages holding data are never executable, they stay read-write or read only
+pub(crate) struct CodeMemory {
+    base: *mut u8,
+    size: usize,
+    page_size: usize,
+    // protection of every page up to the highest page ever used, decommitted pages have no access
+    pages: Vec<Protection>,
+    // pages holding the data of globals, finalize leaves them alone
+    data_pages: HashSet<usize>,
+}
+
+impl CodeMemory {
+    pub(crate) fn new(size: usize) -> Option<Self> {
+        let size = round_to_page(size);
+        let base = reserve_pages(size)?;
+        Some(CodeMemory { base, size, page_size: page_size(), pages: vec![], data_pages: HashSet::new() })
+    }
+
+    pub(crate) fn base(&self) -> *mut u8 {
+        self.base
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        self.size
+    }
+
+    pub(crate) fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    // copy bytes at an offset of the reservation, the touched pages stay writable until finalize is called
+    pub(crate) fn write(&mut self, offset: usize, bytes: &[u8]) -> bool {
+        if !self.make_writable(offset, bytes.len()) {
+            return false;
+        }
+
+        unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), self.base.add(offset), bytes.len()) };
+        true
+    }
+
+    // commit or unprotect the pages of len bytes at an offset of the reservation
+    fn make_writable(&mut self, offset: usize, len: usize) -> bool {
+        if len == 0 {
+            return true;
+        }
+
+        if offset > self.size || len > self.size - offset {
+            return false;
+        }
+
+        let first_page = offset / self.page_size;
+        let last_page = (offset + len - 1) / self.page_size;
+        if self.pages.len() <= last_page {
+            self.pages.resize(last_page + 1, Protection::NoAccess);
+        }
+
+        for page in first_page..=last_page {
+            let ready = if self.pages[page] == Protection::NoAccess {
+                self.commit_page(page)
+            } else {
+                self.set_page_protection(page, Protection::ReadWrite)
+            };
+
+            if !ready {
+                return false;
+            }
+        }
+        true
+    }
+
+    // copy data at an offset of the reservation and zero the rest of its size, in pages that only ever hold data
+    pub(crate) fn write_data(&mut self, offset: usize, bytes: &[u8], size: usize, mutable: bool) -> bool {
+        if bytes.len() > size || !self.make_writable(offset, size) {
+            return false;
+        }
+
+        if size == 0 {
+            return true;
+        }
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), self.base.add(offset), bytes.len());
+            std::ptr::write_bytes(self.base.add(offset + bytes.len()), 0, size - bytes.len());
+        }
+
+        let first_page = offset / self.page_size;
+        let last_page = (offset + size - 1) / self.page_size;
+        (first_page..=last_page).all(|page| {
+            self.data_pages.insert(page);
+            mutable || self.set_page_protection(page, Protection::ReadOnly)
+        })
+    }
+
+    // make every written page of code executable and read only
+    pub(crate) fn finalize(&mut self) -> bool {
+        (0..self.pages.len()).all(|page| {
+            self.pages[page] != Protection::ReadWrite || self.data_pages.contains(&page) ||
+                self.set_page_protection(page, Protection::ReadExecute)
+        })
+    }
+
+    // overwrite code at the addresses, the pages are only writable for the duration of the copies
+    pub(crate) fn patch(&mut self, patches: &[(*mut u8, Vec<u8>)]) -> bool {
+        for (addr, bytes) in patches {
+            let offset = (*addr as usize).wrapping_sub(self.base as usize);
+            if offset >= self.size || !self.write(offset, bytes) {
+                self.finalize();
+                return false;
+            }
+        }
+
+        self.finalize()
+    }
+
+    // give a page that holds no code anymore back to the os, it is committed again on the next write
+    pub(crate) fn release_page(&mut self, page: usize) -> bool {
+        if page >= self.pages.len() || self.pages[page] == Protection::NoAccess {
+            return true;
+        }
+
+        let addr = unsafe { self.base.add(page * self.page_size) };
+        if decommit_pages(addr, self.page_size) {
+            self.pages[page] = Protection::NoAccess;
+            true
+        } else {
+            false
+        }
+    }
+
+    // give a page of data back to the os, once committed again it can hold code
+    pub(crate) fn release_data_page(&mut self, page: usize) -> bool {
+        self.data_pages.remove(&page);
+        self.release_page(page)
+    }
+
+    fn commit_page(&mut self, page: usize) -> bool {
+        let addr = unsafe { self.base.add(page * self.page_size) };
+        if commit_pages(addr, self.page_size, Protection::ReadWrite) {
+            self.pages[page] = Protection::ReadWrite;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn set_page_protection(&mut self, page: usize, protection: Protection) -> bool {
+        if self.pages[page] == protection {
+            return true;
+        }
+
+        let addr = unsafe { self.base.add(page * self.page_size) };
+        if protect_pages(addr, self.page_size, protection) {
+            self.pages[page] = protection;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Drop for CodeMemory {
+    fn drop(&mut self) {
+        free_pages(self.base, self.size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // mov eax, 42; ret
+    const RETURN_42: [u8; 6] = [0xB8, 42, 0, 0, 0, 0xC3];
+
+    fn call(memory: &CodeMemory, offset: usize) -> i32 {
+        let func: extern "C" fn() -> i32 = unsafe { std::mem::transmute(memory.base().add(offset)) };
+        func()
+    }
+
+    #[test]
+    fn written_pages_are_writable_until_finalized() {
+        let mut memory = CodeMemory::new(4 * page_size()).unwrap();
+        assert!(memory.write(0, &RETURN_42));
+        assert_eq!(memory.pages, vec![Protection::ReadWrite]);
+
+        assert!(memory.finalize());
+        assert_eq!(memory.pages, vec![Protection::ReadExecute]);
+        assert_eq!(call(&memory, 0), 42);
+    }
+
+    #[test]
+    fn data_pages_are_never_executable() {
+        let page = page_size();
+        let mut memory = CodeMemory::new(4 * page).unwrap();
+        assert!(memory.write(0, &RETURN_42));
+        assert!(memory.write_data(3 * page, &[1, 2, 3], 3, true));
+        assert!(memory.write_data(2 * page, &[4, 5, 6], 3, false));
+
+        assert!(memory.finalize());
+        assert_eq!(memory.pages, vec![Protection::ReadExecute, Protection::NoAccess, Protection::ReadOnly, Protection::ReadWrite]);
+    }
+
+    #[test]
+    fn patching_leaves_the_code_executable() {
+        let mut memory = CodeMemory::new(page_size()).unwrap();
+        assert!(memory.write(0, &RETURN_42));
+        assert!(memory.finalize());
+
+        let immediate = unsafe { memory.base().add(1) };
+        assert!(memory.patch(&[(immediate, vec![7, 0, 0, 0])]));
+        assert_eq!(memory.pages, vec![Protection::ReadExecute]);
+        assert_eq!(call(&memory, 0), 7);
+    }
+
+    #[test]
+    fn patching_outside_of_the_reservation_fails() {
+        let mut memory = CodeMemory::new(page_size()).unwrap();
+        assert!(memory.write(0, &RETURN_42));
+        assert!(memory.finalize());
+
+        let outside = unsafe { memory.base().add(page_size()) };
+        assert!(!memory.patch(&[(outside, vec![0])]));
+        assert_eq!(memory.pages, vec![Protection::ReadExecute]);
+        assert_eq!(call(&memory, 0), 42);
+    }
+
+    #[test]
+    fn released_pages_are_committed_again_on_write() {
+        let mut memory = CodeMemory::new(page_size()).unwrap();
+        assert!(memory.write(0, &RETURN_42));
+        assert!(memory.release_page(0));
+        assert_eq!(memory.pages, vec![Protection::NoAccess]);
+
+        assert!(memory.write(0, &RETURN_42));
+        assert!(memory.finalize());
+        assert_eq!(call(&memory, 0), 42);
+    }
+}
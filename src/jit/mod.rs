@@ -0,0 +1,4 @@
+pub(crate) mod os_memory;
+pub(crate) mod code_memory;
+pub(crate) mod code_arena;
+pub(crate) mod typed_func;
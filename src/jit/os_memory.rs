@@ -0,0 +1,246 @@
+use std::os::raw::c_void;
+
+// the protection that can be requested for a range of pages
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub(crate) enum Protection {
+    NoAccess,
+    ReadOnly,
+    ReadWrite,
+    ReadExecute,
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::os::raw::c_void;
+    use crate::jit::os_memory::Protection;
+
+    const PAGE_NOACCESS: u32 = 0x01;
+    const PAGE_READONLY: u32 = 0x02;
+    const PAGE_READWRITE: u32 = 0x04;
+    const PAGE_EXECUTE_READ: u32 = 0x20;
+    const MEM_COMMIT: u32 = 0x1000;
+    const MEM_RESERVE: u32 = 0x2000;
+    const MEM_DECOMMIT: u32 = 0x4000;
+    const MEM_RELEASE: u32 = 0x8000;
+
+    #[repr(C)]
+    struct SystemInfo {
+        processor_architecture: u16,
+        reserved: u16,
+        page_size: u32,
+        minimum_application_address: *mut c_void,
+        maximum_application_address: *mut c_void,
+        active_processor_mask: usize,
+        number_of_processors: u32,
+        processor_type: u32,
+        allocation_granularity: u32,
+        processor_level: u16,
+        processor_revision: u16,
+    }
+
+    extern "system" {
+        fn VirtualAlloc(lpAddress: *mut c_void, dwSize: usize, flAllocationType: u32, flProtect: u32) -> *mut c_void;
+        fn VirtualProtect(lpAddress: *mut c_void, dwSize: usize, flNewProtect: u32, lpflOldProtect: *mut u32) -> i32;
+        fn VirtualFree(lpAddress: *mut c_void, dwSize: usize, dwFreeType: u32) -> i32;
+        fn GetSystemInfo(lpSystemInfo: *mut SystemInfo);
+    }
+
+    fn encode_protection(protection: Protection) -> u32 {
+        match protection {
+            Protection::NoAccess => { PAGE_NOACCESS }
+            Protection::ReadOnly => { PAGE_READONLY }
+            Protection::ReadWrite => { PAGE_READWRITE }
+            Protection::ReadExecute => { PAGE_EXECUTE_READ }
+        }
+    }
+
+    pub(super) fn page_size() -> usize {
+        let mut info = std::mem::MaybeUninit::<SystemInfo>::uninit();
+        unsafe {
+            GetSystemInfo(info.as_mut_ptr());
+            info.assume_init().page_size as usize
+        }
+    }
+
+    pub(super) fn reserve(size: usize) -> *mut c_void {
+        unsafe { VirtualAlloc(std::ptr::null_mut(), size, MEM_RESERVE, PAGE_NOACCESS) }
+    }
+
+    pub(super) fn commit(addr: *mut c_void, size: usize, protection: Protection) -> bool {
+        unsafe { !VirtualAlloc(addr, size, MEM_COMMIT, encode_protection(protection)).is_null() }
+    }
+
+    pub(super) fn decommit(addr: *mut c_void, size: usize) -> bool {
+        unsafe { VirtualFree(addr, size, MEM_DECOMMIT) != 0 }
+    }
+
+    pub(super) fn protect(addr: *mut c_void, size: usize, protection: Protection) -> bool {
+        let mut old_protection: u32 = 0;
+        unsafe { VirtualProtect(addr, size, encode_protection(protection), &mut old_protection) != 0 }
+    }
+
+    pub(super) fn free(addr: *mut c_void, _size: usize) -> bool {
+        // releasing a reservation requires a size of 0
+        unsafe { VirtualFree(addr, 0, MEM_RELEASE) != 0 }
+    }
+}
+
+#[cfg(unix)]
+mod platform {
+    use std::os::raw::{c_int, c_long, c_void};
+    use crate::jit::os_memory::Protection;
+
+    const PROT_NONE: c_int = 0x0;
+    const PROT_READ: c_int = 0x1;
+    const PROT_WRITE: c_int = 0x2;
+    const PROT_EXEC: c_int = 0x4;
+    const MAP_PRIVATE: c_int = 0x02;
+    #[cfg(target_os = "macos")]
+    const MAP_ANONYMOUS: c_int = 0x1000;
+    #[cfg(not(target_os = "macos"))]
+    const MAP_ANONYMOUS: c_int = 0x20;
+    #[cfg(target_os = "macos")]
+    const MAP_NORESERVE: c_int = 0x40;
+    #[cfg(not(target_os = "macos"))]
+    const MAP_NORESERVE: c_int = 0x4000;
+    const MADV_DONTNEED: c_int = 4;
+    #[cfg(target_os = "macos")]
+    const SC_PAGESIZE: c_int = 29;
+    #[cfg(not(target_os = "macos"))]
+    const SC_PAGESIZE: c_int = 30;
+
+    extern "C" {
+        fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: i64) -> *mut c_void;
+        fn mprotect(addr: *mut c_void, len: usize, prot: c_int) -> c_int;
+        fn munmap(addr: *mut c_void, len: usize) -> c_int;
+        fn madvise(addr: *mut c_void, len: usize, advice: c_int) -> c_int;
+        fn sysconf(name: c_int) -> c_long;
+    }
+
+    fn encode_protection(protection: Protection) -> c_int {
+        match protection {
+            Protection::NoAccess => { PROT_NONE }
+            Protection::ReadOnly => { PROT_READ }
+            Protection::ReadWrite => { PROT_READ | PROT_WRITE }
+            Protection::ReadExecute => { PROT_READ | PROT_EXEC }
+        }
+    }
+
+    pub(super) fn page_size() -> usize {
+        unsafe { sysconf(SC_PAGESIZE) as usize }
+    }
+
+    pub(super) fn reserve(size: usize) -> *mut c_void {
+        let flags = MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE;
+        let addr = unsafe { mmap(std::ptr::null_mut(), size, PROT_NONE, flags, -1, 0) };
+
+        // mmap reports failure with MAP_FAILED (-1) instead of null
+        if addr as isize == -1 {
+            std::ptr::null_mut()
+        } else {
+            addr
+        }
+    }
+
+    // reserved pages are backed lazily by the kernel, committing is only a matter of access rights
+    pub(super) fn commit(addr: *mut c_void, size: usize, protection: Protection) -> bool {
+        protect(addr, size, protection)
+    }
+
+    pub(super) fn decommit(addr: *mut c_void, size: usize) -> bool {
+        unsafe { madvise(addr, size, MADV_DONTNEED) == 0 && mprotect(addr, size, PROT_NONE) == 0 }
+    }
+
+    pub(super) fn protect(addr: *mut c_void, size: usize, protection: Protection) -> bool {
+        unsafe { mprotect(addr, size, encode_protection(protection)) == 0 }
+    }
+
+    pub(super) fn free(addr: *mut c_void, size: usize) -> bool {
+        unsafe { munmap(addr, size) == 0 }
+    }
+}
+
+pub(crate) fn page_size() -> usize {
+    platform::page_size()
+}
+
+// round a size up to a whole number of pages
+pub(crate) fn round_to_page(size: usize) -> usize {
+    let page = page_size();
+    size.max(1).div_ceil(page) * page
+}
+
+// reserve a range of address space that is not accessible until its pages are committed
+pub(crate) fn reserve_pages(size: usize) -> Option<*mut u8> {
+    let addr = platform::reserve(round_to_page(size));
+    if addr.is_null() {
+        None
+    } else {
+        Some(addr as *mut u8)
+    }
+}
+
+pub(crate) fn commit_pages(addr: *mut u8, size: usize, protection: Protection) -> bool {
+    platform::commit(addr as *mut c_void, round_to_page(size), protection)
+}
+
+// give the physical memory back to the os while keeping the address range reserved
+pub(crate) fn decommit_pages(addr: *mut u8, size: usize) -> bool {
+    platform::decommit(addr as *mut c_void, round_to_page(size))
+}
+
+pub(crate) fn protect_pages(addr: *mut u8, size: usize, protection: Protection) -> bool {
+    platform::protect(addr as *mut c_void, round_to_page(size), protection)
+}
+
+pub(crate) fn free_pages(addr: *mut u8, size: usize) -> bool {
+    platform::free(addr as *mut c_void, round_to_page(size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_are_rounded_to_whole_pages() {
+        let page = page_size();
+        assert!(page.is_power_of_two());
+        assert_eq!(round_to_page(0), page);
+        assert_eq!(round_to_page(1), page);
+        assert_eq!(round_to_page(page), page);
+        assert_eq!(round_to_page(page + 1), 2 * page);
+    }
+
+    #[test]
+    fn committed_pages_can_run_code() {
+        let page = page_size();
+        let addr = reserve_pages(2 * page).unwrap();
+        assert_eq!(addr as usize % page, 0);
+
+        let second = unsafe { addr.add(page) };
+        assert!(commit_pages(second, page, Protection::ReadWrite));
+        // mov eax, 7; ret
+        let code = [0xB8, 7, 0, 0, 0, 0xC3];
+        unsafe { std::ptr::copy_nonoverlapping(code.as_ptr(), second, code.len()) };
+        assert!(protect_pages(second, page, Protection::ReadExecute));
+
+        let func: extern "C" fn() -> i32 = unsafe { std::mem::transmute(second) };
+        assert_eq!(func(), 7);
+
+        assert!(decommit_pages(second, page));
+        assert!(free_pages(addr, 2 * page));
+    }
+
+    #[test]
+    fn decommitted_pages_come_back_zeroed() {
+        let page = page_size();
+        let addr = reserve_pages(page).unwrap();
+        assert!(commit_pages(addr, page, Protection::ReadWrite));
+        unsafe { *addr = 42 };
+
+        assert!(decommit_pages(addr, page));
+        assert!(commit_pages(addr, page, Protection::ReadOnly));
+        assert_eq!(unsafe { *addr }, 0);
+        assert!(free_pages(addr, page));
+    }
+}
@@ -0,0 +1,147 @@
+use std::marker::PhantomData;
+use crate::compiler::Compiler;
+use crate::lang::lang_type::Type;
+
+// a rust type that can cross the boundary between rust and jitted code
+pub trait JitType {
+    fn jit_type() -> Type;
+}
+
+macro_rules! impl_jit_type {
+    ($($rust_type:ty => $jit_type:expr),* $(,)?) => {
+        $(impl JitType for $rust_type {
+            fn jit_type() -> Type { $jit_type }
+        })*
+    };
+}
+
+impl_jit_type! {
+    () => Type::void(),
+    bool => Type::bool(),
+    i8 => Type::i8(),
+    u8 => Type::i8(),
+    i16 => Type::i16(),
+    u16 => Type::i16(),
+    i32 => Type::i32(),
+    u32 => Type::i32(),
+    i64 => Type::i64(),
+    u64 => Type::i64(),
+    f32 => Type::f32(),
+    f64 => Type::f64(),
+}
+
+impl<T> JitType for *const T {
+    fn jit_type() -> Type { Type::ptr() }
+}
+
+impl<T> JitType for *mut T {
+    fn jit_type() -> Type { Type::ptr() }
+}
+
+// a rust fn pointer type that describes the signature of a jitted function, like fn(i64, f64) -> i64
+pub trait JitSignature {
+    fn args() -> Vec<Type>;
+    fn return_type() -> Type;
+}
+
+// a jitted function with a checked signature, it cannot outlive the compiler that owns its code
+pub struct TypedFunc<'a, F: JitSignature> {
+    ptr: *const u8,
+    _compiler: PhantomData<&'a Compiler>,
+    _signature: PhantomData<F>,
+}
+
+impl<'a, F: JitSignature> TypedFunc<'a, F> {
+    // the signature must have been checked against the function the code belongs to
+    pub(crate) fn new(ptr: *const u8) -> Self {
+        TypedFunc { ptr, _compiler: PhantomData, _signature: PhantomData }
+    }
+
+    pub fn ptr(&self) -> *const u8 {
+        self.ptr
+    }
+
+    pub(crate) fn matches(args: &[Type], return_type: &Type) -> bool {
+        F::args() == args && F::return_type() == *return_type
+    }
+}
+
+macro_rules! impl_jit_signature {
+    ($($arg:ident),*) => {
+        impl<R: JitType, $($arg: JitType),*> JitSignature for fn($($arg),*) -> R {
+            fn args() -> Vec<Type> { vec![$($arg::jit_type()),*] }
+            fn return_type() -> Type { R::jit_type() }
+        }
+
+        impl<'a, R: JitType, $($arg: JitType),*> TypedFunc<'a, fn($($arg),*) -> R> {
+            #[allow(non_snake_case, clippy::too_many_arguments)]
+            pub fn call(&self, $($arg: $arg),*) -> R {
+                // the signature was checked when the handle was created and the code lives as long as 'a
+                let func: extern "C" fn($($arg),*) -> R = unsafe { std::mem::transmute(self.ptr) };
+                func($($arg),*)
+            }
+        }
+    };
+}
+
+impl_jit_signature!();
+impl_jit_signature!(A);
+impl_jit_signature!(A, B);
+impl_jit_signature!(A, B, C);
+impl_jit_signature!(A, B, C, D);
+impl_jit_signature!(A, B, C, D, E);
+impl_jit_signature!(A, B, C, D, E, G);
+impl_jit_signature!(A, B, C, D, E, G, H);
+impl_jit_signature!(A, B, C, D, E, G, H, I);
+impl_jit_signature!(A, B, C, D, E, G, H, I, J);
+impl_jit_signature!(A, B, C, D, E, G, H, I, J, K);
+impl_jit_signature!(A, B, C, D, E, G, H, I, J, K, L);
+impl_jit_signature!(A, B, C, D, E, G, H, I, J, K, L, M);
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::Compiler;
+    use crate::error::CowError;
+    use crate::lang::lang_type::Type;
+
+    fn add_identity(compiler: &mut Compiler, name: &str, value_type: Type) {
+        let builder = compiler.add_func(name, std::slice::from_ref(&value_type), value_type.clone()).unwrap().builder();
+        let param = builder.param(0).unwrap();
+        builder.ret(param);
+    }
+
+    #[test]
+    fn the_signature_is_checked() {
+        let mut compiler = Compiler::new().unwrap();
+        add_identity(&mut compiler, "f", Type::i64());
+        assert_eq!(compiler.get_typed::<fn(i64) -> i64>("f").err(), Some(CowError::NotJitted("f".to_string())));
+
+        compiler.jit().unwrap();
+        assert!(compiler.get_typed::<fn(i64) -> i64>("f").is_ok());
+        assert!(compiler.get_typed::<fn(u64) -> u64>("f").is_ok());
+        assert_eq!(compiler.get_typed::<fn(i32) -> i64>("f").err(), Some(CowError::SignatureMismatch("f".to_string())));
+        assert_eq!(compiler.get_typed::<fn(i64)>("f").err(), Some(CowError::SignatureMismatch("f".to_string())));
+        assert_eq!(compiler.get_typed::<fn() -> i64>("f").err(), Some(CowError::SignatureMismatch("f".to_string())));
+        assert_eq!(compiler.get_typed::<fn() -> i64>("g").err(), Some(CowError::UnknownFunction("g".to_string())));
+    }
+
+    #[test]
+    fn rust_types_are_passed_as_their_jit_type() {
+        let mut compiler = Compiler::new().unwrap();
+        add_identity(&mut compiler, "bool", Type::bool());
+        add_identity(&mut compiler, "u8", Type::i8());
+        add_identity(&mut compiler, "i16", Type::i16());
+        add_identity(&mut compiler, "f32", Type::f32());
+        add_identity(&mut compiler, "ptr", Type::ptr());
+        compiler.jit().unwrap();
+
+        assert!(compiler.get_typed::<fn(bool) -> bool>("bool").unwrap().call(true));
+        assert_eq!(compiler.get_typed::<fn(u8) -> u8>("u8").unwrap().call(200), 200);
+        assert_eq!(compiler.get_typed::<fn(i16) -> i16>("i16").unwrap().call(-300), -300);
+        assert_eq!(compiler.get_typed::<fn(f32) -> f32>("f32").unwrap().call(2.5), 2.5);
+        let value = 7u64;
+        let ptr = compiler.get_typed::<fn(*const u64) -> *const u64>("ptr").unwrap();
+        assert_eq!(ptr.call(&value), &value as *const u64);
+        assert!(!ptr.ptr().is_null());
+    }
+}
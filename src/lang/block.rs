@@ -1,31 +1,50 @@
 use crate::lang::instr::Instr;
+use crate::lang::value::Value;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub struct Block {
     id: usize,
+    // the builder that created the block
+    func_id: usize,
 }
 
 impl Block {
-    pub(crate) fn new(id: usize) -> Self { Block { id } }
+    pub(crate) fn new(func_id: usize, id: usize) -> Self { Block { id, func_id } }
 
     pub(crate) fn get_id(&self) -> usize { self.id }
+
+    pub(crate) fn func_id(&self) -> usize { self.func_id }
 }
 
 pub(crate) struct LangBlock {
+    // the values given by the branches to the block, defined at its start
+    params: Vec<Value>,
     instructions: Vec<Instr>,
     offset: usize,
 }
 
 impl LangBlock {
-    pub(crate) fn new() -> Self {
-        LangBlock { instructions: vec![], offset: 0 }
+    pub(crate) fn new(params: Vec<Value>) -> Self {
+        LangBlock { params, instructions: vec![], offset: 0 }
+    }
+
+    pub(crate) fn params(&self) -> &Vec<Value> {
+        &self.params
+    }
+
+    pub(crate) fn params_mut(&mut self) -> &mut Vec<Value> {
+        &mut self.params
     }
 
     pub(crate) fn add_instr(&mut self, instruction: Instr) {
         self.instructions.push(instruction)
     }
 
-    pub(crate) fn instructions(&mut self) -> &mut Vec<Instr> {
+    pub(crate) fn instructions(&self) -> &Vec<Instr> {
+        &self.instructions
+    }
+
+    pub(crate) fn instructions_mut(&mut self) -> &mut Vec<Instr> {
         &mut self.instructions
     }
 
@@ -1,199 +1,1292 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::error::CowError;
 use crate::lang::block::{Block, LangBlock};
-use crate::lang::instr::Instr;
+use crate::lang::global::Globals;
+use crate::lang::instr::{Cast, Instr};
 use crate::lang::lang_type::Type;
+use crate::lang::signature::Signatures;
 use crate::lang::value::Value;
+use crate::lang::variable::Variable;
+
+// gives every builder its own id, so that a value can be traced back to its function
+static NEXT_FUNC_ID: AtomicUsize = AtomicUsize::new(0);
+
+// the most bytes the stack slots of a function can take, the offsets of its frame always fit in an i32 below it
+pub(crate) const MAX_STACK_SLOTS_SIZE: u32 = 1 << 30;
+
+// the bytes the stack slots take with one more, counting the padding its alignment can need wherever it is placed.
+// None past MAX_STACK_SLOTS_SIZE
+pub(crate) fn add_stack_slot(slots_size: u32, size: u32, align: u32) -> Option<u32> {
+    let slots_size = slots_size.checked_add(size)?.checked_add(align - 1)?;
+    (slots_size <= MAX_STACK_SLOTS_SIZE).then_some(slots_size)
+}
 
 pub struct Builder {
+    func_id: usize,
     blocks: Vec<LangBlock>,
     values: Vec<Value>,
+    params: Vec<Value>,
     current_block: usize,
+    signatures: Signatures,
+    globals: Globals,
+    // the type of every declared variable
+    variables: Vec<Type>,
+    // the value of a variable at the end of a block, by variable and block
+    var_defs: HashMap<(usize, usize), Value>,
+    // blocks whose predecessors are all known
+    sealed_blocks: HashSet<usize>,
+    // the parameters created for variables read in blocks that are not sealed yet, they get their arguments on seal
+    incomplete_params: HashMap<usize, Vec<(Variable, Value)>>,
+    // the block parameters created for variables, they always come after the parameters of the block
+    var_params: HashSet<usize>,
+    // values replaced by another one when a useless parameter was removed
+    aliases: HashMap<usize, Value>,
+    // the variables being read through a block with a single predecessor, by variable and block
+    single_pred_reads: HashSet<(usize, usize)>,
+    // the parameters that did not get the arguments of every predecessor yet, they cannot be removed until then
+    pending_params: HashSet<usize>,
+    // the bytes the stack slots can take in the frame, see add_stack_slot
+    stack_slots_size: u32,
 }
 
 impl Builder {
-    pub fn new() -> Self {
-        let mut builder = Builder { blocks: vec![], values: vec![], current_block: 0 };
-        builder.blocks.push(LangBlock::new());
+    pub(crate) fn new(args: &[Type], signatures: Signatures, globals: Globals) -> Self {
+        let mut builder = Builder {
+            func_id: NEXT_FUNC_ID.fetch_add(1, Ordering::Relaxed),
+            blocks: vec![],
+            values: vec![],
+            params: vec![],
+            current_block: 0,
+            signatures,
+            globals,
+            variables: vec![],
+            var_defs: HashMap::new(),
+            sealed_blocks: HashSet::new(),
+            incomplete_params: HashMap::new(),
+            var_params: HashSet::new(),
+            aliases: HashMap::new(),
+            single_pred_reads: HashSet::new(),
+            pending_params: HashSet::new(),
+            stack_slots_size: 0,
+        };
+        builder.blocks.push(LangBlock::new(vec![]));
+        // nothing can branch to the entry block
+        builder.sealed_blocks.insert(0);
+
+        // the parameters are the first values of the function, they are bound at the entry of the function
+        for arg in args {
+            let param = Value::new(builder.func_id, builder.values.len(), arg.clone());
+            builder.values.push(param.clone());
+            builder.params.push(param);
+        }
+
         builder
     }
 
+    pub fn param(&self, index: usize) -> Result<Value, CowError> {
+        self.params.get(index).cloned().ok_or(CowError::UnknownParam(index))
+    }
+
+    pub fn params(&self) -> &Vec<Value> {
+        &self.params
+    }
+
     pub fn create_block(&mut self) -> Block {
-        self.blocks.push(LangBlock::new());
-        Block::new(self.blocks.len() - 1)
+        self.blocks.push(LangBlock::new(vec![]));
+        Block::new(self.func_id, self.blocks.len() - 1)
+    }
+
+    // a block receiving a value for each of its parameters from every branch to it,
+    // which is how a value coming from different predecessors is merged
+    pub fn create_block_with_params(&mut self, params: &[Type]) -> Result<Block, CowError> {
+        if params.contains(&Type::void()) {
+            return Err(CowError::InvalidType("a block parameter cannot be void".to_string()));
+        }
+
+        let params = params.iter().map(|param_type| {
+            let param = Value::new(self.func_id, self.values.len(), param_type.clone());
+            self.values.push(param.clone());
+            param
+        }).collect();
+
+        self.blocks.push(LangBlock::new(params));
+        Ok(Block::new(self.func_id, self.blocks.len() - 1))
     }
 
-    pub fn set_current_block(&mut self, block: Block) {
-        self.current_block = block.get_id()
+    pub fn block_param(&self, block: Block, index: usize) -> Result<Value, CowError> {
+        self.block_params(block)?.get(index).cloned().ok_or(CowError::UnknownParam(index))
+    }
+
+    pub fn block_params(&self, block: Block) -> Result<&Vec<Value>, CowError> {
+        let block_id = self.check_block(block)?;
+        Ok(self.blocks[block_id].params())
+    }
+
+    pub fn set_current_block(&mut self, block: Block) -> Result<(), CowError> {
+        self.current_block = self.check_block(block)?;
+        Ok(())
+    }
+
+    // the index of a block created by this builder
+    fn check_block(&self, block: Block) -> Result<usize, CowError> {
+        if block.func_id() != self.func_id || block.get_id() >= self.blocks.len() {
+            return Err(CowError::UnknownBlock(block.get_id()));
+        }
+        Ok(block.get_id())
     }
 
     pub fn const_i8(&mut self, value: i8) -> Value {
-        let new_value = Value::new(self.values.len(), Type::i8());
+        let new_value = Value::new(self.func_id, self.values.len(), Type::i8());
         self.values.push(new_value.clone());
         let instr = Instr::ConstInt8 { const_value: value, gen_value: new_value.clone() };
-        self.blocks[self.current_block].add_instr(instr);
+        self.add_instr(instr);
         new_value
     }
 
     pub fn const_i16(&mut self, value: i16) -> Value {
-        let new_value = Value::new(self.values.len(), Type::i16());
+        let new_value = Value::new(self.func_id, self.values.len(), Type::i16());
         self.values.push(new_value.clone());
         let instr = Instr::ConstInt16 { const_value: value, gen_value: new_value.clone() };
-        self.blocks[self.current_block].add_instr(instr);
+        self.add_instr(instr);
         new_value
     }
 
     pub fn const_i32(&mut self, value: i32) -> Value {
-        let new_value = Value::new(self.values.len(), Type::i32());
+        let new_value = Value::new(self.func_id, self.values.len(), Type::i32());
         self.values.push(new_value.clone());
         let instr = Instr::ConstInt32 { const_value: value, gen_value: new_value.clone() };
-        self.blocks[self.current_block].add_instr(instr);
+        self.add_instr(instr);
         new_value
     }
 
     pub fn const_i64(&mut self, value: i64) -> Value {
-        let new_value = Value::new(self.values.len(), Type::i64());
+        let new_value = Value::new(self.func_id, self.values.len(), Type::i64());
         self.values.push(new_value.clone());
         let instr = Instr::ConstInt64 { const_value: value, gen_value: new_value.clone() };
-        self.blocks[self.current_block].add_instr(instr);
+        self.add_instr(instr);
         new_value
     }
 
     pub fn const_f32(&mut self, value: f32) -> Value {
-        let new_value = Value::new(self.values.len(), Type::f64());
+        let new_value = Value::new(self.func_id, self.values.len(), Type::f32());
         self.values.push(new_value.clone());
         let instr = Instr::ConstInt32 { const_value: i32::from_le_bytes(value.to_le_bytes()), gen_value: new_value.clone() };
-        self.blocks[self.current_block].add_instr(instr);
+        self.add_instr(instr);
         new_value
     }
 
     pub fn const_f64(&mut self, value: f64) -> Value {
-        let new_value = Value::new(self.values.len(), Type::f64());
+        let new_value = Value::new(self.func_id, self.values.len(), Type::f64());
         self.values.push(new_value.clone());
         let instr = Instr::ConstInt64 { const_value: i64::from_le_bytes(value.to_le_bytes()), gen_value: new_value.clone() };
-        self.blocks[self.current_block].add_instr(instr);
+        self.add_instr(instr);
         new_value
     }
 
     pub fn const_ptr(&mut self, value: usize) -> Value {
-        let new_value = Value::new(self.values.len(), Type::ptr());
+        let new_value = Value::new(self.func_id, self.values.len(), Type::ptr());
         self.values.push(new_value.clone());
         let instr = Instr::ConstPtr { const_value: value, gen_value: new_value.clone() };
-        self.blocks[self.current_block].add_instr(instr);
+        self.add_instr(instr);
         new_value
     }
 
     pub fn add(&mut self, left_value: Value, right_value: Value) -> Value {
-        let new_value = Value::new(self.values.len(), left_value.get_type());
+        let new_value = Value::new(self.func_id, self.values.len(), left_value.get_type());
         self.values.push(new_value.clone());
         let instr = Instr::Add { left_value, right_value, gen_value: new_value.clone() };
-        self.blocks[self.current_block].add_instr(instr);
+        self.add_instr(instr);
         new_value
     }
 
 
     pub fn sub(&mut self, left_value: Value, right_value: Value) -> Value {
-        let new_value = Value::new(self.values.len(), left_value.get_type());
+        let new_value = Value::new(self.func_id, self.values.len(), left_value.get_type());
         self.values.push(new_value.clone());
         let instr = Instr::Sub { left_value, right_value, gen_value: new_value.clone() };
-        self.blocks[self.current_block].add_instr(instr);
+        self.add_instr(instr);
         new_value
     }
 
     pub fn mul(&mut self, left_value: Value, right_value: Value) -> Value {
-        let new_value = Value::new(self.values.len(), left_value.get_type());
+        let new_value = Value::new(self.func_id, self.values.len(), left_value.get_type());
         self.values.push(new_value.clone());
         let instr = Instr::Mul { left_value, right_value, gen_value: new_value.clone() };
-        self.blocks[self.current_block].add_instr(instr);
+        self.add_instr(instr);
         new_value
     }
 
     pub fn div(&mut self, left_value: Value, right_value: Value) -> Value {
-        let new_value = Value::new(self.values.len(), left_value.get_type());
+        let new_value = Value::new(self.func_id, self.values.len(), left_value.get_type());
         self.values.push(new_value.clone());
         let instr = Instr::Div { left_value, right_value, gen_value: new_value.clone() };
-        self.blocks[self.current_block].add_instr(instr);
+        self.add_instr(instr);
+        new_value
+    }
+
+    pub fn neg(&mut self, value: Value) -> Value {
+        let new_value = Value::new(self.func_id, self.values.len(), value.get_type());
+        self.values.push(new_value.clone());
+        let instr = Instr::Neg { value, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        new_value
+    }
+
+    // absolute value of a float
+    pub fn abs(&mut self, value: Value) -> Result<Value, CowError> {
+        if !value.get_type().is_float() {
+            return Err(CowError::InvalidInstr(format!("abs only applies to floats, not to {}", value.get_type())));
+        }
+        let new_value = Value::new(self.func_id, self.values.len(), value.get_type());
+        self.values.push(new_value.clone());
+        let instr = Instr::Abs { value, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        Ok(new_value)
+    }
+
+    // division of both values taken as unsigned
+    pub fn udiv(&mut self, left_value: Value, right_value: Value) -> Value {
+        let new_value = Value::new(self.func_id, self.values.len(), left_value.get_type());
+        self.values.push(new_value.clone());
+        let instr = Instr::UDiv { left_value, right_value, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        new_value
+    }
+
+    // remainder of the signed division, it has the sign of the left value
+    pub fn srem(&mut self, left_value: Value, right_value: Value) -> Value {
+        let new_value = Value::new(self.func_id, self.values.len(), left_value.get_type());
+        self.values.push(new_value.clone());
+        let instr = Instr::SRem { left_value, right_value, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        new_value
+    }
+
+    // remainder of the division of both values taken as unsigned
+    pub fn urem(&mut self, left_value: Value, right_value: Value) -> Value {
+        let new_value = Value::new(self.func_id, self.values.len(), left_value.get_type());
+        self.values.push(new_value.clone());
+        let instr = Instr::URem { left_value, right_value, gen_value: new_value.clone() };
+        self.add_instr(instr);
         new_value
     }
 
     pub fn eq(&mut self, left_value: Value, right_value: Value) -> Value {
-        let new_value = Value::new(self.values.len(), left_value.get_type());
+        let new_value = Value::new(self.func_id, self.values.len(), Type::bool());
         self.values.push(new_value.clone());
         let instr = Instr::Eq { left_value, right_value, gen_value: new_value.clone() };
-        self.blocks[self.current_block].add_instr(instr);
+        self.add_instr(instr);
         new_value
     }
 
     pub fn diff(&mut self, left_value: Value, right_value: Value) -> Value {
-        let new_value = Value::new(self.values.len(), left_value.get_type());
+        let new_value = Value::new(self.func_id, self.values.len(), Type::bool());
         self.values.push(new_value.clone());
         let instr = Instr::Diff { left_value, right_value, gen_value: new_value.clone() };
-        self.blocks[self.current_block].add_instr(instr);
+        self.add_instr(instr);
         new_value
     }
 
     pub fn larger(&mut self, left_value: Value, right_value: Value) -> Value {
-        let new_value = Value::new(self.values.len(), left_value.get_type());
+        let new_value = Value::new(self.func_id, self.values.len(), Type::bool());
         self.values.push(new_value.clone());
         let instr = Instr::Larger { left_value, right_value, gen_value: new_value.clone() };
-        self.blocks[self.current_block].add_instr(instr);
+        self.add_instr(instr);
         new_value
     }
 
     pub fn larger_eq(&mut self, left_value: Value, right_value: Value) -> Value {
-        let new_value = Value::new(self.values.len(), left_value.get_type());
+        let new_value = Value::new(self.func_id, self.values.len(), Type::bool());
         self.values.push(new_value.clone());
         let instr = Instr::LargerEq { left_value, right_value, gen_value: new_value.clone() };
-        self.blocks[self.current_block].add_instr(instr);
+        self.add_instr(instr);
         new_value
     }
 
     pub fn smaller(&mut self, left_value: Value, right_value: Value) -> Value {
-        let new_value = Value::new(self.values.len(), left_value.get_type());
+        let new_value = Value::new(self.func_id, self.values.len(), Type::bool());
         self.values.push(new_value.clone());
         let instr = Instr::Smaller { left_value, right_value, gen_value: new_value.clone() };
-        self.blocks[self.current_block].add_instr(instr);
+        self.add_instr(instr);
         new_value
     }
 
     pub fn smaller_eq(&mut self, left_value: Value, right_value: Value) -> Value {
-        let new_value = Value::new(self.values.len(), left_value.get_type());
+        let new_value = Value::new(self.func_id, self.values.len(), Type::bool());
         self.values.push(new_value.clone());
         let instr = Instr::SmallerEq { left_value, right_value, gen_value: new_value.clone() };
-        self.blocks[self.current_block].add_instr(instr);
+        self.add_instr(instr);
         new_value
     }
 
-    pub fn br(&mut self, block: Block) {
-        let instr = Instr::Br { block_to_br: block };
-        self.blocks[self.current_block].add_instr(instr);
+    // whether neither float is nan, the comparisons of floats are false with a nan but for diff
+    pub fn ordered(&mut self, left_value: Value, right_value: Value) -> Result<Value, CowError> {
+        if !left_value.get_type().is_float() {
+            return Err(CowError::InvalidInstr(format!("ordered only applies to floats, not to {}", left_value.get_type())));
+        }
+        let new_value = Value::new(self.func_id, self.values.len(), Type::bool());
+        self.values.push(new_value.clone());
+        let instr = Instr::Ordered { left_value, right_value, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        Ok(new_value)
     }
 
-    pub fn cond_br(&mut self, value_cond: Value, block_true: Block, block_false: Block) {
-        let instr = Instr::CondBr { block_to_br_true: block_true, block_to_br_false: block_false, value_cond };
-        self.blocks[self.current_block].add_instr(instr);
+    // whether one of the floats is nan
+    pub fn unordered(&mut self, left_value: Value, right_value: Value) -> Result<Value, CowError> {
+        if !left_value.get_type().is_float() {
+            return Err(CowError::InvalidInstr(format!("unordered only applies to floats, not to {}", left_value.get_type())));
+        }
+        let new_value = Value::new(self.func_id, self.values.len(), Type::bool());
+        self.values.push(new_value.clone());
+        let instr = Instr::Unordered { left_value, right_value, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        Ok(new_value)
+    }
+
+    // comparisons of both values taken as unsigned
+    pub fn ugt(&mut self, left_value: Value, right_value: Value) -> Value {
+        let new_value = Value::new(self.func_id, self.values.len(), Type::bool());
+        self.values.push(new_value.clone());
+        let instr = Instr::ULarger { left_value, right_value, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        new_value
+    }
+
+    pub fn uge(&mut self, left_value: Value, right_value: Value) -> Value {
+        let new_value = Value::new(self.func_id, self.values.len(), Type::bool());
+        self.values.push(new_value.clone());
+        let instr = Instr::ULargerEq { left_value, right_value, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        new_value
+    }
+
+    pub fn ult(&mut self, left_value: Value, right_value: Value) -> Value {
+        let new_value = Value::new(self.func_id, self.values.len(), Type::bool());
+        self.values.push(new_value.clone());
+        let instr = Instr::USmaller { left_value, right_value, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        new_value
+    }
+
+    pub fn ule(&mut self, left_value: Value, right_value: Value) -> Value {
+        let new_value = Value::new(self.func_id, self.values.len(), Type::bool());
+        self.values.push(new_value.clone());
+        let instr = Instr::USmallerEq { left_value, right_value, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        new_value
+    }
+
+    // logical shift right, the left value is shifted in zeros
+    pub fn lshr(&mut self, left_value: Value, right_value: Value) -> Value {
+        let new_value = Value::new(self.func_id, self.values.len(), left_value.get_type());
+        self.values.push(new_value.clone());
+        let instr = Instr::LShr { left_value, right_value, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        new_value
+    }
+
+    // arithmetic shift right, the left value keeps its sign
+    pub fn ashr(&mut self, left_value: Value, right_value: Value) -> Value {
+        let new_value = Value::new(self.func_id, self.values.len(), left_value.get_type());
+        self.values.push(new_value.clone());
+        let instr = Instr::AShr { left_value, right_value, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        new_value
+    }
+
+    pub fn and(&mut self, left_value: Value, right_value: Value) -> Value {
+        let new_value = Value::new(self.func_id, self.values.len(), left_value.get_type());
+        self.values.push(new_value.clone());
+        let instr = Instr::And { left_value, right_value, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        new_value
+    }
+
+    pub fn or(&mut self, left_value: Value, right_value: Value) -> Value {
+        let new_value = Value::new(self.func_id, self.values.len(), left_value.get_type());
+        self.values.push(new_value.clone());
+        let instr = Instr::Or { left_value, right_value, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        new_value
+    }
+
+    pub fn xor(&mut self, left_value: Value, right_value: Value) -> Value {
+        let new_value = Value::new(self.func_id, self.values.len(), left_value.get_type());
+        self.values.push(new_value.clone());
+        let instr = Instr::Xor { left_value, right_value, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        new_value
+    }
+
+    // flips every bit of an integer, or the value of a bool
+    pub fn not(&mut self, value: Value) -> Value {
+        let new_value = Value::new(self.func_id, self.values.len(), value.get_type());
+        self.values.push(new_value.clone());
+        let instr = Instr::Not { value, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        new_value
+    }
+
+    pub fn shl(&mut self, left_value: Value, right_value: Value) -> Value {
+        let new_value = Value::new(self.func_id, self.values.len(), left_value.get_type());
+        self.values.push(new_value.clone());
+        let instr = Instr::Shl { left_value, right_value, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        new_value
+    }
+
+    // rotations within the width of the left value
+    pub fn rotl(&mut self, left_value: Value, right_value: Value) -> Value {
+        let new_value = Value::new(self.func_id, self.values.len(), left_value.get_type());
+        self.values.push(new_value.clone());
+        let instr = Instr::Rotl { left_value, right_value, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        new_value
+    }
+
+    pub fn rotr(&mut self, left_value: Value, right_value: Value) -> Value {
+        let new_value = Value::new(self.func_id, self.values.len(), left_value.get_type());
+        self.values.push(new_value.clone());
+        let instr = Instr::Rotr { left_value, right_value, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        new_value
+    }
+
+    // shifts and rotations by a constant amount
+    pub fn shl_imm(&mut self, value: Value, amount: u8) -> Value {
+        let new_value = Value::new(self.func_id, self.values.len(), value.get_type());
+        self.values.push(new_value.clone());
+        let instr = Instr::ShlImm { value, amount, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        new_value
+    }
+
+    pub fn lshr_imm(&mut self, value: Value, amount: u8) -> Value {
+        let new_value = Value::new(self.func_id, self.values.len(), value.get_type());
+        self.values.push(new_value.clone());
+        let instr = Instr::LShrImm { value, amount, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        new_value
+    }
+
+    pub fn ashr_imm(&mut self, value: Value, amount: u8) -> Value {
+        let new_value = Value::new(self.func_id, self.values.len(), value.get_type());
+        self.values.push(new_value.clone());
+        let instr = Instr::AShrImm { value, amount, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        new_value
+    }
+
+    pub fn rotl_imm(&mut self, value: Value, amount: u8) -> Value {
+        let new_value = Value::new(self.func_id, self.values.len(), value.get_type());
+        self.values.push(new_value.clone());
+        let instr = Instr::RotlImm { value, amount, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        new_value
+    }
+
+    pub fn rotr_imm(&mut self, value: Value, amount: u8) -> Value {
+        let new_value = Value::new(self.func_id, self.values.len(), value.get_type());
+        self.values.push(new_value.clone());
+        let instr = Instr::RotrImm { value, amount, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        new_value
+    }
+
+    // integer extensions, a bool extends to 0 or 1 with zext and to 0 or -1 with sext
+    pub fn sext(&mut self, value: Value, to: Type) -> Result<Value, CowError> {
+        self.cast(Cast::SExt, value, to)
+    }
+
+    pub fn zext(&mut self, value: Value, to: Type) -> Result<Value, CowError> {
+        self.cast(Cast::ZExt, value, to)
+    }
+
+    // keeps the low bytes of an integer
+    pub fn trunc(&mut self, value: Value, to: Type) -> Result<Value, CowError> {
+        self.cast(Cast::Trunc, value, to)
+    }
+
+    pub fn sitofp(&mut self, value: Value, to: Type) -> Result<Value, CowError> {
+        self.cast(Cast::SIToFP, value, to)
+    }
+
+    pub fn uitofp(&mut self, value: Value, to: Type) -> Result<Value, CowError> {
+        self.cast(Cast::UIToFP, value, to)
+    }
+
+    // the float is rounded toward zero
+    pub fn fptosi(&mut self, value: Value, to: Type) -> Result<Value, CowError> {
+        self.cast(Cast::FPToSI, value, to)
+    }
+
+    pub fn fptoui(&mut self, value: Value, to: Type) -> Result<Value, CowError> {
+        self.cast(Cast::FPToUI, value, to)
+    }
+
+    pub fn fpext(&mut self, value: Value, to: Type) -> Result<Value, CowError> {
+        self.cast(Cast::FPExt, value, to)
+    }
+
+    pub fn fptrunc(&mut self, value: Value, to: Type) -> Result<Value, CowError> {
+        self.cast(Cast::FPTrunc, value, to)
+    }
+
+    // reinterprets the bits of the value as another type of the same size
+    pub fn bitcast(&mut self, value: Value, to: Type) -> Result<Value, CowError> {
+        self.cast(Cast::Bitcast, value, to)
+    }
+
+    pub fn ptrtoint(&mut self, value: Value, to: Type) -> Result<Value, CowError> {
+        self.cast(Cast::PtrToInt, value, to)
     }
 
-    pub fn call_ptr(&mut self, ptr_to_call: Value, args: &Vec<Value>, return_type: Type) -> Value {
-        let new_value = Value::new(self.values.len(), return_type.clone());
+    // a narrow integer is zero extended to the size of the pointer
+    pub fn inttoptr(&mut self, value: Value, to: Type) -> Result<Value, CowError> {
+        self.cast(Cast::IntToPtr, value, to)
+    }
+
+    fn cast(&mut self, cast: Cast, value: Value, to: Type) -> Result<Value, CowError> {
+        let from = value.get_type();
+        if !cast.accepts(&from, &to) {
+            return Err(CowError::InvalidInstr(format!("{} cannot convert {} to {}", cast.name(), from, to)));
+        }
+        let new_value = Value::new(self.func_id, self.values.len(), to);
+        self.values.push(new_value.clone());
+        let instr = Instr::Cast { cast, value, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        Ok(new_value)
+    }
+
+    // memory in the frame of the function, the frame is 16 bytes aligned so the alignment can be up to 16.
+    // all the stack slots of a function take at most MAX_STACK_SLOTS_SIZE bytes
+    pub fn stack_slot(&mut self, size: u32, align: u32) -> Result<Value, CowError> {
+        if !align.is_power_of_two() || align > 16 {
+            return Err(CowError::InvalidInstr(format!("a stack slot cannot be aligned on {} bytes", align)));
+        }
+        self.stack_slots_size = add_stack_slot(self.stack_slots_size, size, align)
+            .ok_or_else(|| CowError::InvalidInstr(format!("a stack slot of {} bytes does not fit in the frame", size)))?;
+        let new_value = Value::new(self.func_id, self.values.len(), Type::ptr());
+        self.values.push(new_value.clone());
+        let instr = Instr::StackSlot { size, align, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        Ok(new_value)
+    }
+
+    // memory reserved on the stack every time the instruction is executed, until the function returns.
+    // the size is unsigned whatever its integer type, a size bigger than the stack crashes on the guard page of the stack
+    pub fn alloca(&mut self, size_value: Value) -> Result<Value, CowError> {
+        if !size_value.get_type().is_int() {
+            return Err(CowError::InvalidInstr(format!("the size of an alloca cannot be {}", size_value.get_type())));
+        }
+        let new_value = Value::new(self.func_id, self.values.len(), Type::ptr());
+        self.values.push(new_value.clone());
+        let instr = Instr::Alloca { size_value, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        Ok(new_value)
+    }
+
+    // loads a value of the type from ptr + offset
+    pub fn load(&mut self, ty: Type, ptr: Value, offset: i32) -> Result<Value, CowError> {
+        self.load_extended(ty.clone(), ty, ptr, offset, false)
+    }
+
+    // loads an integer of mem_type from ptr + offset and extends it to the wider integer type ty
+    pub fn load_sext(&mut self, mem_type: Type, ty: Type, ptr: Value, offset: i32) -> Result<Value, CowError> {
+        if !mem_type.is_int() || !ty.is_int() || ty.size() <= mem_type.size() {
+            return Err(CowError::InvalidInstr(format!("load_sext cannot extend {} to {}", mem_type, ty)));
+        }
+        self.load_extended(mem_type, ty, ptr, offset, false)
+    }
+
+    pub fn load_zext(&mut self, mem_type: Type, ty: Type, ptr: Value, offset: i32) -> Result<Value, CowError> {
+        if !mem_type.is_int() || !ty.is_int() || ty.size() <= mem_type.size() {
+            return Err(CowError::InvalidInstr(format!("load_zext cannot extend {} to {}", mem_type, ty)));
+        }
+        self.load_extended(mem_type, ty, ptr, offset, true)
+    }
+
+    fn load_extended(&mut self, mem_type: Type, ty: Type, ptr: Value, offset: i32, zero_extend: bool) -> Result<Value, CowError> {
+        if !ptr.get_type().is_ptr() {
+            return Err(CowError::InvalidInstr(format!("load from {} instead of ptr", ptr.get_type())));
+        }
+        if mem_type == Type::void() {
+            return Err(CowError::InvalidInstr("load of a void value".to_string()));
+        }
+        let new_value = Value::new(self.func_id, self.values.len(), ty);
+        self.values.push(new_value.clone());
+        let instr = Instr::Load { value_to_load: ptr, offset, mem_type, zero_extend, gen_value: new_value.clone() };
+        self.add_instr(instr);
+        Ok(new_value)
+    }
+
+    // stores the value at ptr + offset, with the size of its type
+    pub fn store(&mut self, ptr: Value, value: Value, offset: i32) -> Result<(), CowError> {
+        if !ptr.get_type().is_ptr() {
+            return Err(CowError::InvalidInstr(format!("store to {} instead of ptr", ptr.get_type())));
+        }
+        if value.get_type() == Type::void() {
+            return Err(CowError::InvalidInstr("store of a void value".to_string()));
+        }
+        let instr = Instr::Store { value_ptr: ptr, value_to_store: value, offset };
+        self.add_instr(instr);
+        Ok(())
+    }
+
+    pub fn br(&mut self, block: Block, args: &[Value]) -> Result<(), CowError> {
+        self.check_branch_args(block, args)?;
+        let instr = Instr::Br { block_to_br: block, args: args.to_vec() };
+        self.add_instr(instr);
+        Ok(())
+    }
+
+    pub fn cond_br(&mut self, value_cond: Value, block_true: Block, true_args: &[Value], block_false: Block, false_args: &[Value]) -> Result<(), CowError> {
+        self.check_branch_args(block_true, true_args)?;
+        self.check_branch_args(block_false, false_args)?;
+        let instr = Instr::CondBr {
+            block_to_br_true: block_true,
+            true_args: true_args.to_vec(),
+            block_to_br_false: block_false,
+            false_args: false_args.to_vec(),
+            value_cond,
+        };
+        self.add_instr(instr);
+        Ok(())
+    }
+
+    fn check_branch_args(&self, block: Block, args: &[Value]) -> Result<(), CowError> {
+        self.check_block(block)?;
+        if self.sealed_blocks.contains(&block.get_id()) {
+            return Err(CowError::InvalidInstr(format!("branch to the sealed block {}", block.get_id())));
+        }
+
+        // the arguments of the parameters created for variables are added when the block is sealed
+        let param_types: Vec<Type> = self.block_params(block)?.iter()
+            .filter(|param| !self.var_params.contains(&param.get_id()))
+            .map(|param| param.get_type())
+            .collect();
+        let arg_types: Vec<Type> = args.iter().map(|arg| arg.get_type()).collect();
+        if arg_types != param_types {
+            return Err(CowError::InvalidInstr(format!("the arguments of the branch do not match the parameters of block {}", block.get_id())));
+        }
+        Ok(())
+    }
+
+    pub fn call_ptr(&mut self, ptr_to_call: Value, args: &[Value], return_type: Type) -> Value {
+        let new_value = Value::new(self.func_id, self.values.len(), return_type.clone());
+        self.values.push(new_value.clone());
         let instr = Instr::CallPtr {
             ptr_to_call,
-            args: args.clone(),
+            args: args.to_vec(),
             return_type: return_type.clone(),
             gen_value: new_value.clone(),
         };
-        self.blocks[self.current_block].add_instr(instr);
+        self.add_instr(instr);
         new_value.clone()
     }
 
+    // call by name a function or a registered host function of the same compiler,
+    // a function has to be declared but can be built later
+    pub fn call(&mut self, func_name: &str, args: &[Value]) -> Result<Value, CowError> {
+        let signature = match self.signatures.borrow().get(func_name) {
+            Some(signature) => signature.clone(),
+            None => return Err(CowError::UnknownFunction(func_name.to_string())),
+        };
+
+        let arg_types: Vec<Type> = args.iter().map(|arg| arg.get_type()).collect();
+        if arg_types != *signature.args() {
+            return Err(CowError::InvalidInstr(format!("the arguments of the call do not match the signature of {}", func_name)));
+        }
+
+        let new_value = Value::new(self.func_id, self.values.len(), signature.return_type().clone());
+        self.values.push(new_value.clone());
+        let instr = Instr::CallFunc { func_to_call: func_name.to_string(), args: args.to_vec(), gen_value: new_value.clone() };
+        self.add_instr(instr);
+        Ok(new_value)
+    }
+
+    // the address of a global, it is only known once the code is placed so it is computed relative to rip
+    pub fn global_addr(&mut self, name: &str) -> Result<Value, CowError> {
+        if !self.globals.borrow().contains_key(name) {
+            return Err(CowError::UnknownGlobal(name.to_string()));
+        }
+
+        let new_value = Value::new(self.func_id, self.values.len(), Type::ptr());
+        self.values.push(new_value.clone());
+        let instr = Instr::GlobalAddr { name: name.to_string(), gen_value: new_value.clone() };
+        self.add_instr(instr);
+        Ok(new_value)
+    }
+
     pub fn ret(&mut self, value: Value) {
         let instr = Instr::Ret { value_to_return: value };
-        self.blocks[self.current_block].add_instr(instr);
+        self.add_instr(instr);
     }
 
     pub fn ret_void(&mut self) {
-        self.blocks[self.current_block].add_instr(Instr::RetVoid);
+        self.add_instr(Instr::RetVoid);
+    }
+
+    fn add_instr(&mut self, mut instr: Instr) {
+        for value in instr.used_values_mut() {
+            *value = self.resolve_alias(value);
+        }
+        self.blocks[self.current_block].add_instr(instr);
+    }
+
+    pub fn declare_var(&mut self, var_type: Type) -> Result<Variable, CowError> {
+        if var_type == Type::void() {
+            return Err(CowError::InvalidType("a variable cannot be void".to_string()));
+        }
+        self.variables.push(var_type);
+        Ok(Variable::new(self.func_id, self.variables.len() - 1))
+    }
+
+    // assign the variable in the current block
+    pub fn def_var(&mut self, var: Variable, value: Value) -> Result<(), CowError> {
+        let var_type = self.check_var(var)?;
+        if var_type != value.get_type() {
+            let message = format!("the variable {} is {}, it cannot be assigned {}", var.get_id(), var_type, value.get_type());
+            return Err(CowError::InvalidInstr(message));
+        }
+        let value = self.resolve_alias(&value);
+        self.var_defs.insert((var.get_id(), self.current_block), value);
+        Ok(())
+    }
+
+    // the value of the variable in the current block, block parameters are added where values from several predecessors meet
+    pub fn use_var(&mut self, var: Variable) -> Result<Value, CowError> {
+        self.check_var(var)?;
+        self.check_defined(var, self.current_block)?;
+        self.read_var(var, self.current_block)
+    }
+
+    // the type of a variable declared by this builder
+    fn check_var(&self, var: Variable) -> Result<Type, CowError> {
+        match self.variables.get(var.get_id()) {
+            Some(var_type) if var.func_id() == self.func_id => Ok(var_type.clone()),
+            _ => Err(CowError::UnknownVariable(var.get_id())),
+        }
     }
 
-    pub(crate) fn blocks(&mut self) -> &mut Vec<LangBlock> {
+    // tell that every branch to the block was added, the parameters of its variables can be completed
+    pub fn seal_block(&mut self, block: Block) -> Result<(), CowError> {
+        let block_id = self.check_block(block)?;
+        if self.sealed_blocks.contains(&block_id) {
+            return Ok(());
+        }
+
+        // nothing is changed when a variable is not defined on every branch to the block
+        let incomplete = self.incomplete_params.get(&block_id).cloned().unwrap_or_default();
+        for (var, _) in &incomplete {
+            for predecessor in self.predecessors(block_id) {
+                self.check_defined(*var, predecessor)?;
+            }
+        }
+
+        self.incomplete_params.remove(&block_id);
+        for (var, param) in incomplete {
+            self.add_param_args(var, param)?;
+        }
+        self.sealed_blocks.insert(block_id);
+        Ok(())
+    }
+
+    pub fn seal_all_blocks(&mut self) -> Result<(), CowError> {
+        for block in 0..self.blocks.len() {
+            self.seal_block(Block::new(self.func_id, block))?;
+        }
+        Ok(())
+    }
+
+    // a read fails when going up the predecessors reaches a block without any, the read is checked
+    // before anything is changed so that a failed read does not leave parameters without arguments
+    fn check_defined(&self, var: Variable, block: usize) -> Result<(), CowError> {
+        let mut visited = HashSet::new();
+        let mut to_visit = vec![block];
+        while let Some(block) = to_visit.pop() {
+            if !visited.insert(block) || self.var_defs.contains_key(&(var.get_id(), block)) || !self.sealed_blocks.contains(&block) {
+                continue;
+            }
+
+            let predecessors = self.predecessors(block);
+            if predecessors.is_empty() {
+                return Err(CowError::UndefinedVariable(var.get_id()));
+            }
+            to_visit.extend(predecessors);
+        }
+        Ok(())
+    }
+
+    // the algorithm of Braun et al., simple and efficient construction of static single assignment form
+    fn read_var(&mut self, var: Variable, block: usize) -> Result<Value, CowError> {
+        if let Some(value) = self.var_defs.get(&(var.get_id(), block)) {
+            return Ok(self.resolve_alias(value));
+        }
+
+        let predecessors = self.predecessors(block);
+        let value = if !self.sealed_blocks.contains(&block) {
+            // more branches can still come, the arguments are added once the block is sealed
+            let param = self.add_var_param(var, block);
+            self.incomplete_params.entry(block).or_default().push((var, param.clone()));
+            param
+        } else if predecessors.len() == 1 && self.single_pred_reads.insert((var.get_id(), block)) {
+            let value = self.read_var(var, predecessors[0]);
+            self.single_pred_reads.remove(&(var.get_id(), block));
+            value?
+        } else if predecessors.is_empty() {
+            return Err(CowError::UndefinedVariable(var.get_id()));
+        } else {
+            // the parameter is defined first, a loop reading the variable back ends on it,
+            // including a loop of blocks with a single predecessor that came back to this one
+            let param = self.add_var_param(var, block);
+            self.var_defs.insert((var.get_id(), block), param.clone());
+            self.add_param_args(var, param)?
+        };
+
+        self.var_defs.insert((var.get_id(), block), value.clone());
+        Ok(value)
+    }
+
+    fn add_var_param(&mut self, var: Variable, block: usize) -> Value {
+        let param = Value::new(self.func_id, self.values.len(), self.variables[var.get_id()].clone());
+        self.values.push(param.clone());
+        self.blocks[block].params_mut().push(param.clone());
+        self.var_params.insert(param.get_id());
+        self.pending_params.insert(param.get_id());
+        param
+    }
+
+    // give the parameter the value of its variable on every branch to its block
+    fn add_param_args(&mut self, var: Variable, param: Value) -> Result<Value, CowError> {
+        let block = self.param_block(&param);
+        for predecessor in self.predecessors(block) {
+            let arg = self.read_var(var, predecessor)?;
+            for instr in self.blocks[predecessor].instructions_mut() {
+                for (target, args) in instr.branch_args_mut() {
+                    if target.get_id() == block {
+                        args.push(arg.clone());
+                    }
+                }
+            }
+        }
+
+        self.pending_params.remove(&param.get_id());
+        Ok(self.remove_trivial_param(param))
+    }
+
+    // a parameter always given the same value, or itself, is replaced by that value
+    fn remove_trivial_param(&mut self, param: Value) -> Value {
+        if self.pending_params.contains(&param.get_id()) {
+            return param;
+        }
+
+        let block = self.param_block(&param);
+        let index = self.blocks[block].params().iter().position(|value| value.get_id() == param.get_id()).unwrap();
+
+        let mut same: Option<Value> = None;
+        for arg in self.param_args(block, index) {
+            if arg.get_id() == param.get_id() || same.as_ref().is_some_and(|same| same.get_id() == arg.get_id()) {
+                continue;
+            }
+            if same.is_some() {
+                return param;
+            }
+            same = Some(arg);
+        }
+
+        // no value ever reaches the parameter, the block cannot be reached
+        let same = match same {
+            Some(same) => same,
+            None => return param,
+        };
+
+        // the other parameters given this one may become trivial once it is replaced
+        let users: Vec<Value> = self.blocks.iter().flat_map(|block| block.instructions())
+            .flat_map(|instr| instr.branch_args())
+            .flat_map(|(target, args)| {
+                let params = self.blocks[target.get_id()].params();
+                args.iter().zip(params).filter(|(arg, _)| arg.get_id() == param.get_id()).map(|(_, user)| user.clone()).collect::<Vec<_>>()
+            })
+            .filter(|user| user.get_id() != param.get_id() && self.var_params.contains(&user.get_id()))
+            .collect();
+
+        self.blocks[block].params_mut().remove(index);
+        for lang_block in self.blocks.iter_mut() {
+            for instr in lang_block.instructions_mut() {
+                for (target, args) in instr.branch_args_mut() {
+                    if target.get_id() == block {
+                        args.remove(index);
+                    }
+                }
+            }
+        }
+        self.replace_value(&param, &same);
+
+        for user in users {
+            if self.var_params.contains(&user.get_id()) && !self.pending_params.contains(&user.get_id()) {
+                self.remove_trivial_param(user);
+            }
+        }
+
+        self.resolve_alias(&same)
+    }
+
+    fn replace_value(&mut self, old: &Value, new: &Value) {
+        self.var_params.remove(&old.get_id());
+        self.aliases.insert(old.get_id(), new.clone());
+
+        for block in self.blocks.iter_mut() {
+            for instr in block.instructions_mut() {
+                for value in instr.used_values_mut() {
+                    if value.get_id() == old.get_id() {
+                        *value = new.clone();
+                    }
+                }
+            }
+        }
+        for value in self.var_defs.values_mut() {
+            if value.get_id() == old.get_id() {
+                *value = new.clone();
+            }
+        }
+    }
+
+    fn resolve_alias(&self, value: &Value) -> Value {
+        let mut value = value;
+        while let Some(alias) = self.aliases.get(&value.get_id()) {
+            value = alias;
+        }
+        value.clone()
+    }
+
+    fn param_block(&self, param: &Value) -> usize {
+        self.blocks.iter().position(|block| block.params().iter().any(|value| value.get_id() == param.get_id())).unwrap()
+    }
+
+    // the value given to the parameter at the index by every branch to the block
+    fn param_args(&self, block: usize, index: usize) -> Vec<Value> {
+        self.blocks.iter().flat_map(|lang_block| lang_block.instructions())
+            .flat_map(|instr| instr.branch_args())
+            .filter(|(target, _)| target.get_id() == block)
+            .map(|(_, args)| args[index].clone())
+            .collect()
+    }
+
+    // the blocks with a branch to the block, once each
+    fn predecessors(&self, block: usize) -> Vec<usize> {
+        (0..self.blocks.len()).filter(|predecessor| {
+            self.blocks[*predecessor].instructions().iter().any(|instr| instr.successors().iter().any(|target| target.get_id() == block))
+        }).collect()
+    }
+
+    pub(crate) fn func_id(&self) -> usize {
+        self.func_id
+    }
+
+    pub(crate) fn signatures(&self) -> &Signatures {
+        &self.signatures
+    }
+
+    pub(crate) fn globals(&self) -> &Globals {
+        &self.globals
+    }
+
+    pub(crate) fn calls_func(&self, func_name: &str) -> bool {
+        self.blocks.iter().flat_map(|block| block.instructions()).any(|instr| {
+            matches!(instr, Instr::CallFunc { func_to_call, .. } if func_to_call == func_name)
+        })
+    }
+
+    pub(crate) fn blocks(&self) -> &Vec<LangBlock> {
+        &self.blocks
+    }
+
+    pub(crate) fn blocks_mut(&mut self) -> &mut Vec<LangBlock> {
         &mut self.blocks
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::compiler::Compiler;
+    use crate::error::CowError;
+    use crate::lang::builder::MAX_STACK_SLOTS_SIZE;
+    use crate::lang::lang_type::Type;
+
+    #[test]
+    fn missing_params_are_errors() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[Type::i64()], Type::i64()).unwrap().builder();
+        assert!(builder.param(0).is_ok());
+        assert_eq!(builder.param(1).err(), Some(CowError::UnknownParam(1)));
+
+        let block = builder.create_block_with_params(&[Type::i32()]).unwrap();
+        assert!(builder.block_param(block, 0).is_ok());
+        assert_eq!(builder.block_param(block, 1).err(), Some(CowError::UnknownParam(1)));
+    }
+
+    #[test]
+    fn void_block_params_and_variables_are_errors() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[], Type::void()).unwrap().builder();
+        assert!(matches!(builder.create_block_with_params(&[Type::i64(), Type::void()]), Err(CowError::InvalidType(_))));
+        assert!(matches!(builder.declare_var(Type::void()), Err(CowError::InvalidType(_))));
+    }
+
+    #[test]
+    fn handles_of_another_function_are_errors() {
+        let mut compiler = Compiler::new().unwrap();
+        let (block, var) = {
+            let other = compiler.add_func("other", &[], Type::void()).unwrap().builder();
+            other.create_block();
+            (other.create_block(), other.declare_var(Type::i64()).unwrap())
+        };
+
+        let builder = compiler.add_func("f", &[], Type::void()).unwrap().builder();
+        let value = builder.const_i64(1);
+        assert_eq!(builder.set_current_block(block), Err(CowError::UnknownBlock(2)));
+        assert_eq!(builder.block_params(block).err(), Some(CowError::UnknownBlock(2)));
+        assert_eq!(builder.br(block, &[]), Err(CowError::UnknownBlock(2)));
+        assert_eq!(builder.seal_block(block), Err(CowError::UnknownBlock(2)));
+        assert_eq!(builder.def_var(var, value), Err(CowError::UnknownVariable(0)));
+        assert_eq!(builder.use_var(var).err(), Some(CowError::UnknownVariable(0)));
+    }
+
+    // sum of the i * j below n, only the odd products are added, and the total is only assigned in some blocks
+    #[test]
+    fn variables_defined_in_some_predecessors_of_nested_loops() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[Type::i64()], Type::i64()).unwrap().builder();
+        let n = builder.param(0).unwrap();
+        let (i, j, total) = (builder.declare_var(Type::i64()).unwrap(), builder.declare_var(Type::i64()).unwrap(), builder.declare_var(Type::i64()).unwrap());
+        let blocks: Vec<_> = (0..6).map(|_| builder.create_block()).collect();
+        let (outer, inner, body, odd, latch, exit) = (blocks[0], blocks[1], blocks[2], blocks[3], blocks[4], blocks[5]);
+
+        let zero = builder.const_i64(0);
+        builder.def_var(i, zero.clone()).unwrap();
+        builder.def_var(total, zero).unwrap();
+        builder.br(outer, &[]).unwrap();
+
+        builder.set_current_block(outer).unwrap();
+        let zero = builder.const_i64(0);
+        builder.def_var(j, zero).unwrap();
+        builder.br(inner, &[]).unwrap();
+
+        builder.set_current_block(inner).unwrap();
+        let current_j = builder.use_var(j).unwrap();
+        let more = builder.smaller(current_j, n.clone());
+        builder.cond_br(more, body, &[], latch, &[]).unwrap();
+
+        builder.set_current_block(body).unwrap();
+        let (current_i, current_j) = (builder.use_var(i).unwrap(), builder.use_var(j).unwrap());
+        let product = builder.mul(current_i, current_j.clone());
+        let one = builder.const_i64(1);
+        let next_j = builder.add(current_j, one.clone());
+        builder.def_var(j, next_j).unwrap();
+        let low_bit = builder.and(product.clone(), one.clone());
+        let is_odd = builder.eq(low_bit, one);
+        builder.cond_br(is_odd, odd, &[], inner, &[]).unwrap();
+
+        builder.set_current_block(odd).unwrap();
+        let current_total = builder.use_var(total).unwrap();
+        let new_total = builder.add(current_total, product);
+        builder.def_var(total, new_total).unwrap();
+        builder.br(inner, &[]).unwrap();
+        builder.seal_block(odd).unwrap();
+        builder.seal_block(body).unwrap();
+        builder.seal_block(inner).unwrap();
+
+        builder.set_current_block(latch).unwrap();
+        let current_i = builder.use_var(i).unwrap();
+        let one = builder.const_i64(1);
+        let next_i = builder.add(current_i, one);
+        builder.def_var(i, next_i.clone()).unwrap();
+        let more = builder.smaller(next_i, n);
+        builder.cond_br(more, outer, &[], exit, &[]).unwrap();
+        builder.seal_block(latch).unwrap();
+        builder.seal_block(outer).unwrap();
+
+        builder.set_current_block(exit).unwrap();
+        let result = builder.use_var(total).unwrap();
+        builder.ret(result);
+        builder.seal_all_blocks().unwrap();
+
+        compiler.jit().unwrap();
+        let func = compiler.get_typed::<fn(i64) -> i64>("f").unwrap();
+        let expected = |n: i64| (0..n).flat_map(|i| (0..n).map(move |j| i * j)).filter(|product| product % 2 == 1).sum::<i64>();
+        assert_eq!(func.call(5), expected(5));
+        assert_eq!(func.call(8), expected(8));
+        assert_eq!(func.call(1), 0);
+    }
+
+    // the read of the variable defined only in the loop fails, the parameter it started must not stay behind
+    #[test]
+    fn a_failed_read_keeps_the_builder_usable() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[Type::i64()], Type::i64()).unwrap().builder();
+        let n = builder.param(0).unwrap();
+        let (i, doubled, offset) = (builder.declare_var(Type::i64()).unwrap(), builder.declare_var(Type::i64()).unwrap(), builder.declare_var(Type::i64()).unwrap());
+        let (header, body, exit) = (builder.create_block(), builder.create_block(), builder.create_block());
+
+        let zero = builder.const_i64(0);
+        builder.def_var(i, zero).unwrap();
+        let ten = builder.const_i64(10);
+        builder.def_var(offset, ten).unwrap();
+        builder.br(header, &[]).unwrap();
+
+        builder.set_current_block(header).unwrap();
+        let current_i = builder.use_var(i).unwrap();
+        let more = builder.smaller(current_i.clone(), n);
+        builder.cond_br(more, body, &[], exit, &[]).unwrap();
+
+        builder.set_current_block(body).unwrap();
+        let twice = builder.add(current_i.clone(), current_i.clone());
+        builder.def_var(doubled, twice).unwrap();
+        let one = builder.const_i64(1);
+        let next_i = builder.add(current_i, one);
+        builder.def_var(i, next_i).unwrap();
+        builder.br(header, &[]).unwrap();
+        builder.seal_block(header).unwrap();
+
+        builder.set_current_block(exit).unwrap();
+        builder.seal_block(exit).unwrap();
+        assert_eq!(builder.use_var(doubled).err(), Some(CowError::UndefinedVariable(doubled.get_id())));
+        let (current_i, current_offset) = (builder.use_var(i).unwrap(), builder.use_var(offset).unwrap());
+        let result = builder.add(current_i, current_offset);
+        builder.ret(result);
+        builder.seal_all_blocks().unwrap();
+
+        compiler.jit().unwrap();
+        let func = compiler.get_typed::<fn(i64) -> i64>("f").unwrap();
+        assert_eq!(func.call(5), 15);
+        assert_eq!(func.call(0), 10);
+    }
+
+    // sealing fails when a variable read in the block is not defined on every branch to it, the block stays unsealed
+    #[test]
+    fn a_failed_seal_changes_nothing() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[Type::bool()], Type::i64()).unwrap().builder();
+        let x = builder.declare_var(Type::i64()).unwrap();
+        let (left, join) = (builder.create_block(), builder.create_block());
+
+        builder.cond_br(builder.param(0).unwrap(), left, &[], join, &[]).unwrap();
+        builder.set_current_block(left).unwrap();
+        let one = builder.const_i64(1);
+        builder.def_var(x, one).unwrap();
+        builder.br(join, &[]).unwrap();
+
+        builder.set_current_block(join).unwrap();
+        let value = builder.use_var(x).unwrap();
+        assert_eq!(builder.seal_block(join), Err(CowError::UndefinedVariable(x.get_id())));
+        assert_eq!(builder.seal_block(join), Err(CowError::UndefinedVariable(x.get_id())));
+        assert_eq!(builder.block_params(join).unwrap().len(), 1);
+        builder.ret(value);
+    }
+
+    // a block only reached from itself reads its own parameter instead of looping forever
+    #[test]
+    fn a_block_looping_on_itself_alone() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[], Type::i64()).unwrap().builder();
+        let x = builder.declare_var(Type::i64()).unwrap();
+        let (unreachable, exit) = (builder.create_block(), builder.create_block());
+        let one = builder.const_i64(1);
+        builder.def_var(x, one).unwrap();
+        builder.br(exit, &[]).unwrap();
+
+        builder.set_current_block(unreachable).unwrap();
+        builder.br(unreachable, &[]).unwrap();
+        builder.seal_all_blocks().unwrap();
+
+        assert!(builder.use_var(x).is_ok());
+        builder.set_current_block(exit).unwrap();
+        let value = builder.use_var(x).unwrap();
+        builder.ret(value);
+
+        compiler.jit().unwrap();
+        assert_eq!(compiler.get_typed::<fn() -> i64>("f").unwrap().call(), 1);
+    }
+
+    #[test]
+    fn casts_between_unrelated_types_are_errors() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[Type::i32(), Type::f64(), Type::ptr()], Type::void()).unwrap().builder();
+        let (int, float, ptr) = (builder.param(0).unwrap(), builder.param(1).unwrap(), builder.param(2).unwrap());
+        assert!(matches!(builder.sext(int.clone(), Type::i16()), Err(CowError::InvalidInstr(_))));
+        assert!(matches!(builder.zext(int.clone(), Type::i32()), Err(CowError::InvalidInstr(_))));
+        assert!(matches!(builder.trunc(int.clone(), Type::i64()), Err(CowError::InvalidInstr(_))));
+        assert!(matches!(builder.sitofp(float.clone(), Type::f64()), Err(CowError::InvalidInstr(_))));
+        assert!(matches!(builder.fptosi(int.clone(), Type::i64()), Err(CowError::InvalidInstr(_))));
+        assert!(matches!(builder.fpext(float.clone(), Type::f32()), Err(CowError::InvalidInstr(_))));
+        assert!(matches!(builder.bitcast(int.clone(), Type::f64()), Err(CowError::InvalidInstr(_))));
+        assert!(matches!(builder.ptrtoint(float, Type::i64()), Err(CowError::InvalidInstr(_))));
+        assert!(matches!(builder.inttoptr(ptr, Type::ptr()), Err(CowError::InvalidInstr(_))));
+        assert!(builder.bitcast(int, Type::f32()).is_ok());
+    }
+
+    #[test]
+    fn float_only_operations_on_integers_are_errors() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[Type::i64(), Type::i64()], Type::void()).unwrap().builder();
+        let (left, right) = (builder.param(0).unwrap(), builder.param(1).unwrap());
+        assert!(matches!(builder.abs(left.clone()), Err(CowError::InvalidInstr(_))));
+        assert!(matches!(builder.ordered(left.clone(), right.clone()), Err(CowError::InvalidInstr(_))));
+        assert!(matches!(builder.unordered(left, right), Err(CowError::InvalidInstr(_))));
+    }
+
+    #[test]
+    fn loads_and_stores_need_a_pointer_and_a_wider_extension() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[Type::ptr(), Type::i64()], Type::void()).unwrap().builder();
+        let (ptr, int) = (builder.param(0).unwrap(), builder.param(1).unwrap());
+        assert!(matches!(builder.load(Type::i64(), int.clone(), 0), Err(CowError::InvalidInstr(_))));
+        assert!(matches!(builder.store(int.clone(), int.clone(), 0), Err(CowError::InvalidInstr(_))));
+        assert!(matches!(builder.load(Type::void(), ptr.clone(), 0), Err(CowError::InvalidInstr(_))));
+        assert!(matches!(builder.load_sext(Type::i32(), Type::i16(), ptr.clone(), 0), Err(CowError::InvalidInstr(_))));
+        assert!(matches!(builder.load_zext(Type::i32(), Type::i32(), ptr.clone(), 0), Err(CowError::InvalidInstr(_))));
+        assert!(matches!(builder.load_zext(Type::f32(), Type::i64(), ptr.clone(), 0), Err(CowError::InvalidInstr(_))));
+        assert!(builder.load_sext(Type::i8(), Type::i16(), ptr.clone(), 0).is_ok());
+        assert!(builder.store(ptr, int, -8).is_ok());
+    }
+
+    #[test]
+    fn bad_stack_slot_alignments_and_alloca_sizes_are_errors() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[Type::f64()], Type::void()).unwrap().builder();
+        assert!(matches!(builder.stack_slot(8, 3), Err(CowError::InvalidInstr(_))));
+        assert!(matches!(builder.stack_slot(8, 32), Err(CowError::InvalidInstr(_))));
+        assert!(matches!(builder.alloca(builder.param(0).unwrap()), Err(CowError::InvalidInstr(_))));
+        assert!(builder.stack_slot(0, 16).is_ok());
+    }
+
+    #[test]
+    fn stack_slots_that_do_not_fit_in_the_frame_are_errors() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[], Type::void()).unwrap().builder();
+        assert!(matches!(builder.stack_slot(0x7fff_fff0, 16), Err(CowError::InvalidInstr(_))));
+        assert!(matches!(builder.stack_slot(u32::MAX, 1), Err(CowError::InvalidInstr(_))));
+
+        // each slot fits but not both of them
+        assert!(builder.stack_slot(MAX_STACK_SLOTS_SIZE / 2, 16).is_ok());
+        assert!(matches!(builder.stack_slot(MAX_STACK_SLOTS_SIZE / 2, 16), Err(CowError::InvalidInstr(_))));
+        assert!(builder.stack_slot(MAX_STACK_SLOTS_SIZE / 4, 16).is_ok());
+    }
+
+    #[test]
+    fn branch_args_have_to_match_the_block_params() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[Type::i64(), Type::f64()], Type::void()).unwrap().builder();
+        let (int, float) = (builder.param(0).unwrap(), builder.param(1).unwrap());
+        let target = builder.create_block_with_params(&[Type::i64(), Type::f64()]).unwrap();
+        let condition = builder.eq(int.clone(), int.clone());
+        assert!(matches!(builder.br(target, std::slice::from_ref(&int)), Err(CowError::InvalidInstr(_))));
+        assert!(matches!(builder.br(target, &[float.clone(), int.clone()]), Err(CowError::InvalidInstr(_))));
+        assert!(matches!(builder.cond_br(condition.clone(), target, &[int.clone(), float.clone()], target, &[]), Err(CowError::InvalidInstr(_))));
+        assert!(builder.cond_br(condition, target, &[int.clone(), float.clone()], target, &[int.clone(), float.clone()]).is_ok());
+
+        builder.seal_block(target).unwrap();
+        assert!(matches!(builder.br(target, &[int, float]), Err(CowError::InvalidInstr(_))));
+    }
+}
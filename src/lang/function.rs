@@ -1,7 +1,12 @@
+use std::fmt;
 use std::ptr;
+use crate::jit::code_arena::CodeRegion;
 use crate::lang;
 use lang::lang_type::Type;
 use crate::lang::builder::Builder;
+use crate::lang::global::Globals;
+use crate::lang::printer::write_function;
+use crate::lang::signature::Signatures;
 
 pub struct Function {
     name: String,
@@ -9,19 +14,20 @@ pub struct Function {
     return_type: Type,
     builder: Builder,
     code: Vec<u8>,
-    jit_ptr: *mut u8,
+    // the jitted code, owned by the function and given back to the code arena on drop
+    code_region: Option<CodeRegion>,
 }
 
 
 impl Function {
-    pub fn new(name: &str, args: &Vec<Type>, return_type: Type) -> Self {
+    pub(crate) fn new(name: &str, args: &[Type], return_type: Type, signatures: Signatures, globals: Globals) -> Self {
         Function {
             name: String::from(name),
-            args: args.clone(),
+            args: args.to_vec(),
             return_type,
-            builder: Builder::new(),
+            builder: Builder::new(args, signatures, globals),
             code: vec![],
-            jit_ptr: ptr::null_mut(),
+            code_region: None,
         }
     }
 
@@ -29,12 +35,20 @@ impl Function {
         &self.args
     }
 
+    pub fn return_type(&self) -> &Type {
+        &self.return_type
+    }
+
+    pub(crate) fn builder_ref(&self) -> &Builder {
+        &self.builder
+    }
+
     pub fn builder(&mut self) -> &mut Builder {
         &mut self.builder
     }
 
-    pub fn set_code(&mut self, code: &Vec<u8>) {
-        self.code = code.clone();
+    pub fn set_code(&mut self, code: &[u8]) {
+        self.code = code.to_vec();
     }
 
     pub fn code(&self) -> &Vec<u8> {
@@ -45,11 +59,22 @@ impl Function {
         &self.name
     }
 
-    pub fn set_jit_ptr(&mut self, ptr: *mut u8) {
-        self.jit_ptr = ptr;
+    pub(crate) fn set_code_region(&mut self, region: CodeRegion) {
+        self.code_region = Some(region);
     }
 
     pub fn jit_ptr(&self) -> *mut u8 {
-        self.jit_ptr
+        self.code_region.as_ref().map_or(ptr::null_mut(), |region| region.ptr())
+    }
+
+    // the textual ir of the function
+    pub fn print_ir(&self) -> String {
+        self.to_string()
     }
-}
\ No newline at end of file
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_function(f, self)
+    }
+}
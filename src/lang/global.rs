@@ -0,0 +1,45 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// a piece of data living in the data region of the code arena for as long as the compiler
+#[derive(Clone, Debug)]
+pub struct Global {
+    address: usize,
+    size: usize,
+    align: usize,
+    mutable: bool,
+}
+
+impl Global {
+    pub(crate) fn new(address: usize, size: usize, align: usize, mutable: bool) -> Self {
+        Global { address, size, align, mutable }
+    }
+
+    pub fn address(&self) -> usize {
+        self.address
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn align(&self) -> usize {
+        self.align
+    }
+
+    // a copy of the current content of the global, jitted code can change it at any time so no reference to it is handed out.
+    // safety: the compiler that defined the global has to be alive, its arena holds the data
+    pub(crate) unsafe fn read_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; self.size];
+        std::ptr::copy_nonoverlapping(self.address as *const u8, bytes.as_mut_ptr(), self.size);
+        bytes
+    }
+
+    pub fn is_mutable(&self) -> bool {
+        self.mutable
+    }
+}
+
+// the globals that can be addressed by name, shared by a compiler and the builders of its functions
+pub(crate) type Globals = Rc<RefCell<HashMap<String, Global>>>;
@@ -2,19 +2,76 @@ use crate::lang::block::Block;
 use crate::lang::lang_type::Type;
 use crate::lang::value::Value;
 
+// the conversions between integer, float and pointer types
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum Cast {
+    SExt,
+    ZExt,
+    Trunc,
+    SIToFP,
+    UIToFP,
+    FPToSI,
+    FPToUI,
+    FPExt,
+    FPTrunc,
+    Bitcast,
+    PtrToInt,
+    IntToPtr,
+}
+
+impl Cast {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Cast::SExt => "sext",
+            Cast::ZExt => "zext",
+            Cast::Trunc => "trunc",
+            Cast::SIToFP => "sitofp",
+            Cast::UIToFP => "uitofp",
+            Cast::FPToSI => "fptosi",
+            Cast::FPToUI => "fptoui",
+            Cast::FPExt => "fpext",
+            Cast::FPTrunc => "fptrunc",
+            Cast::Bitcast => "bitcast",
+            Cast::PtrToInt => "ptrtoint",
+            Cast::IntToPtr => "inttoptr",
+        }
+    }
+
+    // whether the conversion exists from a type to another
+    pub(crate) fn accepts(&self, from: &Type, to: &Type) -> bool {
+        match self {
+            Cast::SExt | Cast::ZExt => (from.is_int() || from.is_bool()) && to.is_int() && to.size() > from.size(),
+            Cast::Trunc => from.is_int() && to.is_int() && to.size() < from.size(),
+            Cast::SIToFP | Cast::UIToFP => from.is_int() && to.is_float(),
+            Cast::FPToSI | Cast::FPToUI => from.is_float() && to.is_int(),
+            Cast::FPExt => *from == Type::f32() && *to == Type::f64(),
+            Cast::FPTrunc => *from == Type::f64() && *to == Type::f32(),
+            Cast::Bitcast => {
+                (from.is_int() || from.is_float() || from.is_ptr()) && (to.is_int() || to.is_float() || to.is_ptr()) && from.size() == to.size()
+            }
+            Cast::PtrToInt => from.is_ptr() && to.is_int(),
+            Cast::IntToPtr => from.is_int() && to.is_ptr(),
+        }
+    }
+}
 
 pub(crate) enum Instr {
-    ConstInt128 { const_value: i128, gen_value: Value },
     ConstInt64 { const_value: i64, gen_value: Value },
     ConstInt32 { const_value: i32, gen_value: Value },
     ConstInt16 { const_value: i16, gen_value: Value },
     ConstInt8 { const_value: i8, gen_value: Value },
     ConstPtr { const_value: usize, gen_value: Value },
+    GlobalAddr { name: String, gen_value: Value },
 
     Add { left_value: Value, right_value: Value, gen_value: Value },
     Sub { left_value: Value, right_value: Value, gen_value: Value },
     Div { left_value: Value, right_value: Value, gen_value: Value },
     Mul { left_value: Value, right_value: Value, gen_value: Value },
+    UDiv { left_value: Value, right_value: Value, gen_value: Value },
+    Neg { value: Value, gen_value: Value },
+    Abs { value: Value, gen_value: Value },
+    SRem { left_value: Value, right_value: Value, gen_value: Value },
+    URem { left_value: Value, right_value: Value, gen_value: Value },
 
     Eq { left_value: Value, right_value: Value, gen_value: Value },
     Diff { left_value: Value, right_value: Value, gen_value: Value },
@@ -22,14 +79,42 @@ pub(crate) enum Instr {
     LargerEq { left_value: Value, right_value: Value, gen_value: Value },
     Smaller { left_value: Value, right_value: Value, gen_value: Value },
     SmallerEq { left_value: Value, right_value: Value, gen_value: Value },
+    Ordered { left_value: Value, right_value: Value, gen_value: Value },
+    Unordered { left_value: Value, right_value: Value, gen_value: Value },
+    ULarger { left_value: Value, right_value: Value, gen_value: Value },
+    ULargerEq { left_value: Value, right_value: Value, gen_value: Value },
+    USmaller { left_value: Value, right_value: Value, gen_value: Value },
+    USmallerEq { left_value: Value, right_value: Value, gen_value: Value },
+
+    LShr { left_value: Value, right_value: Value, gen_value: Value },
+    AShr { left_value: Value, right_value: Value, gen_value: Value },
+
+    And { left_value: Value, right_value: Value, gen_value: Value },
+    Or { left_value: Value, right_value: Value, gen_value: Value },
+    Xor { left_value: Value, right_value: Value, gen_value: Value },
+    Not { value: Value, gen_value: Value },
+
+    Shl { left_value: Value, right_value: Value, gen_value: Value },
+    Rotl { left_value: Value, right_value: Value, gen_value: Value },
+    Rotr { left_value: Value, right_value: Value, gen_value: Value },
+    ShlImm { value: Value, amount: u8, gen_value: Value },
+    LShrImm { value: Value, amount: u8, gen_value: Value },
+    AShrImm { value: Value, amount: u8, gen_value: Value },
+    RotlImm { value: Value, amount: u8, gen_value: Value },
+    RotrImm { value: Value, amount: u8, gen_value: Value },
+
+    Cast { cast: Cast, value: Value, gen_value: Value },
 
-    Not { value: Value, right_value: Value, gen_value: Value },
+    // memory of the frame of the function, alloca reserves it on the stack when it is executed
+    StackSlot { size: u32, align: u32, gen_value: Value },
+    Alloca { size_value: Value, gen_value: Value },
 
-    Load { value_to_load: Value, gen_value: Value },
-    Store { value_ptr: Value, value_to_store: Value },
+    // loads the bytes of mem_type at value_to_load + offset, a narrower mem_type is extended to the type of gen_value
+    Load { value_to_load: Value, offset: i32, mem_type: Type, zero_extend: bool, gen_value: Value },
+    Store { value_ptr: Value, value_to_store: Value, offset: i32 },
 
-    Br { block_to_br: Block },
-    CondBr { block_to_br_true: Block, block_to_br_false: Block, value_cond: Value },
+    Br { block_to_br: Block, args: Vec<Value> },
+    CondBr { block_to_br_true: Block, true_args: Vec<Value>, block_to_br_false: Block, false_args: Vec<Value>, value_cond: Value },
 
     CallPtr { ptr_to_call: Value, args: Vec<Value>, return_type: Type, gen_value: Value },
     CallFunc { func_to_call: String, args: Vec<Value>, gen_value: Value },
@@ -37,3 +122,312 @@ pub(crate) enum Instr {
     Ret { value_to_return: Value },
     RetVoid,
 }
+
+impl Instr {
+    // the name of the instruction, the same as the method of the builder adding it
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Instr::ConstInt64 { .. } |
+            Instr::ConstInt32 { .. } |
+            Instr::ConstInt16 { .. } |
+            Instr::ConstInt8 { .. } |
+            Instr::ConstPtr { .. } => "const",
+            Instr::GlobalAddr { .. } => "global_addr",
+            Instr::Add { .. } => "add",
+            Instr::Sub { .. } => "sub",
+            Instr::Div { .. } => "div",
+            Instr::Mul { .. } => "mul",
+            Instr::Neg { .. } => "neg",
+            Instr::Abs { .. } => "abs",
+            Instr::UDiv { .. } => "udiv",
+            Instr::SRem { .. } => "srem",
+            Instr::URem { .. } => "urem",
+            Instr::Eq { .. } => "eq",
+            Instr::Diff { .. } => "diff",
+            Instr::Larger { .. } => "larger",
+            Instr::LargerEq { .. } => "larger_eq",
+            Instr::Smaller { .. } => "smaller",
+            Instr::SmallerEq { .. } => "smaller_eq",
+            Instr::Ordered { .. } => "ordered",
+            Instr::Unordered { .. } => "unordered",
+            Instr::ULarger { .. } => "ugt",
+            Instr::ULargerEq { .. } => "uge",
+            Instr::USmaller { .. } => "ult",
+            Instr::USmallerEq { .. } => "ule",
+            Instr::LShr { .. } => "lshr",
+            Instr::AShr { .. } => "ashr",
+            Instr::And { .. } => "and",
+            Instr::Or { .. } => "or",
+            Instr::Xor { .. } => "xor",
+            Instr::Not { .. } => "not",
+            Instr::Shl { .. } => "shl",
+            Instr::Rotl { .. } => "rotl",
+            Instr::Rotr { .. } => "rotr",
+            Instr::ShlImm { .. } => "shl_imm",
+            Instr::LShrImm { .. } => "lshr_imm",
+            Instr::AShrImm { .. } => "ashr_imm",
+            Instr::RotlImm { .. } => "rotl_imm",
+            Instr::RotrImm { .. } => "rotr_imm",
+            Instr::Cast { cast, .. } => cast.name(),
+            Instr::StackSlot { .. } => "stack_slot",
+            Instr::Alloca { .. } => "alloca",
+            Instr::Load { mem_type, zero_extend, gen_value, .. } => {
+                match (*mem_type == gen_value.get_type(), zero_extend) {
+                    (true, _) => "load",
+                    (false, false) => "load_sext",
+                    (false, true) => "load_zext",
+                }
+            }
+            Instr::Store { .. } => "store",
+            Instr::Br { .. } => "br",
+            Instr::CondBr { .. } => "cond_br",
+            Instr::CallPtr { .. } => "call_ptr",
+            Instr::CallFunc { .. } => "call",
+            Instr::Ret { .. } => "ret",
+            Instr::RetVoid => "ret_void",
+        }
+    }
+
+    pub(crate) fn is_terminator(&self) -> bool {
+        matches!(self, Instr::Br { .. } | Instr::CondBr { .. } | Instr::Ret { .. } | Instr::RetVoid)
+    }
+
+    // the value defined by the instruction
+    pub(crate) fn gen_value(&self) -> Option<&Value> {
+        match self {
+            Instr::ConstInt64 { gen_value, .. } |
+            Instr::ConstInt32 { gen_value, .. } |
+            Instr::ConstInt16 { gen_value, .. } |
+            Instr::ConstInt8 { gen_value, .. } |
+            Instr::ConstPtr { gen_value, .. } |
+            Instr::GlobalAddr { gen_value, .. } |
+            Instr::Add { gen_value, .. } |
+            Instr::Sub { gen_value, .. } |
+            Instr::Div { gen_value, .. } |
+            Instr::Mul { gen_value, .. } |
+            Instr::UDiv { gen_value, .. } |
+            Instr::Neg { gen_value, .. } |
+            Instr::Abs { gen_value, .. } |
+            Instr::SRem { gen_value, .. } |
+            Instr::URem { gen_value, .. } |
+            Instr::Eq { gen_value, .. } |
+            Instr::Diff { gen_value, .. } |
+            Instr::Larger { gen_value, .. } |
+            Instr::LargerEq { gen_value, .. } |
+            Instr::Smaller { gen_value, .. } |
+            Instr::SmallerEq { gen_value, .. } |
+            Instr::Ordered { gen_value, .. } |
+            Instr::Unordered { gen_value, .. } |
+            Instr::ULarger { gen_value, .. } |
+            Instr::ULargerEq { gen_value, .. } |
+            Instr::USmaller { gen_value, .. } |
+            Instr::USmallerEq { gen_value, .. } |
+            Instr::LShr { gen_value, .. } |
+            Instr::AShr { gen_value, .. } |
+            Instr::And { gen_value, .. } |
+            Instr::Or { gen_value, .. } |
+            Instr::Xor { gen_value, .. } |
+            Instr::Not { gen_value, .. } |
+            Instr::Shl { gen_value, .. } |
+            Instr::Rotl { gen_value, .. } |
+            Instr::Rotr { gen_value, .. } |
+            Instr::ShlImm { gen_value, .. } |
+            Instr::LShrImm { gen_value, .. } |
+            Instr::AShrImm { gen_value, .. } |
+            Instr::RotlImm { gen_value, .. } |
+            Instr::RotrImm { gen_value, .. } |
+            Instr::Cast { gen_value, .. } |
+            Instr::StackSlot { gen_value, .. } |
+            Instr::Alloca { gen_value, .. } |
+            Instr::Load { gen_value, .. } |
+            Instr::CallPtr { gen_value, .. } |
+            Instr::CallFunc { gen_value, .. } => Some(gen_value),
+
+            Instr::Store { .. } |
+            Instr::Br { .. } |
+            Instr::CondBr { .. } |
+            Instr::Ret { .. } |
+            Instr::RetVoid => None,
+        }
+    }
+
+    // the values read by the instruction, in operand order
+    pub(crate) fn used_values(&self) -> Vec<&Value> {
+        match self {
+            Instr::ConstInt64 { .. } |
+            Instr::ConstInt32 { .. } |
+            Instr::ConstInt16 { .. } |
+            Instr::ConstInt8 { .. } |
+            Instr::ConstPtr { .. } |
+            Instr::GlobalAddr { .. } |
+            Instr::StackSlot { .. } |
+            Instr::RetVoid => vec![],
+
+            Instr::Add { left_value, right_value, .. } |
+            Instr::Sub { left_value, right_value, .. } |
+            Instr::Div { left_value, right_value, .. } |
+            Instr::Mul { left_value, right_value, .. } |
+            Instr::UDiv { left_value, right_value, .. } |
+            Instr::SRem { left_value, right_value, .. } |
+            Instr::URem { left_value, right_value, .. } |
+            Instr::Eq { left_value, right_value, .. } |
+            Instr::Diff { left_value, right_value, .. } |
+            Instr::Larger { left_value, right_value, .. } |
+            Instr::LargerEq { left_value, right_value, .. } |
+            Instr::Smaller { left_value, right_value, .. } |
+            Instr::SmallerEq { left_value, right_value, .. } |
+            Instr::Ordered { left_value, right_value, .. } |
+            Instr::Unordered { left_value, right_value, .. } |
+            Instr::ULarger { left_value, right_value, .. } |
+            Instr::ULargerEq { left_value, right_value, .. } |
+            Instr::USmaller { left_value, right_value, .. } |
+            Instr::USmallerEq { left_value, right_value, .. } |
+            Instr::LShr { left_value, right_value, .. } |
+            Instr::AShr { left_value, right_value, .. } |
+            Instr::And { left_value, right_value, .. } |
+            Instr::Or { left_value, right_value, .. } |
+            Instr::Xor { left_value, right_value, .. } |
+            Instr::Shl { left_value, right_value, .. } |
+            Instr::Rotl { left_value, right_value, .. } |
+            Instr::Rotr { left_value, right_value, .. } => vec![left_value, right_value],
+
+            Instr::Not { value, .. } |
+            Instr::Neg { value, .. } |
+            Instr::Abs { value, .. } |
+            Instr::ShlImm { value, .. } |
+            Instr::LShrImm { value, .. } |
+            Instr::AShrImm { value, .. } |
+            Instr::RotlImm { value, .. } |
+            Instr::RotrImm { value, .. } |
+            Instr::Cast { value, .. } => vec![value],
+
+            Instr::Alloca { size_value, .. } => vec![size_value],
+            Instr::Load { value_to_load, .. } => vec![value_to_load],
+            Instr::Store { value_ptr, value_to_store, .. } => vec![value_ptr, value_to_store],
+
+            Instr::Br { args, .. } => args.iter().collect(),
+            Instr::CondBr { value_cond, true_args, false_args, .. } => {
+                let mut values = vec![value_cond];
+                values.extend(true_args.iter());
+                values.extend(false_args.iter());
+                values
+            }
+
+            Instr::CallPtr { ptr_to_call, args, .. } => {
+                let mut values = vec![ptr_to_call];
+                values.extend(args.iter());
+                values
+            }
+            Instr::CallFunc { args, .. } => args.iter().collect(),
+
+            Instr::Ret { value_to_return } => vec![value_to_return],
+        }
+    }
+
+    // the values read by the instruction, to be replaced in place
+    pub(crate) fn used_values_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            Instr::ConstInt64 { .. } |
+            Instr::ConstInt32 { .. } |
+            Instr::ConstInt16 { .. } |
+            Instr::ConstInt8 { .. } |
+            Instr::ConstPtr { .. } |
+            Instr::GlobalAddr { .. } |
+            Instr::StackSlot { .. } |
+            Instr::RetVoid => vec![],
+
+            Instr::Add { left_value, right_value, .. } |
+            Instr::Sub { left_value, right_value, .. } |
+            Instr::Div { left_value, right_value, .. } |
+            Instr::Mul { left_value, right_value, .. } |
+            Instr::UDiv { left_value, right_value, .. } |
+            Instr::SRem { left_value, right_value, .. } |
+            Instr::URem { left_value, right_value, .. } |
+            Instr::Eq { left_value, right_value, .. } |
+            Instr::Diff { left_value, right_value, .. } |
+            Instr::Larger { left_value, right_value, .. } |
+            Instr::LargerEq { left_value, right_value, .. } |
+            Instr::Smaller { left_value, right_value, .. } |
+            Instr::SmallerEq { left_value, right_value, .. } |
+            Instr::Ordered { left_value, right_value, .. } |
+            Instr::Unordered { left_value, right_value, .. } |
+            Instr::ULarger { left_value, right_value, .. } |
+            Instr::ULargerEq { left_value, right_value, .. } |
+            Instr::USmaller { left_value, right_value, .. } |
+            Instr::USmallerEq { left_value, right_value, .. } |
+            Instr::LShr { left_value, right_value, .. } |
+            Instr::AShr { left_value, right_value, .. } |
+            Instr::And { left_value, right_value, .. } |
+            Instr::Or { left_value, right_value, .. } |
+            Instr::Xor { left_value, right_value, .. } |
+            Instr::Shl { left_value, right_value, .. } |
+            Instr::Rotl { left_value, right_value, .. } |
+            Instr::Rotr { left_value, right_value, .. } => vec![left_value, right_value],
+
+            Instr::Not { value, .. } |
+            Instr::Neg { value, .. } |
+            Instr::Abs { value, .. } |
+            Instr::ShlImm { value, .. } |
+            Instr::LShrImm { value, .. } |
+            Instr::AShrImm { value, .. } |
+            Instr::RotlImm { value, .. } |
+            Instr::RotrImm { value, .. } |
+            Instr::Cast { value, .. } => vec![value],
+
+            Instr::Alloca { size_value, .. } => vec![size_value],
+            Instr::Load { value_to_load, .. } => vec![value_to_load],
+            Instr::Store { value_ptr, value_to_store, .. } => vec![value_ptr, value_to_store],
+
+            Instr::Br { args, .. } => args.iter_mut().collect(),
+            Instr::CondBr { value_cond, true_args, false_args, .. } => {
+                let mut values = vec![value_cond];
+                values.extend(true_args.iter_mut());
+                values.extend(false_args.iter_mut());
+                values
+            }
+
+            Instr::CallPtr { ptr_to_call, args, .. } => {
+                let mut values = vec![ptr_to_call];
+                values.extend(args.iter_mut());
+                values
+            }
+            Instr::CallFunc { args, .. } => args.iter_mut().collect(),
+
+            Instr::Ret { value_to_return } => vec![value_to_return],
+        }
+    }
+
+    // the blocks the instruction can jump to
+    pub(crate) fn successors(&self) -> Vec<Block> {
+        match self {
+            Instr::Br { block_to_br, .. } => vec![*block_to_br],
+            Instr::CondBr { block_to_br_true, block_to_br_false, .. } => vec![*block_to_br_true, *block_to_br_false],
+            _ => vec![],
+        }
+    }
+
+    // the blocks the instruction can jump to with the arguments given to their parameters
+    pub(crate) fn branch_args(&self) -> Vec<(Block, &Vec<Value>)> {
+        match self {
+            Instr::Br { block_to_br, args } => vec![(*block_to_br, args)],
+            Instr::CondBr { block_to_br_true, true_args, block_to_br_false, false_args, .. } => {
+                vec![(*block_to_br_true, true_args), (*block_to_br_false, false_args)]
+            }
+            _ => vec![],
+        }
+    }
+
+    pub(crate) fn branch_args_mut(&mut self) -> Vec<(Block, &mut Vec<Value>)> {
+        match self {
+            Instr::Br { block_to_br, args } => vec![(*block_to_br, args)],
+            Instr::CondBr { block_to_br_true, true_args, block_to_br_false, false_args, .. } => {
+                vec![(*block_to_br_true, true_args), (*block_to_br_false, false_args)]
+            }
+            _ => vec![],
+        }
+    }
+
+    pub(crate) fn is_call(&self) -> bool {
+        matches!(self, Instr::CallPtr { .. } | Instr::CallFunc { .. })
+    }
+}
@@ -1,6 +1,9 @@
-#[derive(PartialEq, Clone)]
+use std::fmt;
+
+#[derive(PartialEq, Clone, Debug)]
 pub enum LangDataType {
     DataTypeVoid,
+    DataTypeBool,
     DataTypeI64,
     DataTypeI32,
     DataTypeI16,
@@ -11,7 +14,7 @@ pub enum LangDataType {
 }
 
 
-#[derive(Clone)]
+#[derive(PartialEq, Clone, Debug)]
 pub struct Type {
     data_type: LangDataType,
 }
@@ -19,6 +22,11 @@ pub struct Type {
 impl Type {
     pub fn void() -> Self { Self { data_type: LangDataType::DataTypeVoid } }
 
+    // the result of a comparison, 0 or 1
+    pub fn bool() -> Self {
+        Self { data_type: LangDataType::DataTypeBool }
+    }
+
     pub fn i8() -> Self {
         Self { data_type: LangDataType::DataTypeI8 }
     }
@@ -52,10 +60,42 @@ impl Type {
     }
 
     pub fn is_int(&self) -> bool {
-        !(self.is_float() && self.is_ptr())
+        matches!(self.data_type, LangDataType::DataTypeI8 | LangDataType::DataTypeI16 | LangDataType::DataTypeI32 | LangDataType::DataTypeI64)
+    }
+
+    pub fn is_bool(&self) -> bool {
+        self.data_type == LangDataType::DataTypeBool
     }
 
     pub fn is_ptr(&self) -> bool {
         self.data_type == LangDataType::DataTypePtr
     }
-}
\ No newline at end of file
+
+    // number of bytes a value of the type takes in memory
+    pub fn size(&self) -> usize {
+        match self.data_type {
+            LangDataType::DataTypeVoid => 0,
+            LangDataType::DataTypeBool | LangDataType::DataTypeI8 => 1,
+            LangDataType::DataTypeI16 => 2,
+            LangDataType::DataTypeI32 | LangDataType::DataTypeF32 => 4,
+            LangDataType::DataTypeI64 | LangDataType::DataTypeF64 | LangDataType::DataTypePtr => 8,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.data_type {
+            LangDataType::DataTypeVoid => "void",
+            LangDataType::DataTypeBool => "bool",
+            LangDataType::DataTypeI64 => "i64",
+            LangDataType::DataTypeI32 => "i32",
+            LangDataType::DataTypeI16 => "i16",
+            LangDataType::DataTypeI8 => "i8",
+            LangDataType::DataTypeF64 => "f64",
+            LangDataType::DataTypeF32 => "f32",
+            LangDataType::DataTypePtr => "ptr",
+        };
+        write!(f, "{}", name)
+    }
+}
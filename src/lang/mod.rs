@@ -3,4 +3,10 @@ pub mod function;
 pub mod block;
 pub mod builder;
 pub mod value;
-pub mod instr;
\ No newline at end of file
+pub mod instr;
+pub mod signature;
+pub mod global;
+pub mod variable;
+pub mod verifier;
+pub mod printer;
+pub mod parser;
\ No newline at end of file
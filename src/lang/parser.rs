@@ -0,0 +1,1066 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::compiler::Compiler;
+use crate::error::CowError;
+use crate::lang::block::Block;
+use crate::lang::builder::Builder;
+use crate::lang::lang_type::Type;
+use crate::lang::value::Value;
+use crate::lang::verifier::verify_function;
+
+// a mistake in the text of the ir, with the line and the column where it was found, both counted from 1
+#[derive(Clone, PartialEq, Debug)]
+pub struct ParseError {
+    line: usize,
+    column: usize,
+    message: String,
+}
+
+impl ParseError {
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    pub fn message(&self) -> &String {
+        &self.message
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum TokenKind {
+    Ident(String),
+    // kept as written since its meaning depends on the type it is read as
+    Number(String),
+    Global(String),
+    Punct(char),
+    Arrow,
+    Newline,
+    End,
+}
+
+struct Token {
+    kind: TokenKind,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match &self.kind {
+            TokenKind::Ident(name) | TokenKind::Number(name) => format!("`{}`", name),
+            TokenKind::Global(name) => format!("`@{}`", name),
+            TokenKind::Punct(c) => format!("`{}`", c),
+            TokenKind::Arrow => "`->`".to_string(),
+            TokenKind::Newline => "the end of the line".to_string(),
+            TokenKind::End => "the end of the text".to_string(),
+        }
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+fn lex(text: &str) -> Result<Vec<Token>, ParseError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = vec![];
+    let (mut index, mut line, mut line_start) = (0, 1, 0);
+
+    while index < chars.len() {
+        let c = chars[index];
+        let (start, column) = (index, index - line_start + 1);
+
+        let kind = if c == '\n' {
+            index += 1;
+            line += 1;
+            line_start = index;
+            TokenKind::Newline
+        } else if c.is_whitespace() {
+            index += 1;
+            continue;
+        } else if c == '/' && chars.get(index + 1) == Some(&'/') {
+            // a comment runs to the end of the line
+            while index < chars.len() && chars[index] != '\n' {
+                index += 1;
+            }
+            continue;
+        } else if c == '-' && chars.get(index + 1) == Some(&'>') {
+            index += 2;
+            TokenKind::Arrow
+        } else if c == '@' {
+            index += 1;
+            while index < chars.len() && is_name_char(chars[index]) {
+                index += 1;
+            }
+            TokenKind::Global(chars[start + 1..index].iter().collect())
+        } else if c.is_ascii_digit() || (c == '-' && chars.get(index + 1).is_some_and(|next| next.is_ascii_alphanumeric())) {
+            // the exponent of a float can have a sign, a hex number cannot have an exponent
+            index += 1;
+            while index < chars.len() {
+                let previous = chars[index - 1];
+                let hex = chars[start..index].contains(&'x');
+                if is_name_char(chars[index]) || ((chars[index] == '-' || chars[index] == '+') && (previous == 'e' || previous == 'E') && !hex) {
+                    index += 1;
+                } else {
+                    break;
+                }
+            }
+            TokenKind::Number(chars[start..index].iter().collect())
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            while index < chars.len() && is_name_char(chars[index]) {
+                index += 1;
+            }
+            TokenKind::Ident(chars[start..index].iter().collect())
+        } else if "(){}[]:,=".contains(c) {
+            index += 1;
+            TokenKind::Punct(c)
+        } else {
+            return Err(ParseError { line, column, message: format!("unexpected character `{}`", c) });
+        };
+
+        let line = if kind == TokenKind::Newline { line - 1 } else { line };
+        tokens.push(Token { kind, line, column });
+    }
+
+    let column = index - line_start + 1;
+    tokens.push(Token { kind: TokenKind::End, line, column });
+    Ok(tokens)
+}
+
+fn type_from_name(name: &str) -> Option<Type> {
+    match name {
+        "void" => Some(Type::void()),
+        "bool" => Some(Type::bool()),
+        "i64" => Some(Type::i64()),
+        "i32" => Some(Type::i32()),
+        "i16" => Some(Type::i16()),
+        "i8" => Some(Type::i8()),
+        "f64" => Some(Type::f64()),
+        "f32" => Some(Type::f32()),
+        "ptr" => Some(Type::ptr()),
+        _ => None,
+    }
+}
+
+// a decimal or a 0x prefixed hex integer, with an optional minus sign
+fn parse_int(text: &str) -> Option<i128> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => i128::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<i128>().ok()?,
+    };
+    Some(if negative { -value } else { value })
+}
+
+// a function whose header is parsed, its body is parsed once every function of the text is declared
+struct FuncDecl {
+    name: String,
+    name_pos: usize,
+    params: Vec<(String, usize)>,
+    body_start: usize,
+    body_end: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum LineKind {
+    Block,
+    Def,
+    Instr,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &TokenKind {
+        &self.tokens[self.pos].kind
+    }
+
+    fn advance(&mut self) -> usize {
+        let pos = self.pos;
+        if self.pos < self.tokens.len() - 1 {
+            self.pos += 1;
+        }
+        pos
+    }
+
+    fn error_at(&self, pos: usize, message: String) -> CowError {
+        let token = &self.tokens[pos];
+        CowError::Parse(ParseError { line: token.line, column: token.column, message })
+    }
+
+    // an error of the compiler or of the builder, reported at the token that caused it
+    fn wrap(&self, pos: usize, error: CowError) -> CowError {
+        self.error_at(pos, error.to_string())
+    }
+
+    fn expected(&self, what: &str) -> CowError {
+        self.error_at(self.pos, format!("expected {}, found {}", what, self.tokens[self.pos].describe()))
+    }
+
+    fn skip_newlines(&mut self) {
+        while *self.peek() == TokenKind::Newline {
+            self.advance();
+        }
+    }
+
+    fn punct(&mut self, c: char) -> Result<(), CowError> {
+        if *self.peek() != TokenKind::Punct(c) {
+            return Err(self.expected(&format!("`{}`", c)));
+        }
+        self.advance();
+        Ok(())
+    }
+
+    fn eat_punct(&mut self, c: char) -> bool {
+        let found = *self.peek() == TokenKind::Punct(c);
+        if found {
+            self.advance();
+        }
+        found
+    }
+
+    fn ident(&mut self) -> Result<(String, usize), CowError> {
+        match self.peek().clone() {
+            TokenKind::Ident(name) => Ok((name, self.advance())),
+            _ => Err(self.expected("a name")),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), CowError> {
+        match self.peek() {
+            TokenKind::Ident(name) if name == keyword => {
+                self.advance();
+                Ok(())
+            }
+            _ => Err(self.expected(&format!("`{}`", keyword))),
+        }
+    }
+
+    fn global_name(&mut self) -> Result<(String, usize), CowError> {
+        match self.peek().clone() {
+            TokenKind::Global(name) => Ok((name, self.advance())),
+            _ => Err(self.expected("a `@` name")),
+        }
+    }
+
+    fn lang_type(&mut self) -> Result<Type, CowError> {
+        if let TokenKind::Ident(name) = self.peek() {
+            if let Some(lang_type) = type_from_name(name) {
+                self.advance();
+                return Ok(lang_type);
+            }
+        }
+        Err(self.expected("a type"))
+    }
+
+    fn int<T: TryFrom<i128>>(&mut self, what: &str) -> Result<T, CowError> {
+        if let TokenKind::Number(text) = self.peek() {
+            if let Some(value) = parse_int(text).and_then(|value| T::try_from(value).ok()) {
+                self.advance();
+                return Ok(value);
+            }
+        }
+        Err(self.expected(what))
+    }
+
+    fn line_end(&mut self) -> Result<(), CowError> {
+        match self.peek() {
+            TokenKind::Newline => {
+                self.advance();
+                Ok(())
+            }
+            TokenKind::End => Ok(()),
+            _ => Err(self.expected("the end of the line")),
+        }
+    }
+
+    fn parse_module(&mut self, compiler: &mut Compiler) -> Result<(), CowError> {
+        let mut funcs = vec![];
+        loop {
+            self.skip_newlines();
+            if *self.peek() == TokenKind::End {
+                break;
+            }
+
+            match self.peek() {
+                TokenKind::Ident(keyword) if keyword == "global" => self.parse_global(compiler)?,
+                TokenKind::Ident(keyword) if keyword == "extern" => self.parse_extern(compiler)?,
+                TokenKind::Ident(keyword) if keyword == "function" => funcs.push(self.parse_func_header(compiler)?),
+                _ => return Err(self.expected("`global`, `extern` or `function`")),
+            }
+        }
+
+        // every function is declared before any body is parsed, so a function can call the ones after it
+        for func in &funcs {
+            self.parse_func_body(compiler, func)?;
+        }
+        Ok(())
+    }
+
+    // global @name: size 8, align 8, mutable = [01 02]
+    fn parse_global(&mut self, compiler: &mut Compiler) -> Result<(), CowError> {
+        self.advance();
+        let (name, name_pos) = self.global_name()?;
+        self.punct(':')?;
+        self.keyword("size")?;
+        let size = self.int::<usize>("a size")?;
+        self.punct(',')?;
+        self.keyword("align")?;
+        let align = self.int::<usize>("an alignment")?;
+        self.punct(',')?;
+        let mutable = match self.ident()? {
+            (access, _) if access == "mutable" => true,
+            (access, _) if access == "readonly" => false,
+            (_, pos) => return Err(self.error_at(pos, "expected `mutable` or `readonly`".to_string())),
+        };
+
+        let mut bytes = vec![];
+        if self.eat_punct('=') {
+            self.punct('[')?;
+            while !self.eat_punct(']') {
+                let byte = match self.peek() {
+                    TokenKind::Number(text) | TokenKind::Ident(text) => u8::from_str_radix(text, 16).ok(),
+                    _ => None,
+                };
+                match byte {
+                    Some(byte) => bytes.push(byte),
+                    None => return Err(self.expected("a hex byte or `]`")),
+                }
+                self.advance();
+            }
+        }
+        self.line_end()?;
+
+        compiler.add_global(&name, size, align, &bytes, mutable).map_err(|error| self.wrap(name_pos, error))
+    }
+
+    // the host functions cannot be created from the text, the declaration only checks the registered one
+    fn parse_extern(&mut self, compiler: &Compiler) -> Result<(), CowError> {
+        self.advance();
+        let (name, name_pos) = self.global_name()?;
+        self.punct('(')?;
+        let mut args = vec![];
+        if !self.eat_punct(')') {
+            loop {
+                args.push(self.lang_type()?);
+                if self.eat_punct(')') {
+                    break;
+                }
+                self.punct(',')?;
+            }
+        }
+        if *self.peek() != TokenKind::Arrow {
+            return Err(self.expected("`->`"));
+        }
+        self.advance();
+        let return_type = self.lang_type()?;
+        self.line_end()?;
+
+        match compiler.host_signature(&name) {
+            Some(signature) if *signature.args() == args && *signature.return_type() == return_type => Ok(()),
+            Some(_) => Err(self.error_at(name_pos, format!("the host function {} is registered with another signature", name))),
+            None => Err(self.error_at(name_pos, format!("the host function {} is not registered", name))),
+        }
+    }
+
+    // function @name(v0: i64, v1: f64) -> i64 {
+    fn parse_func_header(&mut self, compiler: &mut Compiler) -> Result<FuncDecl, CowError> {
+        let keyword_pos = self.advance();
+        let (name, name_pos) = self.global_name()?;
+        self.punct('(')?;
+        let (mut params, mut args) = (vec![], vec![]);
+        if !self.eat_punct(')') {
+            loop {
+                let (param, param_pos) = self.ident()?;
+                self.punct(':')?;
+                args.push(self.lang_type()?);
+                params.push((param, param_pos));
+                if self.eat_punct(')') {
+                    break;
+                }
+                self.punct(',')?;
+            }
+        }
+        if *self.peek() != TokenKind::Arrow {
+            return Err(self.expected("`->`"));
+        }
+        self.advance();
+        let return_type = self.lang_type()?;
+        self.punct('{')?;
+        self.line_end()?;
+
+        compiler.add_func(&name, &args, return_type).map_err(|error| self.wrap(name_pos, error))?;
+
+        // the body ends at the first closing brace, no instruction contains one
+        let body_start = self.pos;
+        while *self.peek() != TokenKind::Punct('}') {
+            if *self.peek() == TokenKind::End {
+                return Err(self.error_at(keyword_pos, format!("the body of {} is not closed", name)));
+            }
+            self.advance();
+        }
+        let body_end = self.advance();
+        self.line_end()?;
+
+        Ok(FuncDecl { name, name_pos, params, body_start, body_end })
+    }
+
+    fn parse_func_body(&mut self, compiler: &mut Compiler, func: &FuncDecl) -> Result<(), CowError> {
+        let builder = compiler.get_func_mut_by_name(&func.name)?.builder();
+        let mut body = BodyParser {
+            func_id: builder.func_id(),
+            values: HashMap::new(),
+            declared: HashMap::new(),
+            blocks: HashMap::new(),
+            forward: HashMap::new(),
+            block_pos: HashMap::new(),
+            instr_pos: HashMap::new(),
+        };
+        for (index, (param, pos)) in func.params.iter().enumerate() {
+            body.define(self, param, *pos, builder.param(index)?)?;
+        }
+
+        let lines = self.body_lines(func);
+        body.declare(self, builder, &lines)?;
+        body.build(self, builder, &lines)?;
+        body.resolve_forward(builder);
+
+        // the errors of the verifier are reported at the line of the instruction or of the block
+        let built = compiler.get_func_by_name(&func.name)?;
+        verify_function(built).map_err(|error| {
+            let pos = match (error.block(), error.instr_index()) {
+                (Some(block), Some(index)) => body.instr_pos.get(&block).and_then(|positions| positions.get(index)).copied(),
+                (Some(block), None) => body.block_pos.get(&block).copied(),
+                _ => None,
+            };
+            self.error_at(pos.unwrap_or(func.name_pos), error.message().clone())
+        })
+    }
+
+    // the position of the first token of every line of the body that is not empty
+    fn body_lines(&mut self, func: &FuncDecl) -> Vec<usize> {
+        let mut lines = vec![];
+        self.pos = func.body_start;
+        while self.pos < func.body_end {
+            self.skip_newlines();
+            if self.pos >= func.body_end {
+                break;
+            }
+            lines.push(self.pos);
+            while self.pos < func.body_end && *self.peek() != TokenKind::Newline {
+                self.advance();
+            }
+        }
+        lines
+    }
+
+    fn line_kind(&self, line: usize) -> LineKind {
+        let (first, second) = (&self.tokens[line].kind, &self.tokens[line + 1].kind);
+        match (first, second) {
+            (TokenKind::Ident(_), TokenKind::Punct('(')) => LineKind::Block,
+            (TokenKind::Ident(_), TokenKind::Punct(':')) if self.tokens[line + 2].kind == TokenKind::Newline => LineKind::Block,
+            (TokenKind::Ident(_), TokenKind::Punct(':')) => LineKind::Def,
+            _ => LineKind::Instr,
+        }
+    }
+}
+
+// the names of a function body, a value can be used before the line defining it
+struct BodyParser {
+    func_id: usize,
+    values: HashMap<String, Value>,
+    // the type of every value defined by an instruction of the body
+    declared: HashMap<String, Type>,
+    blocks: HashMap<String, Block>,
+    // placeholder values standing for values defined further down, by placeholder id
+    forward: HashMap<usize, String>,
+    // the position of the label of every block and of the name of every instruction in it, by block id
+    block_pos: HashMap<usize, usize>,
+    instr_pos: HashMap<usize, Vec<usize>>,
+}
+
+impl BodyParser {
+    fn define(&mut self, parser: &Parser, name: &str, pos: usize, value: Value) -> Result<(), CowError> {
+        if self.values.contains_key(name) {
+            return Err(parser.error_at(pos, format!("the value {} is defined twice", name)));
+        }
+        self.values.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    // create the blocks and find the type of every value before any instruction is added
+    fn declare(&mut self, parser: &mut Parser, builder: &mut Builder, lines: &[usize]) -> Result<(), CowError> {
+        for &line in lines {
+            parser.pos = line;
+            match parser.line_kind(line) {
+                LineKind::Block => {
+                    let (name, name_pos) = parser.ident()?;
+                    if self.blocks.contains_key(&name) {
+                        return Err(parser.error_at(name_pos, format!("the block {} is defined twice", name)));
+                    }
+
+                    let mut params = vec![];
+                    if parser.eat_punct('(') {
+                        loop {
+                            let (param, param_pos) = parser.ident()?;
+                            parser.punct(':')?;
+                            let param_type = parser.lang_type()?;
+                            if param_type == Type::void() {
+                                return Err(parser.error_at(param_pos, "a block parameter cannot be void".to_string()));
+                            }
+                            params.push((param, param_pos, param_type));
+                            if parser.eat_punct(')') {
+                                break;
+                            }
+                            parser.punct(',')?;
+                        }
+                    }
+                    parser.punct(':')?;
+                    parser.line_end()?;
+
+                    // the first block is the entry block of the function, it has no parameters
+                    let block = if self.blocks.is_empty() {
+                        if !params.is_empty() {
+                            return Err(parser.error_at(name_pos, "the entry block cannot have parameters".to_string()));
+                        }
+                        Block::new(self.func_id, 0)
+                    } else {
+                        let types: Vec<Type> = params.iter().map(|(_, _, param_type)| param_type.clone()).collect();
+                        builder.create_block_with_params(&types).map_err(|error| parser.wrap(name_pos, error))?
+                    };
+                    self.blocks.insert(name, block);
+                    self.block_pos.insert(block.get_id(), name_pos);
+
+                    for (index, (param, param_pos, _)) in params.iter().enumerate() {
+                        self.define(parser, param, *param_pos, builder.block_param(block, index)?)?;
+                    }
+                }
+                LineKind::Def => {
+                    let (name, name_pos) = parser.ident()?;
+                    parser.punct(':')?;
+                    let value_type = parser.lang_type()?;
+                    if self.values.contains_key(&name) || self.declared.contains_key(&name) {
+                        return Err(parser.error_at(name_pos, format!("the value {} is defined twice", name)));
+                    }
+                    self.declared.insert(name, value_type);
+                }
+                LineKind::Instr => {}
+            }
+
+            if self.blocks.is_empty() {
+                return Err(parser.error_at(line, "expected a block before the first instruction".to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    fn build(&mut self, parser: &mut Parser, builder: &mut Builder, lines: &[usize]) -> Result<(), CowError> {
+        let mut current_block = 0;
+        for &line in lines {
+            parser.pos = line;
+            match parser.line_kind(line) {
+                LineKind::Block => {
+                    let (name, _) = parser.ident()?;
+                    current_block = self.blocks[&name].get_id();
+                    builder.set_current_block(self.blocks[&name])?;
+                }
+                LineKind::Def => {
+                    let (name, name_pos) = parser.ident()?;
+                    parser.punct(':')?;
+                    let value_type = parser.lang_type()?;
+                    parser.punct('=')?;
+
+                    let (op, op_pos) = parser.ident()?;
+                    let value = self.parse_instr(parser, builder, &op, op_pos, Some(&value_type))?;
+                    parser.line_end()?;
+
+                    match value {
+                        Some(value) if value.get_type() == value_type => self.define(parser, &name, name_pos, value)?,
+                        Some(value) if value.get_type() != Type::void() => {
+                            return Err(parser.error_at(op_pos, format!("{} gives {}, not {}", op, value.get_type(), value_type)));
+                        }
+                        _ => return Err(parser.error_at(op_pos, format!("{} does not give a value", op))),
+                    }
+                    self.instr_pos.entry(current_block).or_default().push(op_pos);
+                }
+                LineKind::Instr => {
+                    let (op, op_pos) = parser.ident()?;
+                    let value = self.parse_instr(parser, builder, &op, op_pos, None)?;
+                    parser.line_end()?;
+
+                    if let Some(value) = value {
+                        if value.get_type() != Type::void() {
+                            return Err(parser.error_at(op_pos, format!("the {} value given by {} has no name", value.get_type(), op)));
+                        }
+                    }
+                    self.instr_pos.entry(current_block).or_default().push(op_pos);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // replace the placeholders by the values once they are all defined
+    fn resolve_forward(&self, builder: &mut Builder) {
+        for block in builder.blocks_mut() {
+            for instr in block.instructions_mut() {
+                for value in instr.used_values_mut() {
+                    if let Some(name) = self.forward.get(&value.get_id()) {
+                        *value = self.values[name].clone();
+                    }
+                }
+            }
+        }
+    }
+
+    fn value(&mut self, parser: &mut Parser) -> Result<Value, CowError> {
+        let (name, pos) = parser.ident()?;
+        if let Some(value) = self.values.get(&name) {
+            return Ok(value.clone());
+        }
+        match self.declared.get(&name) {
+            Some(value_type) => {
+                // the ids are counted down from the largest one so that they never meet the ids of the builder
+                let placeholder = Value::new(self.func_id, usize::MAX - self.forward.len(), value_type.clone());
+                self.forward.insert(placeholder.get_id(), name);
+                Ok(placeholder)
+            }
+            None => Err(parser.error_at(pos, format!("the value {} is not defined", name))),
+        }
+    }
+
+    fn value_list(&mut self, parser: &mut Parser) -> Result<Vec<Value>, CowError> {
+        parser.punct('(')?;
+        let mut values = vec![];
+        if !parser.eat_punct(')') {
+            loop {
+                values.push(self.value(parser)?);
+                if parser.eat_punct(')') {
+                    break;
+                }
+                parser.punct(',')?;
+            }
+        }
+        Ok(values)
+    }
+
+    fn binary(&mut self, parser: &mut Parser) -> Result<(Value, Value), CowError> {
+        let left = self.value(parser)?;
+        parser.punct(',')?;
+        let right = self.value(parser)?;
+        Ok((left, right))
+    }
+
+    // block1 or block1(v2, v3)
+    fn branch_target(&mut self, parser: &mut Parser) -> Result<(Block, Vec<Value>), CowError> {
+        let (name, pos) = parser.ident()?;
+        let block = match self.blocks.get(&name) {
+            Some(block) => *block,
+            None => return Err(parser.error_at(pos, format!("the block {} is not defined", name))),
+        };
+        let args = if *parser.peek() == TokenKind::Punct('(') { self.value_list(parser)? } else { vec![] };
+        Ok((block, args))
+    }
+
+    fn constant(&mut self, parser: &mut Parser, builder: &mut Builder, value_type: &Type) -> Result<Value, CowError> {
+        let text = match parser.peek() {
+            TokenKind::Number(text) | TokenKind::Ident(text) => text.clone(),
+            _ => return Err(parser.expected("a constant")),
+        };
+
+        // a float written in hex gives its bits, which is how a nan is written
+        let value = match value_type.to_string().as_str() {
+            "i8" => parse_int(&text).and_then(|value| i8::try_from(value).ok()).map(|value| builder.const_i8(value)),
+            "i16" => parse_int(&text).and_then(|value| i16::try_from(value).ok()).map(|value| builder.const_i16(value)),
+            "i32" => parse_int(&text).and_then(|value| i32::try_from(value).ok()).map(|value| builder.const_i32(value)),
+            "i64" => parse_int(&text).and_then(|value| i64::try_from(value).ok()).map(|value| builder.const_i64(value)),
+            "ptr" => parse_int(&text).and_then(|value| usize::try_from(value).ok()).map(|value| builder.const_ptr(value)),
+            "f64" => match text.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16).ok().map(f64::from_bits),
+                None => text.parse::<f64>().ok(),
+            }.map(|value| builder.const_f64(value)),
+            "f32" => match text.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16).ok().map(f32::from_bits),
+                None => text.parse::<f64>().ok().map(|value| value as f32),
+            }.map(|value| builder.const_f32(value)),
+            _ => return Err(parser.error_at(parser.pos, format!("there is no constant of type {}", value_type))),
+        };
+
+        match value {
+            Some(value) => {
+                parser.advance();
+                Ok(value)
+            }
+            None => Err(parser.expected(&format!("a constant of type {}", value_type))),
+        }
+    }
+
+    // the type of the value an instruction defines, for the instructions that cannot find it from their operands
+    fn result_type(parser: &Parser, op: &str, op_pos: usize, declared: Option<&Type>) -> Result<Type, CowError> {
+        match declared {
+            Some(value_type) => Ok(value_type.clone()),
+            None => Err(parser.error_at(op_pos, format!("the value given by {} needs a name and a type", op))),
+        }
+    }
+
+    // parse the operands of the instruction and add it, the declared type is the one of the value it defines
+    fn parse_instr(&mut self, parser: &mut Parser, builder: &mut Builder, op: &str, op_pos: usize, declared: Option<&Type>) -> Result<Option<Value>, CowError> {
+        let value = match op {
+            "const" => {
+                let value_type = Self::result_type(parser, op, op_pos, declared)?;
+                self.constant(parser, builder, &value_type)?
+            }
+            "global_addr" => {
+                let (name, _) = parser.global_name()?;
+                builder.global_addr(&name).map_err(|error| parser.wrap(op_pos, error))?
+            }
+
+            "add" | "sub" | "mul" | "div" | "udiv" | "srem" | "urem" | "eq" | "diff" | "larger" | "larger_eq" | "smaller" |
+            "smaller_eq" | "ordered" | "unordered" | "ugt" | "uge" | "ult" | "ule" | "lshr" | "ashr" | "and" | "or" | "xor" |
+            "shl" | "rotl" | "rotr" => {
+                let (left, right) = self.binary(parser)?;
+                match op {
+                    "add" => builder.add(left, right),
+                    "sub" => builder.sub(left, right),
+                    "mul" => builder.mul(left, right),
+                    "div" => builder.div(left, right),
+                    "udiv" => builder.udiv(left, right),
+                    "srem" => builder.srem(left, right),
+                    "urem" => builder.urem(left, right),
+                    "eq" => builder.eq(left, right),
+                    "diff" => builder.diff(left, right),
+                    "larger" => builder.larger(left, right),
+                    "larger_eq" => builder.larger_eq(left, right),
+                    "smaller" => builder.smaller(left, right),
+                    "smaller_eq" => builder.smaller_eq(left, right),
+                    "ordered" => builder.ordered(left, right).map_err(|error| parser.wrap(op_pos, error))?,
+                    "unordered" => builder.unordered(left, right).map_err(|error| parser.wrap(op_pos, error))?,
+                    "ugt" => builder.ugt(left, right),
+                    "uge" => builder.uge(left, right),
+                    "ult" => builder.ult(left, right),
+                    "ule" => builder.ule(left, right),
+                    "lshr" => builder.lshr(left, right),
+                    "ashr" => builder.ashr(left, right),
+                    "and" => builder.and(left, right),
+                    "or" => builder.or(left, right),
+                    "xor" => builder.xor(left, right),
+                    "shl" => builder.shl(left, right),
+                    "rotl" => builder.rotl(left, right),
+                    _ => builder.rotr(left, right),
+                }
+            }
+            "neg" => {
+                let value = self.value(parser)?;
+                builder.neg(value)
+            }
+            "not" => {
+                let value = self.value(parser)?;
+                builder.not(value)
+            }
+            "abs" => {
+                let value = self.value(parser)?;
+                builder.abs(value).map_err(|error| parser.wrap(op_pos, error))?
+            }
+
+            "shl_imm" | "lshr_imm" | "ashr_imm" | "rotl_imm" | "rotr_imm" => {
+                let value = self.value(parser)?;
+                parser.punct(',')?;
+                let amount = parser.int::<u8>("a shift amount")?;
+                match op {
+                    "shl_imm" => builder.shl_imm(value, amount),
+                    "lshr_imm" => builder.lshr_imm(value, amount),
+                    "ashr_imm" => builder.ashr_imm(value, amount),
+                    "rotl_imm" => builder.rotl_imm(value, amount),
+                    _ => builder.rotr_imm(value, amount),
+                }
+            }
+
+            "sext" | "zext" | "trunc" | "sitofp" | "uitofp" | "fptosi" | "fptoui" | "fpext" | "fptrunc" | "bitcast" | "ptrtoint" | "inttoptr" => {
+                let to = Self::result_type(parser, op, op_pos, declared)?;
+                let value = self.value(parser)?;
+                match op {
+                    "sext" => builder.sext(value, to),
+                    "zext" => builder.zext(value, to),
+                    "trunc" => builder.trunc(value, to),
+                    "sitofp" => builder.sitofp(value, to),
+                    "uitofp" => builder.uitofp(value, to),
+                    "fptosi" => builder.fptosi(value, to),
+                    "fptoui" => builder.fptoui(value, to),
+                    "fpext" => builder.fpext(value, to),
+                    "fptrunc" => builder.fptrunc(value, to),
+                    "bitcast" => builder.bitcast(value, to),
+                    "ptrtoint" => builder.ptrtoint(value, to),
+                    _ => builder.inttoptr(value, to),
+                }.map_err(|error| parser.wrap(op_pos, error))?
+            }
+
+            "stack_slot" => {
+                let size = parser.int::<u32>("a size")?;
+                parser.punct(',')?;
+                let align = parser.int::<u32>("an alignment")?;
+                builder.stack_slot(size, align).map_err(|error| parser.wrap(op_pos, error))?
+            }
+            "alloca" => {
+                let size_value = self.value(parser)?;
+                builder.alloca(size_value).map_err(|error| parser.wrap(op_pos, error))?
+            }
+            "load" | "load_sext" | "load_zext" => {
+                let value_type = Self::result_type(parser, op, op_pos, declared)?;
+                let mem_type = if op == "load" {
+                    value_type.clone()
+                } else {
+                    let mem_type = parser.lang_type()?;
+                    parser.punct(',')?;
+                    mem_type
+                };
+                let ptr = self.value(parser)?;
+                parser.punct(',')?;
+                let offset = parser.int::<i32>("an offset")?;
+                match op {
+                    "load" => builder.load(value_type, ptr, offset),
+                    "load_sext" => builder.load_sext(mem_type, value_type, ptr, offset),
+                    _ => builder.load_zext(mem_type, value_type, ptr, offset),
+                }.map_err(|error| parser.wrap(op_pos, error))?
+            }
+            "store" => {
+                let (ptr, value) = self.binary(parser)?;
+                parser.punct(',')?;
+                let offset = parser.int::<i32>("an offset")?;
+                builder.store(ptr, value, offset).map_err(|error| parser.wrap(op_pos, error))?;
+                return Ok(None);
+            }
+
+            "br" => {
+                let (block, args) = self.branch_target(parser)?;
+                builder.br(block, &args).map_err(|error| parser.wrap(op_pos, error))?;
+                return Ok(None);
+            }
+            "cond_br" => {
+                let cond = self.value(parser)?;
+                parser.punct(',')?;
+                let (block_true, true_args) = self.branch_target(parser)?;
+                parser.punct(',')?;
+                let (block_false, false_args) = self.branch_target(parser)?;
+                builder.cond_br(cond, block_true, &true_args, block_false, &false_args).map_err(|error| parser.wrap(op_pos, error))?;
+                return Ok(None);
+            }
+
+            "call" => {
+                let (name, _) = parser.global_name()?;
+                let args = self.value_list(parser)?;
+                builder.call(&name, &args).map_err(|error| parser.wrap(op_pos, error))?
+            }
+            "call_ptr" => {
+                let return_type = declared.cloned().unwrap_or(Type::void());
+                let ptr = self.value(parser)?;
+                let args = self.value_list(parser)?;
+                builder.call_ptr(ptr, &args, return_type)
+            }
+            "ret" => {
+                let value = self.value(parser)?;
+                builder.ret(value);
+                return Ok(None);
+            }
+            "ret_void" => {
+                builder.ret_void();
+                return Ok(None);
+            }
+
+            _ => return Err(parser.error_at(op_pos, format!("unknown instruction `{}`", op))),
+        };
+        Ok(Some(value))
+    }
+}
+
+// add the globals and the functions of the text to the compiler
+pub(crate) fn parse_module(compiler: &mut Compiler, text: &str) -> Result<(), CowError> {
+    let tokens = lex(text)?;
+    let mut parser = Parser { tokens, pos: 0 };
+    parser.parse_module(compiler)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::Compiler;
+    use crate::error::CowError;
+    use crate::jit::code_arena::ArenaConfig;
+    use crate::lang::lang_type::Type;
+
+    const MODULE: &str = "\
+global @total: size 8, align 8, mutable = [05]
+global @values: size 16, align 8, readonly = [01 00 00 00 00 00 00 00 02]
+
+function @bits() -> i64 {
+block0:
+    v0: f64 = const 0x7ff8000000000001
+    v1: f32 = const -1.5
+    v2: bool = ordered v0, v0
+    v3: i64 = bitcast v0
+    ret v3
+}
+
+function @sum(v0: i64) -> i64 {
+block0:
+    v1: i64 = const 0
+    br block1(v1, v1)
+block1(v2: i64, v3: i64):
+    v4: bool = smaller v2, v0
+    cond_br v4, block2, block3
+block2:
+    v5: i64 = const 1
+    v6: i64 = add v2, v5
+    v7: i64 = call @twice(v2)
+    v8: i64 = add v3, v7
+    v9: ptr = global_addr @total
+    store v9, v8, 0
+    br block1(v6, v8)
+block3:
+    ret v3
+}
+
+function @twice(v0: i64) -> i64 {
+block0:
+    v1: i64 = add v0, v0
+    ret v1
+}
+";
+
+    fn parse_error(compiler: &mut Compiler, text: &str) -> (usize, usize) {
+        match compiler.parse_ir(text) {
+            Err(CowError::Parse(error)) => (error.line(), error.column()),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn printing_parsed_ir_gives_the_same_text() {
+        let mut compiler = Compiler::new().unwrap();
+        compiler.parse_ir(MODULE).unwrap();
+        assert_eq!(compiler.print_ir(), MODULE);
+
+        let mut reparsed = Compiler::new().unwrap();
+        reparsed.parse_ir(&compiler.print_ir()).unwrap();
+        assert_eq!(reparsed.print_ir(), MODULE);
+
+        reparsed.jit().unwrap();
+        assert_eq!(reparsed.get_typed::<fn(i64) -> i64>("sum").unwrap().call(4), 12);
+        assert_eq!(reparsed.global_bytes("total").unwrap(), 12i64.to_le_bytes());
+    }
+
+    #[test]
+    fn errors_give_the_line_and_the_column() {
+        let mut compiler = Compiler::new().unwrap();
+        let text = "function @f(v0: i64) -> i64 {\nblock0:\n    v1: i64 = add v0, v7\n    ret v1\n}\n";
+        assert_eq!(parse_error(&mut compiler, text), (3, 23));
+
+        let text = "function @f() -> i64 {\nblock0:\n    v0: i64 = const 1 $\n}\n";
+        assert_eq!(parse_error(&mut compiler, text), (3, 23));
+
+        let text = "global @g: size 8, align 8, writable\n";
+        assert_eq!(parse_error(&mut compiler, text), (1, 29));
+    }
+
+    #[test]
+    fn values_can_be_used_before_the_line_defining_them() {
+        let mut compiler = Compiler::new().unwrap();
+        compiler.parse_ir("\
+function @f(v0: i64) -> i64 {
+block0:
+    br block2
+block1:
+    v2: i64 = add v1, v1
+    ret v2
+block2:
+    v1: i64 = add v0, v0
+    br block1
+}
+").unwrap();
+
+        compiler.jit().unwrap();
+        assert_eq!(compiler.get_typed::<fn(i64) -> i64>("f").unwrap().call(3), 12);
+    }
+
+    #[test]
+    fn nan_constants_keep_their_bits() {
+        let mut compiler = Compiler::new().unwrap();
+        compiler.parse_ir("\
+function @f64_nan() -> i64 {
+block0:
+    v0: f64 = const 0x7ff4000000000123
+    v1: i64 = bitcast v0
+    ret v1
+}
+
+function @f32_nan() -> i32 {
+block0:
+    v0: f32 = const 0xffc00042
+    v1: i32 = bitcast v0
+    ret v1
+}
+").unwrap();
+        let printed = compiler.print_ir();
+        assert!(printed.contains("const 0x7ff4000000000123") && printed.contains("const 0xffc00042"));
+
+        compiler.jit().unwrap();
+        assert_eq!(compiler.get_typed::<fn() -> i64>("f64_nan").unwrap().call(), 0x7ff4000000000123);
+        assert_eq!(compiler.get_typed::<fn() -> i32>("f32_nan").unwrap().call(), 0xffc00042u32 as i32);
+    }
+
+    #[test]
+    fn rejected_text_adds_nothing() {
+        let mut compiler = Compiler::with_arena_config(ArenaConfig { alignment: 16, reserve_size: 1024 * 1024 }).unwrap();
+        compiler.add_global("first_global", 8, 8, &[], true).unwrap();
+        let builder = compiler.add_func("kept", &[], Type::i64()).unwrap().builder();
+        let value = builder.const_i64(7);
+        builder.ret(value);
+        let before = compiler.print_ir();
+
+        let text = "\
+global @added: size 8, align 8, mutable
+global @big: size 524288, align 8, readonly
+
+function @first() -> i64 {
+block0:
+    v0: i64 = call @kept()
+    ret v0
+}
+
+function @second() -> i64 {
+block0:
+    v0: i64 = call @missing()
+    ret v0
+}
+";
+        // the globals of a rejected text do not keep any memory of the arena, which only has room for two big ones
+        for _ in 0..4 {
+            assert_eq!(parse_error(&mut compiler, text), (12, 15));
+        }
+        assert_eq!(compiler.print_ir(), before);
+        assert_eq!(compiler.get_global("added").err(), Some(CowError::UnknownGlobal("added".to_string())));
+        assert!(compiler.get_func_by_name("first").is_err());
+        compiler.add_global("next_global", 8, 8, &[], true).unwrap();
+        let first_global = compiler.get_global("first_global").unwrap().address();
+        assert_eq!(compiler.get_global("next_global").unwrap().address(), first_global + 8);
+
+        // the names can be used again once the text is fixed
+        compiler.parse_ir(&text.replace("@missing", "@first")).unwrap();
+        compiler.jit().unwrap();
+        assert_eq!(compiler.get_typed::<fn() -> i64>("second").unwrap().call(), 7);
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::lang::block::Block;
+use crate::lang::function::Function;
+use crate::lang::global::Global;
+use crate::lang::instr::Instr;
+use crate::lang::lang_type::Type;
+use crate::lang::signature::Signature;
+use crate::lang::value::Value;
+
+// the values are numbered in the order they are printed, so that the same function always prints the same way
+// whatever the order its values were created in
+struct ValueNames {
+    func_id: usize,
+    numbers: HashMap<usize, usize>,
+}
+
+impl ValueNames {
+    fn new(func: &Function) -> Self {
+        let builder = func.builder_ref();
+        let mut defined: Vec<&Value> = builder.params().iter().collect();
+        for block in builder.blocks() {
+            defined.extend(block.params().iter());
+            defined.extend(block.instructions().iter().filter_map(|instr| instr.gen_value()).filter(|value| value.get_type() != Type::void()));
+        }
+
+        let numbers = defined.iter().enumerate().map(|(number, value)| (value.get_id(), number)).collect();
+        ValueNames { func_id: builder.func_id(), numbers }
+    }
+
+    fn name(&self, value: &Value) -> String {
+        match self.numbers.get(&value.get_id()).filter(|_| value.func_id() == self.func_id) {
+            Some(number) => format!("v{}", number),
+            // a value that is not defined in the function or belongs to another one, the verifier rejects it
+            None => format!("?v{}", value.get_id()),
+        }
+    }
+
+    fn list(&self, values: &[Value]) -> String {
+        values.iter().map(|value| self.name(value)).collect::<Vec<_>>().join(", ")
+    }
+
+    fn typed_list(&self, values: &[Value]) -> String {
+        values.iter().map(|value| format!("{}: {}", self.name(value), value.get_type())).collect::<Vec<_>>().join(", ")
+    }
+}
+
+pub(crate) fn write_function(f: &mut fmt::Formatter<'_>, func: &Function) -> fmt::Result {
+    let names = ValueNames::new(func);
+    let builder = func.builder_ref();
+
+    writeln!(f, "function @{}({}) -> {} {{", func.name(), names.typed_list(builder.params()), func.return_type())?;
+    for (block_id, block) in builder.blocks().iter().enumerate() {
+        if block.params().is_empty() {
+            writeln!(f, "block{}:", block_id)?;
+        } else {
+            writeln!(f, "block{}({}):", block_id, names.typed_list(block.params()))?;
+        }
+
+        for instr in block.instructions() {
+            write!(f, "    ")?;
+            write_instr(f, instr, &names)?;
+            writeln!(f)?;
+        }
+    }
+    writeln!(f, "}}")
+}
+
+fn write_instr(f: &mut fmt::Formatter<'_>, instr: &Instr, names: &ValueNames) -> fmt::Result {
+    if let Some(value) = instr.gen_value() {
+        if value.get_type() != Type::void() {
+            write!(f, "{}: {} = ", names.name(value), value.get_type())?;
+        }
+    }
+    write!(f, "{}", instr.name())?;
+
+    match instr {
+        Instr::ConstInt64 { const_value, gen_value } if gen_value.get_type().is_float() => {
+            write!(f, " {}", float_literal(f64::from_bits(*const_value as u64), *const_value as u64))
+        }
+        Instr::ConstInt32 { const_value, gen_value } if gen_value.get_type().is_float() => {
+            write!(f, " {}", float_literal(f32::from_bits(*const_value as u32) as f64, *const_value as u32 as u64))
+        }
+        Instr::ConstInt64 { const_value, .. } => write!(f, " {}", const_value),
+        Instr::ConstInt32 { const_value, .. } => write!(f, " {}", const_value),
+        Instr::ConstInt16 { const_value, .. } => write!(f, " {}", const_value),
+        Instr::ConstInt8 { const_value, .. } => write!(f, " {}", const_value),
+        Instr::ConstPtr { const_value, .. } => write!(f, " {:#x}", const_value),
+        Instr::GlobalAddr { name, .. } => write!(f, " @{}", name),
+
+        Instr::ShlImm { value, amount, .. } |
+        Instr::LShrImm { value, amount, .. } |
+        Instr::AShrImm { value, amount, .. } |
+        Instr::RotlImm { value, amount, .. } |
+        Instr::RotrImm { value, amount, .. } => write!(f, " {}, {}", names.name(value), amount),
+
+        Instr::StackSlot { size, align, .. } => write!(f, " {}, {}", size, align),
+
+        Instr::Load { value_to_load, offset, mem_type, gen_value, .. } => {
+            if *mem_type != gen_value.get_type() {
+                write!(f, " {},", mem_type)?;
+            }
+            write!(f, " {}, {}", names.name(value_to_load), offset)
+        }
+        Instr::Store { value_ptr, value_to_store, offset } => {
+            write!(f, " {}, {}, {}", names.name(value_ptr), names.name(value_to_store), offset)
+        }
+
+        Instr::Br { block_to_br, args } => write!(f, " {}", branch_target(*block_to_br, args, names)),
+        Instr::CondBr { value_cond, block_to_br_true, true_args, block_to_br_false, false_args } => {
+            write!(f, " {}, {}, {}", names.name(value_cond), branch_target(*block_to_br_true, true_args, names), branch_target(*block_to_br_false, false_args, names))
+        }
+
+        Instr::CallPtr { ptr_to_call, args, .. } => write!(f, " {}({})", names.name(ptr_to_call), names.list(args)),
+        Instr::CallFunc { func_to_call, args, .. } => write!(f, " @{}({})", func_to_call, names.list(args)),
+
+        _ => {
+            let operands: Vec<Value> = instr.used_values().into_iter().cloned().collect();
+            if operands.is_empty() {
+                Ok(())
+            } else {
+                write!(f, " {}", names.list(&operands))
+            }
+        }
+    }
+}
+
+fn branch_target(block: Block, args: &[Value], names: &ValueNames) -> String {
+    if args.is_empty() {
+        format!("block{}", block.get_id())
+    } else {
+        format!("block{}({})", block.get_id(), names.list(args))
+    }
+}
+
+// the shortest decimal reading back as the same float, a nan keeps its payload by being written as its bits
+fn float_literal(value: f64, bits: u64) -> String {
+    if value.is_nan() {
+        format!("{:#x}", bits)
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+pub(crate) fn write_global(f: &mut fmt::Formatter<'_>, name: &str, global: &Global) -> fmt::Result {
+    let access = if global.is_mutable() { "mutable" } else { "readonly" };
+    write!(f, "global @{}: size {}, align {}, {}", name, global.size(), global.align(), access)?;
+
+    // the trailing zeros are implied by the size
+    // the globals are printed by their compiler, which keeps their data alive
+    let bytes = unsafe { global.read_bytes() };
+    let used = bytes.iter().rposition(|byte| *byte != 0).map_or(0, |last| last + 1);
+    if used > 0 {
+        let data: Vec<String> = bytes[..used].iter().map(|byte| format!("{:02x}", byte)).collect();
+        write!(f, " = [{}]", data.join(" "))?;
+    }
+    writeln!(f)
+}
+
+pub(crate) fn write_extern(f: &mut fmt::Formatter<'_>, name: &str, signature: &Signature) -> fmt::Result {
+    let args: Vec<String> = signature.args().iter().map(|arg| arg.to_string()).collect();
+    writeln!(f, "extern @{}({}) -> {}", name, args.join(", "), signature.return_type())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::Compiler;
+    use crate::lang::lang_type::Type;
+
+    #[test]
+    fn a_compiler_prints_its_globals_externs_and_functions_sorted_by_name() {
+        let mut compiler = Compiler::new().unwrap();
+        compiler.add_global("zeros", 4, 4, &[], true).unwrap();
+        compiler.add_global("data", 8, 2, &[0, 7, 0, 0], false).unwrap();
+        compiler.register_extern("host", std::ptr::null(), &[Type::ptr(), Type::f32()], Type::void()).unwrap();
+
+        let builder = compiler.add_func("g", &[], Type::i64()).unwrap().builder();
+        let three = builder.const_i64(3);
+        builder.ret(three);
+
+        let builder = compiler.add_func("f", &[Type::ptr(), Type::f32()], Type::i64()).unwrap().builder();
+        let (ptr, float) = (builder.param(0).unwrap(), builder.param(1).unwrap());
+        // the param of the exit block is created before the values of the entry block
+        let exit = builder.create_block_with_params(&[Type::i64()]).unwrap();
+        let slot = builder.stack_slot(12, 4).unwrap();
+        let narrow = builder.load_zext(Type::i8(), Type::i32(), slot, -4).unwrap();
+        let rotated = builder.rotl_imm(narrow, 3);
+        builder.store(ptr.clone(), rotated, 8).unwrap();
+        builder.call("host", &[ptr, float]).unwrap();
+        let address = builder.const_ptr(0x1000);
+        let wide = builder.load(Type::i64(), address, 0).unwrap();
+        builder.br(exit, &[wide]).unwrap();
+        builder.set_current_block(exit).unwrap();
+        builder.ret(builder.block_param(exit, 0).unwrap());
+
+        assert_eq!(compiler.print_ir(), "\
+global @data: size 8, align 2, readonly = [00 07]
+global @zeros: size 4, align 4, mutable
+extern @host(ptr, f32) -> void
+
+function @f(v0: ptr, v1: f32) -> i64 {
+block0:
+    v2: ptr = stack_slot 12, 4
+    v3: i32 = load_zext i8, v2, -4
+    v4: i32 = rotl_imm v3, 3
+    store v0, v4, 8
+    call @host(v0, v1)
+    v5: ptr = const 0x1000
+    v6: i64 = load v5, 0
+    br block1(v6)
+block1(v7: i64):
+    ret v7
+}
+
+function @g() -> i64 {
+block0:
+    v0: i64 = const 3
+    ret v0
+}
+");
+    }
+
+    #[test]
+    fn values_of_another_function_are_marked() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("g", &[Type::i64()], Type::i64()).unwrap().builder();
+        let foreign = builder.param(0).unwrap();
+        builder.ret(foreign.clone());
+
+        let builder = compiler.add_func("f", &[], Type::i64()).unwrap().builder();
+        let one = builder.const_i64(1);
+        let sum = builder.add(one, foreign);
+        builder.ret(sum);
+
+        let text = compiler.get_func_mut_by_name("f").unwrap().print_ir();
+        assert_eq!(text, "function @f() -> i64 {\nblock0:\n    v0: i64 = const 1\n    v1: i64 = add v0, ?v0\n    ret v1\n}\n");
+    }
+}
@@ -0,0 +1,27 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use crate::lang::lang_type::Type;
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct Signature {
+    args: Vec<Type>,
+    return_type: Type,
+}
+
+impl Signature {
+    pub fn new(args: &[Type], return_type: Type) -> Self {
+        Signature { args: args.to_vec(), return_type }
+    }
+
+    pub fn args(&self) -> &Vec<Type> {
+        &self.args
+    }
+
+    pub fn return_type(&self) -> &Type {
+        &self.return_type
+    }
+}
+
+// the signature of every function that can be called by name, shared by a compiler and the builders of its functions
+pub(crate) type Signatures = Rc<RefCell<HashMap<String, Signature>>>;
@@ -1,14 +1,16 @@
 use crate::lang::lang_type::Type;
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Value {
     id: usize,
     value_type: Type,
+    // the builder of the function the value belongs to, the ids are only unique inside of a function
+    func_id: usize,
 }
 
 impl Value {
-    pub fn new(id: usize, lang_type: Type) -> Self {
-        Value { id, value_type: lang_type }
+    pub(crate) fn new(func_id: usize, id: usize, lang_type: Type) -> Self {
+        Value { id, value_type: lang_type, func_id }
     }
 
     pub fn get_type(&self) -> Type {
@@ -18,5 +20,8 @@ impl Value {
     pub fn get_id(&self) -> usize {
         self.id
     }
-}
 
+    pub(crate) fn func_id(&self) -> usize {
+        self.func_id
+    }
+}
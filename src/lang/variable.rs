@@ -0,0 +1,15 @@
+// a mutable variable of the frontend, turned into ssa values by the builder
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Variable {
+    id: usize,
+    // the builder that declared the variable
+    func_id: usize,
+}
+
+impl Variable {
+    pub(crate) fn new(func_id: usize, id: usize) -> Self { Variable { id, func_id } }
+
+    pub(crate) fn get_id(&self) -> usize { self.id }
+
+    pub(crate) fn func_id(&self) -> usize { self.func_id }
+}
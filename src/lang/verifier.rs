@@ -0,0 +1,613 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use crate::lang::block::Block;
+use crate::lang::builder::{add_stack_slot, Builder};
+use crate::lang::function::Function;
+use crate::lang::instr::Instr;
+use crate::lang::lang_type::Type;
+use crate::lang::value::Value;
+
+// an invariant of the ir broken by a function, with the block and the instruction where it was found
+#[derive(Clone, PartialEq, Debug)]
+pub struct VerifierError {
+    func_name: String,
+    block: Option<usize>,
+    instr: Option<(usize, &'static str)>,
+    message: String,
+}
+
+impl VerifierError {
+    pub fn func_name(&self) -> &String {
+        &self.func_name
+    }
+
+    pub fn block(&self) -> Option<usize> {
+        self.block
+    }
+
+    // the index of the instruction in its block
+    pub fn instr_index(&self) -> Option<usize> {
+        self.instr.map(|(index, _)| index)
+    }
+
+    pub fn message(&self) -> &String {
+        &self.message
+    }
+}
+
+impl fmt::Display for VerifierError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "function {}", self.func_name)?;
+        if let Some(block) = self.block {
+            write!(f, ", block {}", block)?;
+        }
+        if let Some((index, name)) = self.instr {
+            write!(f, ", instruction {} ({})", index, name)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Definition {
+    Param,
+    BlockParam(usize),
+    // the block and the index of the instruction in it
+    Instr(usize, usize),
+}
+
+// check everything the code generation relies on: the blocks end with a single terminator, the operands have the types
+// the instructions expect and every value is defined in the function before it is used
+pub(crate) fn verify_function(func: &Function) -> Result<(), VerifierError> {
+    let verifier = Verifier { func, builder: func.builder_ref(), definitions: HashMap::new(), dominators: vec![] };
+    verifier.verify()
+}
+
+struct Verifier<'a> {
+    func: &'a Function,
+    builder: &'a Builder,
+    // where every value is defined, with its type
+    definitions: HashMap<usize, (Definition, Type)>,
+    // the blocks dominating every block, None for the blocks that cannot be reached
+    dominators: Vec<Option<HashSet<usize>>>,
+}
+
+impl Verifier<'_> {
+    fn verify(mut self) -> Result<(), VerifierError> {
+        self.check_structure()?;
+        self.collect_definitions()?;
+        self.dominators = self.compute_dominators();
+
+        let mut stack_slots_size = 0;
+        for (block_id, block) in self.builder.blocks().iter().enumerate() {
+            for (index, instr) in block.instructions().iter().enumerate() {
+                let at_instr = |message: String| self.error(Some(block_id), Some((index, instr.name())), message);
+
+                for value in instr.used_values() {
+                    self.check_use(value, block_id, index).map_err(at_instr)?;
+                }
+                self.check_types(instr).map_err(at_instr)?;
+
+                // the stack slots of the function are only checked together once each of them is valid
+                if let Instr::StackSlot { size, align, .. } = instr {
+                    stack_slots_size = add_stack_slot(stack_slots_size, *size, *align)
+                        .ok_or_else(|| at_instr(format!("a stack slot of {} bytes does not fit in the frame", size)))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn error(&self, block: Option<usize>, instr: Option<(usize, &'static str)>, message: String) -> VerifierError {
+        VerifierError { func_name: self.func.name().clone(), block, instr, message }
+    }
+
+    // every block ends with its only terminator, and only branches to blocks of the function
+    fn check_structure(&self) -> Result<(), VerifierError> {
+        let blocks = self.builder.blocks();
+
+        for (block_id, block) in blocks.iter().enumerate() {
+            let instructions = block.instructions();
+            match instructions.last() {
+                Some(last) if last.is_terminator() => {}
+                _ => return Err(self.error(Some(block_id), None, "the block does not end with a terminator".to_string())),
+            }
+
+            for (index, instr) in instructions.iter().enumerate() {
+                if index > 0 && instructions[index - 1].is_terminator() {
+                    return Err(self.error(Some(block_id), Some((index, instr.name())), "instruction after the terminator of the block".to_string()));
+                }
+                for target in instr.successors() {
+                    if target.get_id() >= blocks.len() {
+                        let message = format!("branch to the block {} which is not in the function", target.get_id());
+                        return Err(self.error(Some(block_id), Some((index, instr.name())), message));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn collect_definitions(&mut self) -> Result<(), VerifierError> {
+        let mut definitions = vec![];
+        for param in self.builder.params() {
+            definitions.push((param, Definition::Param, None));
+        }
+        for (block_id, block) in self.builder.blocks().iter().enumerate() {
+            for param in block.params() {
+                definitions.push((param, Definition::BlockParam(block_id), Some(block_id)));
+            }
+            for (index, instr) in block.instructions().iter().enumerate() {
+                if let Some(value) = instr.gen_value() {
+                    definitions.push((value, Definition::Instr(block_id, index), Some(block_id)));
+                }
+            }
+        }
+
+        for (value, definition, block) in definitions {
+            if self.definitions.insert(value.get_id(), (definition, value.get_type())).is_some() {
+                let instr = match definition {
+                    Definition::Instr(block_id, index) => Some((index, self.builder.blocks()[block_id].instructions()[index].name())),
+                    _ => None,
+                };
+                return Err(self.error(block, instr, format!("the value v{} is defined more than once", value.get_id())));
+            }
+        }
+
+        Ok(())
+    }
+
+    // the dominators of the reachable blocks, iterated until nothing changes
+    fn compute_dominators(&self) -> Vec<Option<HashSet<usize>>> {
+        let blocks = self.builder.blocks();
+        let successors: Vec<Vec<usize>> = blocks.iter().map(|block| {
+            block.instructions().iter().flat_map(|instr| instr.successors()).map(|target| target.get_id()).collect()
+        }).collect();
+
+        let mut reachable = HashSet::from([0]);
+        let mut stack = vec![0];
+        while let Some(block) = stack.pop() {
+            for successor in &successors[block] {
+                if reachable.insert(*successor) {
+                    stack.push(*successor);
+                }
+            }
+        }
+
+        let mut predecessors: Vec<Vec<usize>> = vec![vec![]; blocks.len()];
+        for (block, block_successors) in successors.iter().enumerate() {
+            if reachable.contains(&block) {
+                for successor in block_successors {
+                    predecessors[*successor].push(block);
+                }
+            }
+        }
+
+        let mut dominators: Vec<Option<HashSet<usize>>> = (0..blocks.len())
+            .map(|block| reachable.contains(&block).then(|| reachable.clone()))
+            .collect();
+        dominators[0] = Some(HashSet::from([0]));
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in 1..blocks.len() {
+                if dominators[block].is_none() {
+                    continue;
+                }
+
+                let mut new_dominators: Option<HashSet<usize>> = None;
+                for predecessor in &predecessors[block] {
+                    let predecessor_dominators = dominators[*predecessor].as_ref().unwrap();
+                    new_dominators = Some(match new_dominators {
+                        Some(current) => current.intersection(predecessor_dominators).copied().collect(),
+                        None => predecessor_dominators.clone(),
+                    });
+                }
+                let mut new_dominators = new_dominators.unwrap_or_default();
+                new_dominators.insert(block);
+
+                if dominators[block].as_ref() != Some(&new_dominators) {
+                    dominators[block] = Some(new_dominators);
+                    changed = true;
+                }
+            }
+        }
+
+        dominators
+    }
+
+    // the value has to be defined in this function and its definition has to dominate the use
+    fn check_use(&self, value: &Value, block: usize, index: usize) -> Result<(), String> {
+        if value.func_id() != self.builder.func_id() {
+            return Err(format!("the value v{} belongs to another function", value.get_id()));
+        }
+        let (definition, value_type) = match self.definitions.get(&value.get_id()) {
+            Some(definition) => definition,
+            None => return Err(format!("the value v{} is not defined in the function", value.get_id())),
+        };
+        if *value_type != value.get_type() {
+            return Err(format!("the value v{} is {} in the function, not {}", value.get_id(), value_type, value.get_type()));
+        }
+        if value.get_type() == Type::void() {
+            return Err(format!("the void value v{} is used", value.get_id()));
+        }
+
+        // the uses in a block that cannot be reached never run
+        let dominators = match &self.dominators[block] {
+            Some(dominators) => dominators,
+            None => return Ok(()),
+        };
+
+        let dominates = match *definition {
+            Definition::Param => true,
+            Definition::BlockParam(def_block) => dominators.contains(&def_block),
+            Definition::Instr(def_block, def_index) if def_block == block => def_index < index,
+            Definition::Instr(def_block, _) => dominators.contains(&def_block),
+        };
+        if !dominates {
+            return Err(format!("the definition of v{} does not dominate its use", value.get_id()));
+        }
+
+        Ok(())
+    }
+
+    fn check_types(&self, instr: &Instr) -> Result<(), String> {
+        let int_or_float = |value_type: &Type| value_type.is_int() || value_type.is_float();
+        let int_or_bool = |value_type: &Type| value_type.is_int() || value_type.is_bool();
+        let int_or_ptr = |value_type: &Type| value_type.is_int() || value_type.is_ptr();
+        let int = |value_type: &Type| value_type.is_int();
+        let float = |value_type: &Type| value_type.is_float();
+
+        match instr {
+            Instr::ConstInt64 { gen_value, .. } => expect_one_of(gen_value, &[Type::i64(), Type::f64()]),
+            Instr::ConstInt32 { gen_value, .. } => expect_one_of(gen_value, &[Type::i32(), Type::f32()]),
+            Instr::ConstInt16 { gen_value, .. } => expect_one_of(gen_value, &[Type::i16()]),
+            Instr::ConstInt8 { gen_value, .. } => expect_one_of(gen_value, &[Type::i8()]),
+            Instr::ConstPtr { gen_value, .. } => expect_one_of(gen_value, &[Type::ptr()]),
+
+            Instr::GlobalAddr { name, gen_value } => {
+                if !self.builder.globals().borrow().contains_key(name) {
+                    return Err(format!("the global {} is not defined", name));
+                }
+                expect_one_of(gen_value, &[Type::ptr()])
+            }
+
+            Instr::Add { left_value, right_value, gen_value } |
+            Instr::Sub { left_value, right_value, gen_value } |
+            Instr::Mul { left_value, right_value, gen_value } |
+            Instr::Div { left_value, right_value, gen_value } => check_binary(left_value, right_value, gen_value, int_or_float),
+
+            Instr::UDiv { left_value, right_value, gen_value } |
+            Instr::SRem { left_value, right_value, gen_value } |
+            Instr::URem { left_value, right_value, gen_value } => check_binary(left_value, right_value, gen_value, int),
+
+            Instr::And { left_value, right_value, gen_value } |
+            Instr::Or { left_value, right_value, gen_value } |
+            Instr::Xor { left_value, right_value, gen_value } => check_binary(left_value, right_value, gen_value, int_or_bool),
+
+            Instr::Neg { value, gen_value } => check_unary(value, gen_value, int_or_float),
+            Instr::Abs { value, gen_value } => check_unary(value, gen_value, float),
+            Instr::Not { value, gen_value } => check_unary(value, gen_value, int_or_bool),
+
+            Instr::Eq { left_value, right_value, gen_value } |
+            Instr::Diff { left_value, right_value, gen_value } => {
+                check_compare(left_value, right_value, gen_value, |value_type| value_type != &Type::void())
+            }
+
+            Instr::Larger { left_value, right_value, gen_value } |
+            Instr::LargerEq { left_value, right_value, gen_value } |
+            Instr::Smaller { left_value, right_value, gen_value } |
+            Instr::SmallerEq { left_value, right_value, gen_value } => check_compare(left_value, right_value, gen_value, int_or_float),
+
+            Instr::Ordered { left_value, right_value, gen_value } |
+            Instr::Unordered { left_value, right_value, gen_value } => check_compare(left_value, right_value, gen_value, float),
+
+            Instr::ULarger { left_value, right_value, gen_value } |
+            Instr::ULargerEq { left_value, right_value, gen_value } |
+            Instr::USmaller { left_value, right_value, gen_value } |
+            Instr::USmallerEq { left_value, right_value, gen_value } => check_compare(left_value, right_value, gen_value, int_or_ptr),
+
+            // the amount of a shift can be an integer of any size
+            Instr::LShr { left_value, right_value, gen_value } |
+            Instr::AShr { left_value, right_value, gen_value } |
+            Instr::Shl { left_value, right_value, gen_value } |
+            Instr::Rotl { left_value, right_value, gen_value } |
+            Instr::Rotr { left_value, right_value, gen_value } => {
+                if !right_value.get_type().is_int() {
+                    return Err(format!("cannot shift by {}", right_value.get_type()));
+                }
+                check_unary(left_value, gen_value, int)
+            }
+
+            Instr::ShlImm { value, amount, gen_value } |
+            Instr::LShrImm { value, amount, gen_value } |
+            Instr::AShrImm { value, amount, gen_value } |
+            Instr::RotlImm { value, amount, gen_value } |
+            Instr::RotrImm { value, amount, gen_value } => {
+                check_unary(value, gen_value, int)?;
+                if *amount as usize >= value.get_type().size() * 8 {
+                    return Err(format!("cannot shift {} by {} bits", value.get_type(), amount));
+                }
+                Ok(())
+            }
+
+            Instr::Cast { cast, value, gen_value } => {
+                if !cast.accepts(&value.get_type(), &gen_value.get_type()) {
+                    return Err(format!("cannot convert {} to {}", value.get_type(), gen_value.get_type()));
+                }
+                Ok(())
+            }
+
+            Instr::StackSlot { size, align, gen_value } => {
+                if !align.is_power_of_two() || *align > 16 {
+                    return Err(format!("a stack slot cannot be aligned on {} bytes", align));
+                }
+                if add_stack_slot(0, *size, *align).is_none() {
+                    return Err(format!("a stack slot of {} bytes does not fit in the frame", size));
+                }
+                expect_one_of(gen_value, &[Type::ptr()])
+            }
+
+            Instr::Alloca { size_value, gen_value } => {
+                if !size_value.get_type().is_int() {
+                    return Err(format!("the size of an alloca cannot be {}", size_value.get_type()));
+                }
+                expect_one_of(gen_value, &[Type::ptr()])
+            }
+
+            Instr::Load { value_to_load, mem_type, gen_value, .. } => {
+                expect_ptr(value_to_load)?;
+                let loaded_type = gen_value.get_type();
+                let extended = mem_type.is_int() && loaded_type.is_int() && loaded_type.size() > mem_type.size();
+                if *mem_type == Type::void() || (*mem_type != loaded_type && !extended) {
+                    return Err(format!("cannot load {} as {}", mem_type, loaded_type));
+                }
+                Ok(())
+            }
+
+            Instr::Store { value_ptr, .. } => expect_ptr(value_ptr),
+
+            Instr::Br { block_to_br, args } => self.check_branch(*block_to_br, args),
+
+            Instr::CondBr { value_cond, block_to_br_true, true_args, block_to_br_false, false_args } => {
+                if !value_cond.get_type().is_bool() {
+                    return Err(format!("the condition is {} instead of bool", value_cond.get_type()));
+                }
+                self.check_branch(*block_to_br_true, true_args)?;
+                self.check_branch(*block_to_br_false, false_args)
+            }
+
+            Instr::CallPtr { ptr_to_call, return_type, gen_value, .. } => {
+                expect_ptr(ptr_to_call)?;
+                expect_one_of(gen_value, std::slice::from_ref(return_type))
+            }
+
+            Instr::CallFunc { func_to_call, args, gen_value } => {
+                let signature = match self.builder.signatures().borrow().get(func_to_call) {
+                    Some(signature) => signature.clone(),
+                    None => return Err(format!("call to the undeclared function {}", func_to_call)),
+                };
+                let arg_types: Vec<Type> = args.iter().map(|arg| arg.get_type()).collect();
+                if arg_types != *signature.args() {
+                    return Err(format!("the arguments ({}) do not match the parameters of {} ({})", type_list(&arg_types), func_to_call, type_list(signature.args())));
+                }
+                expect_one_of(gen_value, std::slice::from_ref(signature.return_type()))
+            }
+
+            Instr::Ret { value_to_return } => {
+                if value_to_return.get_type() != *self.func.return_type() {
+                    return Err(format!("returns {} from a function returning {}", value_to_return.get_type(), self.func.return_type()));
+                }
+                Ok(())
+            }
+
+            Instr::RetVoid => {
+                if *self.func.return_type() != Type::void() {
+                    return Err(format!("returns nothing from a function returning {}", self.func.return_type()));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn check_branch(&self, block: Block, args: &[Value]) -> Result<(), String> {
+        let param_types: Vec<Type> = self.builder.blocks()[block.get_id()].params().iter().map(|param| param.get_type()).collect();
+        let arg_types: Vec<Type> = args.iter().map(|arg| arg.get_type()).collect();
+        if arg_types != param_types {
+            return Err(format!("the arguments ({}) do not match the parameters of block {} ({})", type_list(&arg_types), block.get_id(), type_list(&param_types)));
+        }
+        Ok(())
+    }
+}
+
+fn check_binary(left_value: &Value, right_value: &Value, gen_value: &Value, accepts: impl Fn(&Type) -> bool) -> Result<(), String> {
+    if left_value.get_type() != right_value.get_type() {
+        return Err(format!("the operands have different types {} and {}", left_value.get_type(), right_value.get_type()));
+    }
+    check_unary(left_value, gen_value, accepts)
+}
+
+fn check_unary(value: &Value, gen_value: &Value, accepts: impl Fn(&Type) -> bool) -> Result<(), String> {
+    if !accepts(&value.get_type()) {
+        return Err(format!("the operand cannot be {}", value.get_type()));
+    }
+    expect_one_of(gen_value, &[value.get_type()])
+}
+
+fn check_compare(left_value: &Value, right_value: &Value, gen_value: &Value, accepts: impl Fn(&Type) -> bool) -> Result<(), String> {
+    if left_value.get_type() != right_value.get_type() {
+        return Err(format!("the operands have different types {} and {}", left_value.get_type(), right_value.get_type()));
+    }
+    if !accepts(&left_value.get_type()) {
+        return Err(format!("cannot compare values of type {}", left_value.get_type()));
+    }
+    expect_one_of(gen_value, &[Type::bool()])
+}
+
+fn expect_ptr(value: &Value) -> Result<(), String> {
+    if !value.get_type().is_ptr() {
+        return Err(format!("the address is {} instead of ptr", value.get_type()));
+    }
+    Ok(())
+}
+
+// the type of the value defined by the instruction
+fn expect_one_of(gen_value: &Value, types: &[Type]) -> Result<(), String> {
+    if !types.contains(&gen_value.get_type()) {
+        return Err(format!("the result v{} cannot be {}", gen_value.get_id(), gen_value.get_type()));
+    }
+    Ok(())
+}
+
+fn type_list(types: &[Type]) -> String {
+    types.iter().map(|value_type| value_type.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::Compiler;
+    use crate::error::CowError;
+    use crate::lang::builder::MAX_STACK_SLOTS_SIZE;
+    use crate::lang::instr::Instr;
+    use crate::lang::lang_type::Type;
+
+    fn verifier_message(compiler: &mut Compiler) -> String {
+        match compiler.jit() {
+            Err(CowError::Verifier(error)) => error.message().clone(),
+            other => panic!("expected a verifier error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn accepts_a_valid_function() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[Type::i64()], Type::i64()).unwrap().builder();
+        let param = builder.param(0).unwrap();
+        let sum = builder.add(param.clone(), param);
+        builder.ret(sum);
+        assert_eq!(compiler.jit(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_a_value_of_another_function() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("x", &[], Type::i64()).unwrap().builder();
+        let foreign = builder.const_i64(1);
+        builder.ret(foreign.clone());
+
+        let builder = compiler.add_func("y", &[], Type::i64()).unwrap().builder();
+        // the value has the same id and type as the first value of y
+        let own = builder.const_i64(2);
+        let _ = builder.add(own.clone(), own);
+        builder.ret(foreign);
+
+        assert_eq!(verifier_message(&mut compiler), "the value v0 belongs to another function");
+    }
+
+    #[test]
+    fn rejects_a_block_without_terminator() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[], Type::void()).unwrap().builder();
+        builder.const_i64(1);
+        assert_eq!(verifier_message(&mut compiler), "the block does not end with a terminator");
+    }
+
+    #[test]
+    fn rejects_an_instruction_after_the_terminator() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[], Type::void()).unwrap().builder();
+        builder.ret_void();
+        builder.ret_void();
+        assert_eq!(verifier_message(&mut compiler), "instruction after the terminator of the block");
+    }
+
+    #[test]
+    fn rejects_a_return_of_the_wrong_type() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[], Type::i32()).unwrap().builder();
+        let value = builder.const_i64(1);
+        builder.ret(value);
+        assert_eq!(verifier_message(&mut compiler), "returns i64 from a function returning i32");
+    }
+
+    #[test]
+    fn rejects_operands_of_different_types() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[Type::i64(), Type::i32()], Type::i64()).unwrap().builder();
+        let sum = builder.add(builder.param(0).unwrap(), builder.param(1).unwrap());
+        builder.ret(sum);
+        assert!(matches!(compiler.jit(), Err(CowError::Verifier(_))));
+    }
+
+    #[test]
+    fn rejects_a_use_not_dominated_by_its_definition() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[Type::bool()], Type::i64()).unwrap().builder();
+        let (left, right, join) = (builder.create_block(), builder.create_block(), builder.create_block());
+        builder.cond_br(builder.param(0).unwrap(), left, &[], right, &[]).unwrap();
+
+        builder.set_current_block(left).unwrap();
+        let value = builder.const_i64(1);
+        builder.br(join, &[]).unwrap();
+
+        builder.set_current_block(right).unwrap();
+        builder.br(join, &[]).unwrap();
+
+        builder.set_current_block(join).unwrap();
+        builder.ret(value);
+
+        let error = match compiler.jit() {
+            Err(CowError::Verifier(error)) => error,
+            other => panic!("expected a verifier error, got {:?}", other),
+        };
+        assert_eq!(error.block(), Some(3));
+        assert_eq!(error.instr_index(), Some(0));
+        assert_eq!(error.message(), "the definition of v1 does not dominate its use");
+    }
+
+    #[test]
+    fn the_error_names_the_function_the_block_and_the_instruction() {
+        let mut compiler = Compiler::new().unwrap();
+        let builder = compiler.add_func("f", &[Type::i64()], Type::void()).unwrap().builder();
+        let (yes, no) = (builder.create_block(), builder.create_block());
+        builder.cond_br(builder.param(0).unwrap(), yes, &[], no, &[]).unwrap();
+        for block in [yes, no] {
+            builder.set_current_block(block).unwrap();
+            builder.ret_void();
+        }
+
+        let error = match compiler.jit() {
+            Err(CowError::Verifier(error)) => error,
+            other => panic!("expected a verifier error, got {:?}", other),
+        };
+        assert_eq!(error.to_string(), "function f, block 0, instruction 0 (cond_br): the condition is i64 instead of bool");
+    }
+
+    #[test]
+    fn rejects_stack_slots_that_do_not_fit_in_the_frame() {
+        // the builder refuses these sizes, the instructions are changed after it
+        let slots_with_sizes = |sizes: &[u32]| {
+            let mut compiler = Compiler::new().unwrap();
+            let builder = compiler.add_func("f", &[], Type::void()).unwrap().builder();
+            for _ in sizes {
+                builder.stack_slot(16, 16).unwrap();
+            }
+            builder.ret_void();
+            for (instr, new_size) in builder.blocks_mut()[0].instructions_mut().iter_mut().zip(sizes) {
+                if let Instr::StackSlot { size, .. } = instr {
+                    *size = *new_size;
+                }
+            }
+            compiler
+        };
+
+        assert_eq!(verifier_message(&mut slots_with_sizes(&[u32::MAX])), "a stack slot of 4294967295 bytes does not fit in the frame");
+        assert_eq!(verifier_message(&mut slots_with_sizes(&[0x7fff_fff0])), "a stack slot of 2147483632 bytes does not fit in the frame");
+        let half = MAX_STACK_SLOTS_SIZE / 2;
+        assert_eq!(verifier_message(&mut slots_with_sizes(&[half, half])), format!("a stack slot of {} bytes does not fit in the frame", half));
+        assert_eq!(slots_with_sizes(&[half, half / 2]).jit(), Ok(()));
+    }
+}
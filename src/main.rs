@@ -1,40 +1,41 @@
 extern crate core;
 
 use crate::compiler::Compiler;
+use crate::error::CowError;
 
 mod lang;
 mod gen;
 mod misc;
+mod jit;
 
 use lang::lang_type::Type;
 
 pub mod compiler;
+pub mod error;
 
-fn main() {
-    let mut compiler = Compiler::new();
-    let mut my_func = compiler.add_func("my_func", &vec![], Type::i32()).unwrap();
+fn main() -> Result<(), CowError> {
+    let mut compiler = Compiler::new()?;
+    let my_func = compiler.add_func("my_func", &[], Type::i32())?;
 
     {
         let builder = my_func.builder();
 
         let block = builder.create_block();
 
-        builder.br(block);
+        builder.br(block, &[])?;
 
-        builder.set_current_block(block);
-        let first = builder.const_i64(10);
+        builder.set_current_block(block)?;
+        let first = builder.const_i32(10);
         builder.ret(first);
 
-        compiler.jit();
+        compiler.jit()?;
     }
 
-    let func_ptr: *mut u8 = compiler.get_func_by_name("my_func").unwrap().jit_ptr();
-
-    // Transmute the pointer to a function type
-    let func: unsafe extern "C" fn() -> i64 = unsafe { std::mem::transmute(func_ptr) };
+    let func = compiler.get_typed::<fn() -> i32>("my_func")?;
 
     // Call the function
-    let result = unsafe { func() };
+    let result = func.call();
 
     println!("Function returned: {}", result);
-}
+    Ok(())
+}
\ No newline at end of file
@@ -7,7 +7,7 @@ impl ByteWriter {
         ByteWriter { data: vec![] }
     }
 
-    pub fn from_bytes(bytes: &Vec<u8>) -> Self { Self { data: bytes.clone() } }
+    pub fn from_bytes(bytes: &[u8]) -> Self { Self { data: bytes.to_vec() } }
 
     pub fn write_i8(&mut self, value: i8) -> usize {
         let result = self.data.len();
@@ -33,23 +33,6 @@ impl ByteWriter {
         result
     }
 
-    pub fn write_f32(&mut self, value: f32) -> usize {
-        let result = self.data.len();
-        self.data.extend_from_slice(&value.to_le_bytes());
-        result
-    }
-
-    pub fn write_f64(&mut self, value: f64) -> usize {
-        let result = self.data.len();
-        self.data.extend_from_slice(&value.to_le_bytes());
-        result
-    }
-
-    pub fn rewrite_i64(&mut self, index: usize, value: i64) {
-        let value_as_bytes = value.to_le_bytes();
-        self.data.splice(index..index + std::mem::size_of_val(&value), value_as_bytes.iter().cloned());
-    }
-
     pub fn rewrite_i32(&mut self, index: usize, value: i32) {
         let value_as_bytes = value.to_le_bytes();
         self.data.splice(index..index + std::mem::size_of_val(&value), value_as_bytes.iter().cloned());